@@ -0,0 +1,52 @@
+//! Fixtures shared by the benchmarks.
+
+#![allow( dead_code )]
+
+use std::convert::TryFrom;
+
+use trees::Tree;
+
+/// Node counts every benchmark is run against.
+pub const SIZES: [usize; 4] = [ 1_000, 10_000, 100_000, 1_000_000 ];
+
+/// Children per node in the generated trees, keeping the depth around log10(n).
+pub const FAN_OUT: usize = 10;
+
+/// Builds the string form of a tree holding exactly `n` nodes, named by their bfs index.
+pub fn tree_string( n: usize ) -> String {
+    let mut s = String::new();
+    push_node( &mut s, 0, n );
+    s
+}
+
+fn push_node( s: &mut String, index: usize, n: usize ) {
+    s.push_str( &index.to_string() );
+    let first = index * FAN_OUT + 1;
+    if first < n {
+        s.push_str( "( " );
+        for child in first..( first + FAN_OUT ).min( n ) {
+            push_node( s, child, n );
+            s.push( ' ' );
+        }
+        s.push( ')' );
+    }
+}
+
+/// A tree with one allocation per node, as the parser builds it.
+pub fn scattered( n: usize ) -> Tree<String> {
+    Tree::try_from( tree_string( n )).unwrap()
+}
+
+/// A tree with all nodes in one allocation, as `deep_clone()` builds it.
+pub fn piled( n: usize ) -> Tree<String> {
+    scattered( n ).root().deep_clone()
+}
+
+/// The value of the last node in preorder, the worst case for a linear locate.
+pub fn last_value( n: usize ) -> String {
+    let mut index = 0;
+    while index * FAN_OUT + 1 < n {
+        index = ( index * FAN_OUT + FAN_OUT ).min( n - 1 );
+    }
+    index.to_string()
+}
@@ -0,0 +1,73 @@
+//! Perf regression gate: times each operation once on a mid-sized tree and fails when one
+//! exceeds its budget in `benches/thresholds.txt`.
+//!
+//! `cargo bench --bench thresholds`, set `TREES_BENCH_NODES` to change the tree size.
+
+mod common;
+
+use std::convert::TryFrom;
+use std::time::{ Duration, Instant };
+use std::{ env, fs, process };
+
+use trees::Tree;
+
+use common::*;
+
+const DEFAULT_NODES: usize = 100_000;
+const ROUNDS: u32 = 5;
+
+// best of ROUNDS, so one noisy run on a shared CI box does not fail the gate
+fn time<R>( mut f: impl FnMut() -> R ) -> Duration {
+    ( 0..ROUNDS ).map( |_| {
+        let start = Instant::now();
+        let r = f();
+        let elapsed = start.elapsed();
+        drop( r );
+        elapsed
+    }).min().unwrap()
+}
+
+fn main() {
+    let n = env::var( "TREES_BENCH_NODES" ).ok()
+        .and_then( |v| v.parse().ok() )
+        .unwrap_or( DEFAULT_NODES );
+    let budgets = fs::read_to_string( concat!( env!( "CARGO_MANIFEST_DIR" ), "/benches/thresholds.txt" ))
+        .expect( "benches/thresholds.txt is readable" );
+
+    let s = tree_string( n );
+    let target = last_value( n );
+    let scattered = scattered( n );
+    let piled = piled( n );
+
+    let mut failed = 0;
+    println!( "{:<28} {:>12} {:>12}  {}", "operation", "elapsed(ms)", "budget(ms)", n );
+    for line in budgets.lines().map( str::trim ).filter( |l| !l.is_empty() && !l.starts_with( '#' )) {
+        let mut fields = line.split_whitespace();
+        let ( name, budget ) = match ( fields.next(), fields.next().and_then( |b| b.parse::<f64>().ok() )) {
+            ( Some( name ), Some( budget )) => ( name, budget ),
+            _ => panic!( "malformed threshold line: {}", line ),
+        };
+        let elapsed = match name {
+            "parse"                => time( || Tree::try_from( s.clone() ).unwrap() ),
+            "serialize/scattered"  => time( || scattered.to_string() ),
+            "serialize/piled"      => time( || piled.to_string() ),
+            "locate/scattered"     => time( || scattered.root().locate_first_by_data( &target ).is_some() ),
+            "locate/piled"         => time( || piled.root().locate_first_by_data( &target ).is_some() ),
+            "bfs/scattered"        => time( || scattered.root().bfs().iter.count() ),
+            "bfs/piled"            => time( || piled.root().bfs().iter.count() ),
+            "deep_clone/scattered" => time( || scattered.root().deep_clone() ),
+            "deep_clone/piled"     => time( || piled.root().deep_clone() ),
+            _ => panic!( "unknown operation in thresholds: {}", name ),
+        };
+        // budgets are written for DEFAULT_NODES, every operation here is linear
+        let budget = budget * n as f64 / DEFAULT_NODES as f64;
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let verdict = if ms > budget { failed += 1; "FAIL" } else { "ok" };
+        println!( "{:<28} {:>12.3} {:>12.3}  {}", name, ms, budget, verdict );
+    }
+
+    if failed > 0 {
+        eprintln!( "{} operation(s) over budget", failed );
+        process::exit( 1 );
+    }
+}
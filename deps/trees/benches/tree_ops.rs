@@ -0,0 +1,86 @@
+//! Criterion benchmarks of the operations the module leans on, on scattered and piled trees.
+//!
+//! `cargo bench --bench tree_ops`
+
+mod common;
+
+use std::convert::TryFrom;
+
+use criterion::{ criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion, Throughput };
+use criterion::measurement::WallTime;
+use trees::Tree;
+
+use common::*;
+
+// a million-node iteration takes a while, the default 100 samples would too
+fn configure<'c>( c: &'c mut Criterion, name: &str ) -> BenchmarkGroup<'c, WallTime> {
+    let mut group = c.benchmark_group( name );
+    group.sample_size( 10 );
+    group
+}
+
+fn parse( c: &mut Criterion ) {
+    let mut group = configure( c, "parse" );
+    for &n in SIZES.iter() {
+        let s = tree_string( n );
+        group.throughput( Throughput::Elements( n as u64 ));
+        group.bench_with_input( BenchmarkId::from_parameter( n ), &s, |b, s| {
+            b.iter_batched( || s.clone(), |s| Tree::try_from( s ).unwrap(), BatchSize::LargeInput )
+        });
+    }
+    group.finish();
+}
+
+fn serialize( c: &mut Criterion ) {
+    let mut group = configure( c, "serialize" );
+    for &n in SIZES.iter() {
+        group.throughput( Throughput::Elements( n as u64 ));
+        for &( shape, tree ) in [( "scattered", &scattered( n )), ( "piled", &piled( n ))].iter() {
+            group.bench_with_input( BenchmarkId::new( shape, n ), tree, |b, tree| b.iter( || tree.to_string() ));
+        }
+    }
+    group.finish();
+}
+
+fn locate( c: &mut Criterion ) {
+    let mut group = configure( c, "locate" );
+    for &n in SIZES.iter() {
+        let target = last_value( n );
+        group.throughput( Throughput::Elements( n as u64 ));
+        for &( shape, tree ) in [( "scattered", &scattered( n )), ( "piled", &piled( n ))].iter() {
+            group.bench_with_input( BenchmarkId::new( shape, n ), tree, |b, tree| {
+                b.iter( || tree.root().locate_first_by_data( &target ).is_some() )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bfs( c: &mut Criterion ) {
+    let mut group = configure( c, "bfs" );
+    for &n in SIZES.iter() {
+        group.throughput( Throughput::Elements( n as u64 ));
+        for &( shape, tree ) in [( "scattered", &scattered( n )), ( "piled", &piled( n ))].iter() {
+            group.bench_with_input( BenchmarkId::new( shape, n ), tree, |b, tree| {
+                b.iter( || tree.root().bfs().iter.count() )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn deep_clone( c: &mut Criterion ) {
+    let mut group = configure( c, "deep_clone" );
+    for &n in SIZES.iter() {
+        group.throughput( Throughput::Elements( n as u64 ));
+        for &( shape, tree ) in [( "scattered", &scattered( n )), ( "piled", &piled( n ))].iter() {
+            group.bench_with_input( BenchmarkId::new( shape, n ), tree, |b, tree| {
+                b.iter_with_large_drop( || tree.root().deep_clone() )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!( benches, parse, serialize, locate, bfs, deep_clone );
+criterion_main!( benches );
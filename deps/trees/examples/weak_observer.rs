@@ -0,0 +1,60 @@
+//! An index over a `RcNode` tree which observes nodes through `WeakNode`s, so detaching
+//! a subtree frees it instead of the index keeping it alive.
+//!
+//! `cargo run --example weak_observer`
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use trees::{RcNode, Tree, WeakNode};
+
+struct Index {
+    nodes: HashMap<String, WeakNode<String>>,
+}
+
+impl Index {
+    fn build( root: &RcNode<String> ) -> Self {
+        let mut nodes = HashMap::new();
+        let mut stack = vec![ root.clone() ];
+        while let Some( node ) = stack.pop() {
+            for child in node.children_weak() {
+                stack.extend( child.upgrade() );
+            }
+            nodes.insert( node.data().clone(), node.downgrade() );
+        }
+        Index{ nodes }
+    }
+
+    // the upgraded node stays alive for as long as the caller holds it
+    fn get( &self, data: &str ) -> Option<RcNode<String>> {
+        self.nodes.get( data ).and_then( WeakNode::upgrade )
+    }
+
+    fn parent_of( &self, data: &str ) -> Option<String> {
+        let parent = self.get( data )?.parent_weak()?.upgrade()?;
+        let data = parent.data().clone();
+        Some( data )
+    }
+
+    // drops the entries whose nodes are gone
+    fn purge( &mut self ) -> usize {
+        let before = self.nodes.len();
+        self.nodes.retain( |_, node| node.upgrade().is_some() );
+        before - self.nodes.len()
+    }
+}
+
+fn main() {
+    let tree = Tree::try_from( "USA( Legislature( House Senate ) WhiteHouse( Biden ) )" ).unwrap();
+    let root = RcNode::from( tree );
+    let mut index = Index::build( &root );
+
+    println!( "father of Senate: {:?}", index.parent_of( "Senate" ));
+    println!( "WhiteHouse: {:?}", index.get( "WhiteHouse" ).map( |node| node.to_string() ));
+
+    // detaching and dropping the subtree frees it, the index does not hold it back
+    drop( root.pop_back() );
+    println!( "WhiteHouse after detach: {:?}", index.get( "WhiteHouse" ).map( |node| node.to_string() ));
+    println!( "purged {} stale entries", index.purge() );
+    println!( "tree: {}", root );
+}
@@ -5,6 +5,7 @@ use super::{Data, Node, NodeVec, Size};
 use crate::rust::*;
 
 pub(crate) fn make_node<T>( data: Data<T> ) -> NonNull<Node<T>> {
+    #[cfg( feature = "stats" )] crate::stats::count_allocations( 1 );
     let rc = Rc::new( RefCell::new( Node{
         prev : None,
         next : None,
@@ -35,10 +36,12 @@ pub(crate) fn drop_node<T>( mut node: NonNull<Node<T>> ) {
             Data::None => (),
             Data::ScatteredNone{ owner     } |
             Data::Scattered    { owner, .. } => {
+                #[cfg( feature = "stats" )] crate::stats::count_drop();
                 drop( Rc::from_raw( owner.as_ptr() ));
             },
             Data::PiledNone    { owner     } |
             Data::Piled        { owner, .. } => {
+                #[cfg( feature = "stats" )] crate::stats::count_drop();
                 NodeVec::decr_ref( owner );
             },
         }
@@ -28,6 +28,7 @@ impl<T> Iterator for UncountedRawIter<T> {
 
     fn next( &mut self ) -> Option<Self::Item> {
         self.curr.map( |curr| unsafe {
+            #[cfg( feature = "stats" )] crate::stats::count_traversal_step();
             let item = curr;
             self.curr = curr.as_ref().next;
             item
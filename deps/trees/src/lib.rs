@@ -78,6 +78,8 @@
 //! 7. Support exclusive ownership with static borrow check.
 //!
 //! 8. Support shared ownership with dynamic borrow check.
+//!
+//! 9. Optional counters of allocations, drops and traversal steps with the `stats` feature, see `stats::snapshot()`.
 
 #![cfg_attr( feature = "no_std", no_std )]
 
@@ -159,7 +161,7 @@ pub use into_iter::IntoIter;
 pub mod heap;
 
 pub mod walk;
-pub use walk::{TreeWalk, ForestWalk};
+pub use walk::{TreeWalk, ForestWalk, NodeWalk};
 
 pub mod notation;
 pub use notation::{tr, fr};
@@ -172,28 +174,50 @@ pub use rc::{RcNode, WeakNode};
 
 pub(crate) mod bfs_impls;
 
+#[cfg( all( feature = "stats", feature = "no_std" ))]
+compile_error!( "the stats feature counts in thread locals, which needs std" );
+
+#[cfg( feature = "stats" )]
+pub mod stats;
+
 
 #[derive(Debug)]
 pub struct Error {
     pub msg: String,
+    /// Where a tree string stopped parsing, `None` for errors not tied to one spot.
+    pub position: Option<ErrorPosition>,
+}
+
+/// The offending token of a malformed tree string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorPosition {
+    /// Byte offset of the token in the tree string.
+    pub offset: usize,
+    /// The token as written.
+    pub token: String,
+    /// The token with up to ten characters on either side, "..." marking the cuts.
+    pub excerpt: String,
 }
 
 impl From<String> for Error {
     fn from(e: String) -> Self {
-        Error { msg: e }
+        Error { msg: e, position: None }
     }
 }
 
 impl From<&str> for Error {
     fn from(e: &str) -> Self {
-        Error { msg: e.to_string() }
+        Error { msg: e.to_string(), position: None }
     }
 }
 
 use std::fmt;
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.msg)
+        match &self.position {
+            Some(position) => write!(f, "{}: {} at byte {} near \"{}\"", self.msg, position.token, position.offset, position.excerpt),
+            None => write!(f, "{}", self.msg),
+        }
     }
 }
 
@@ -207,6 +231,9 @@ impl error::Error for Error {
 
 
 use std::pin::Pin;
+use crate::rust::{Display, Hash, Hasher, NonNull, Ordering, VecDeque};
+use std::fmt::Write;
+use crate::bfs::{BfsTree, Splitted, Visit};
 impl<T> Node<T> {
     pub fn locate_first_by_path<'s, 't>(&'s self, mut path: impl Iterator<Item=&'t T> + Clone ) -> Option<&'s Node<T>>
         where T: 't + PartialEq
@@ -229,15 +256,48 @@ impl<T> Node<T> {
         None
     }
 
+    // the node `n` steps into a preorder walk from self, found by skipping whole subtrees
+    // on their node counts, so it takes O(depth * degree) rather than O(n)
+    pub fn nth_in_preorder(&self, mut n: usize) -> Option<&Node<T>> {
+        if n >= self.node_count() {
+            return None;
+        }
+        let mut node = self;
+        while n > 0 {
+            n -= 1;
+            for child in node.iter() {
+                if n < child.node_count() {
+                    node = child;
+                    break;
+                }
+                n -= child.node_count();
+            }
+        }
+        Some(node)
+    }
+
     pub fn locate_first_by_data<'s, 't>(&'s self, data: &'t T) -> Option<&'s Node<T>>
         where T: 't + PartialEq
     {
-        if self.data() == data {
+        self.locate_first_by(&|v: &T| v == data)
+    }
+
+    pub fn locate_first_mut_by_data<'s, 't>(&'s mut self, data: &'t T) ->  Option<Pin<&'s mut Node<T>>>
+        where T: 't + PartialEq
+    {
+        self.locate_first_mut_by(&|v: &T| v == data)
+    }
+
+    // locate the first node in preorder whose data satisfies `matches`
+    pub fn locate_first_by<'s, F>(&'s self, matches: &F) -> Option<&'s Node<T>>
+        where F: Fn(&T) -> bool
+    {
+        if matches(self.data()) {
            return Some(self)
         }
 
         for child in self.iter() {
-            if let Some(node) = child.locate_first_by_data(data) {
+            if let Some(node) = child.locate_first_by(matches) {
                 return Some(node);
             }
         }
@@ -245,22 +305,82 @@ impl<T> Node<T> {
         None
     }
 
-    pub fn locate_first_mut_by_data<'s, 't>(&'s mut self, data: &'t T) ->  Option<Pin<&'s mut Node<T>>>
+    // lazily locate every node in preorder whose data equals `data`
+    pub fn locate_all_by_data<'s, 't>(&'s self, data: &'t T) -> LocateAll<'s, T, impl Fn(&T) -> bool + 't>
         where T: 't + PartialEq
     {
-        if self.data() == data {
+        self.locate_all_by(move |v: &T| v == data)
+    }
+
+    pub fn locate_all_by<F>(&self, matches: F) -> LocateAll<'_, T, F>
+        where F: Fn(&T) -> bool
+    {
+        LocateAll { stack: vec![self], matches }
+    }
+
+    pub fn locate_first_mut_by<'s, F>(&'s mut self, matches: &F) ->  Option<Pin<&'s mut Node<T>>>
+        where F: Fn(&T) -> bool
+    {
+        if matches(self.data()) {
             return Some( unsafe { Pin::new_unchecked(self)});
         }
 
         for child in self.iter_mut() {
             let child = unsafe{ Pin::get_unchecked_mut(child) };
-            if let Some(node) = child.locate_first_mut_by_data(data) {
+            if let Some(node) = child.locate_first_mut_by(matches) {
                 return Some(node);
             }
         }
         None
     }
 
+    // applies `update` to the data of every node in preorder whose data satisfies `matches`,
+    // returns how many nodes were updated
+    pub fn update_all_by<F, U>(&mut self, matches: F, mut update: U) -> usize
+        where F: Fn(&T) -> bool, U: FnMut(&mut T)
+    {
+        let mut updated = 0;
+        let mut stack = vec![NonNull::from(self)];
+        while let Some(mut node) = stack.pop() {
+            let node = unsafe { node.as_mut() };
+            if matches(node.data()) {
+                update(node.data_mut());
+                updated += 1;
+            }
+            let children = node.iter_mut().map(|child| {
+                NonNull::from(unsafe { Pin::get_unchecked_mut(child) })
+            }).collect::<Vec<_>>();
+            stack.extend(children.into_iter().rev());
+        }
+        updated
+    }
+
+    // reorders the children of self by `compare`, stable so equal ones keep their order,
+    // with `recursive` the children of every node below as well. True when any child moved
+    pub fn sort_children_by<F>(&mut self, recursive: bool, mut compare: F) -> bool
+        where F: FnMut(&T, &T) -> Ordering
+    {
+        let mut moved = false;
+        let mut stack = vec![NonNull::from(self)];
+        while let Some(mut node) = stack.pop() {
+            let node = unsafe { node.as_mut() };
+            let children = node.iter().collect::<Vec<_>>();
+            // children already in order stay where they are
+            if children.windows(2).any(|pair| compare(pair[0].data(), pair[1].data()) == Ordering::Greater) {
+                moved = true;
+                let mut children = std::iter::from_fn(|| node.pop_front()).collect::<Vec<_>>();
+                children.sort_by(|a, b| compare(a.root().data(), b.root().data()));
+                children.into_iter().for_each(|child| node.push_back(child));
+            }
+            if recursive {
+                stack.extend(node.iter_mut().map(|child| {
+                    NonNull::from(unsafe { Pin::get_unchecked_mut(child) })
+                }));
+            }
+        }
+        moved
+    }
+
     pub fn locate_first_mut_by_path<'s, 't>(&'s mut self, mut path: impl Iterator<Item=&'t T> + Clone ) -> Option<Pin<&'s mut Node<T>>>
         where T: 't + PartialEq
     {
@@ -286,16 +406,55 @@ impl<T> Node<T> {
 
 
     pub fn ancestors(&self) -> Vec<&T> {
+        self.ancestor_nodes().into_iter().map(|v| v.data()).collect::<Vec<_>>()
+    }
+
+    // parent first, root last
+    pub fn ancestor_nodes(&self) -> Vec<&Node<T>> {
         let mut ancestors = vec![];
 
         let mut current_node = self;
         while let Some(node) = current_node.parent(){
-            ancestors.push(node.data());
+            ancestors.push(node);
             current_node = node;
         }
         ancestors
     }
 
+    // a node counts as its own ancestor here, so lca(a, descendant of a) is a
+    pub fn lowest_common_ancestor<'s>(&'s self, other: &'s Node<T>) -> Option<&'s Node<T>> {
+        let mut ancestor_set = std::collections::HashSet::new();
+        ancestor_set.insert(self as *const Node<T>);
+        for node in self.ancestor_nodes() {
+            ancestor_set.insert(node as *const Node<T>);
+        }
+
+        if ancestor_set.contains(&(other as *const Node<T>)) {
+            return Some(other);
+        }
+        other.ancestor_nodes().into_iter().find(|node| ancestor_set.contains(&(*node as *const Node<T>)))
+    }
+
+    // data along the unique path from self up to the lca and down to other, both ends included
+    pub fn path_to<'s>(&'s self, other: &'s Node<T>) -> Option<Vec<&'s T>> {
+        let lca = self.lowest_common_ancestor(other)?;
+        let chain = |from: &'s Node<T>| {
+            let mut nodes = vec![from];
+            let mut current_node = from;
+            while !core::ptr::eq(current_node, lca) {
+                current_node = current_node.parent().unwrap();
+                nodes.push(current_node);
+            }
+            nodes
+        };
+
+        let mut path = chain(self);
+        let mut down = chain(other);
+        down.pop();
+        path.extend(down.into_iter().rev());
+        Some(path.into_iter().map(|v| v.data()).collect::<Vec<_>>())
+    }
+
 
     pub fn descendants(&self) -> Vec<&T> {
         self.bfs().iter.map(|v| {
@@ -303,6 +462,199 @@ impl<T> Node<T> {
         }).collect::<Vec<_>>()
     }
 
+    // bfs descendants at most `depth` levels below self
+    pub fn descendants_to_depth(&self, depth: usize) -> Vec<&T> {
+        self.bfs_levels()
+            .take_while(|(level, _)| *level <= depth)
+            .map(|(_, visit)| visit.data)
+            .collect()
+    }
+
+    // copy of self and the nodes at most `depth` levels below, a copied node whose children
+    // were left out gets a single `marker` child in their place, if there is one
+    pub fn truncated(&self, depth: usize, marker: Option<&T>) -> Tree<T>
+        where T: Clone
+    {
+        let cut = |node: &Node<T>| {
+            let mut tree = Tree::new(node.data().clone());
+            if let (Some(marker), false) = (marker, node.has_no_child()) {
+                tree.push_back(Tree::new(marker.clone()));
+            }
+            tree
+        };
+        if depth == 0 {
+            return cut(self)
+        }
+
+        // copies in the making from self down to the node being copied, each with the children left to copy
+        let mut stack = vec![(Tree::new(self.data().clone()), self.iter())];
+        loop {
+            let next = stack.last_mut().unwrap().1.next();
+            match next {
+                Some(child) if stack.len() < depth => stack.push((Tree::new(child.data().clone()), child.iter())),
+                Some(child) => stack.last_mut().unwrap().0.push_back(cut(child)),
+                None => {
+                    let (tree, _) = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some((parent, _)) => parent.push_back(tree),
+                        None => return tree,
+                    }
+                },
+            }
+        }
+    }
+
+    // copy of the nodes `keep` holds for together with their ancestors up to self, so they stay
+    // connected, in the same order; None when it holds for none
+    pub fn filtered<F>(&self, mut keep: F) -> Option<Tree<T>>
+        where T: Clone, F: FnMut(&T) -> bool
+    {
+        // nodes from self down to the one being looked at, each with its children left to look at
+        // and the copies of those that were kept
+        let mut stack = vec![(self, self.iter(), Vec::new())];
+        loop {
+            let next = stack.last_mut().unwrap().1.next();
+            match next {
+                Some(child) => stack.push((child, child.iter(), Vec::new())),
+                None => {
+                    let (node, _, kept) = stack.pop().unwrap();
+                    let copy = if !kept.is_empty() || keep(node.data()) {
+                        let mut tree = Tree::new(node.data().clone());
+                        kept.into_iter().for_each(|child| tree.push_back(child));
+                        Some(tree)
+                    } else {
+                        None
+                    };
+                    match stack.last_mut() {
+                        Some((_, _, siblings)) => siblings.extend(copy),
+                        None => return copy,
+                    }
+                },
+            }
+        }
+    }
+
+    // how many nodes each level holds, self's level first
+    pub fn level_widths(&self) -> Vec<usize> {
+        let mut widths = Vec::new();
+        for (level, _) in self.bfs_levels() {
+            if level == widths.len() {
+                widths.push(0);
+            }
+            widths[level] += 1;
+        }
+        widths
+    }
+
+    // bfs visits of self and the nodes below, each with how many levels below self it is
+    pub fn bfs_levels(&self) -> BfsLevels<Splitted<Iter<'_, T>>> {
+        BfsLevels { iter: self.bfs().iter, level: 0, level_remaining: 1, next_level_width: 0 }
+    }
+
+
+    // FNV-1a over (data, degree) in preorder, which pins down both values and shape,
+    // stable across runs unlike `Hash` with the std hasher
+    pub fn subtree_hash(&self) -> u64
+        where T: Hash
+    {
+        let mut hasher = Fnv1a::default();
+        for node in self.locate_all_by(|_| true) {
+            node.data().hash(&mut hasher);
+            hasher.write(&(node.degree() as u64).to_le_bytes());
+        }
+        hasher.finish()
+    }
+
+    // same structure with every value replaced by its index among its siblings, self being 0
+    pub fn shape(&self) -> Tree<usize> {
+        let (iter, size) = self.bfs().wrap().iter_and_size();
+
+        // bfs visits the children of each parent in a row, parents in the order they were visited
+        let mut groups = VecDeque::new();
+        let mut remaining = 0;
+        let mut index = 0;
+        let mut first = true;
+        let iter = iter.map(move |visit| {
+            let data = if first {
+                first = false;
+                0
+            } else {
+                while remaining == 0 {
+                    remaining = groups.pop_front().unwrap();
+                    index = 0;
+                }
+                remaining -= 1;
+                index += 1;
+                index - 1
+            };
+            if visit.size.degree > 0 {
+                groups.push_back(visit.size.degree);
+            }
+            Visit { data, size: visit.size }
+        });
+        Tree::from(BfsTree { iter, size })
+    }
+
+    // FNV-1a over the degrees in preorder, equal for trees of the same shape whatever their values
+    pub fn shape_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        for node in self.locate_all_by(|_| true) {
+            hasher.write(&(node.degree() as u64).to_le_bytes());
+        }
+        hasher.finish()
+    }
+
+    // edges between the root and self
+    pub fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut current_node = self;
+        while let Some(parent) = current_node.parent() {
+            depth += 1;
+            current_node = parent;
+        }
+        depth
+    }
+
+    // edges on the longest downward path, the level bfs visits last
+    pub fn height(&self) -> usize {
+        self.bfs_levels().last().map_or(0, |(level, _)| level)
+    }
+
+    // 0-based position among the siblings counted along the prev pointers, None for a tree root
+    pub fn child_index(&self) -> Option<usize> {
+        self.up?;
+        let mut index = 0;
+        let mut prev = self.prev;
+        while let Some(node) = prev {
+            index += 1;
+            prev = unsafe { node.as_ref() }.prev;
+        }
+        Some(index)
+    }
+
+    // leaves under self, self being its own only leaf when it has no child
+    pub fn leaves(&self) -> Leaves<'_, T> {
+        Leaves { start: Some(self), stack: Vec::new() }
+    }
+
+    // (root-to-node path, node) for self and every node below in preorder, the path built up
+    // in one buffer that is cut back to the parent's path before each child is appended
+    pub fn iter_paths<'s>(&'s self, separator: &str) -> IterPaths<'s, T>
+        where T: Display
+    {
+        IterPaths { start: Some(self), stack: Vec::new(), path: String::new(), separator: separator.to_string() }
+    }
+
+    // siblings walked from the first child of the parent, self skipped unless `with_self`
+    pub fn siblings(&self, with_self: bool) -> Siblings<'_, T> {
+        let iter = match self.up {
+            Some(up) => unsafe { &*up.as_ptr() }.iter(),
+            None => Iter::new(None, 0),
+        };
+        let skip = if with_self { None } else { Some(self as *const Node<T>) };
+        Siblings { iter, skip }
+    }
+
 
     pub fn children(&self) -> Vec<&T> {
         self.iter().map(|v| v.data()).collect::<Vec<_>>()
@@ -312,18 +664,362 @@ impl<T> Node<T> {
         self.parent().map(|v| v.data())
     }
 
+    // data from root down to self, self included
+    pub fn path(&self) -> Vec<&T> {
+        let mut path = self.ancestors();
+        path.reverse();
+        path.push(self.data());
+        path
+    }
+
+    // nested {"value":..,"children":[..]} objects, one per node in depth first order,
+    // with an "attributes" object and a "weight" number in between for data that has them.
+    // A value that is not UTF-8 goes as "value_hex", its bytes in hex
+    pub fn to_json(&self) -> String
+        where T: JsonData
+    {
+        let mut json = String::new();
+        for visit in NodeWalk::from(self) {
+            if let walk::Visit::Begin(node) | walk::Visit::Leaf(node) = visit {
+                // a sibling closed right before this node
+                if json.ends_with('}') {
+                    json.push(',');
+                }
+                match std::str::from_utf8(node.data().json_value()) {
+                    Ok(value) => {
+                        json.push_str("{\"value\":");
+                        push_json_string(&mut json, value);
+                    },
+                    Err(_) => {
+                        json.push_str("{\"value_hex\":\"");
+                        node.data().json_value().iter().for_each(|b| write!(json, "{:02x}", b).unwrap());
+                        json.push('"');
+                    },
+                }
+                let attributes = node.data().json_attributes();
+                if !attributes.is_empty() {
+                    json.push_str(",\"attributes\":{");
+                    for (i, (field, value)) in attributes.into_iter().enumerate() {
+                        if i > 0 {
+                            json.push(',');
+                        }
+                        push_json_string(&mut json, field);
+                        json.push(':');
+                        push_json_string(&mut json, value);
+                    }
+                    json.push('}');
+                }
+                if let Some(weight) = node.data().json_weight() {
+                    json.push_str(&format!(",\"weight\":{}", weight));
+                }
+                json.push_str(",\"children\":[");
+            }
+            if let walk::Visit::End(_) | walk::Visit::Leaf(_) = visit {
+                json.push_str("]}");
+            }
+        }
+        json
+    }
+
+    // GraphViz digraph with the nodes numbered in preorder, labels carry the data as values may repeat
+    pub fn to_dot(&self) -> String
+        where T: Display
+    {
+        let mut dot = String::from("digraph tree {\n");
+        let mut count = 0;
+        // numbers of the nodes from self down to the visited one
+        let mut open: Vec<usize> = Vec::new();
+        for visit in NodeWalk::from(self) {
+            if let walk::Visit::Begin(node) | walk::Visit::Leaf(node) = visit {
+                dot.push_str(&format!("  n{} [label=", count));
+                push_json_string(&mut dot, &node.data().to_string());
+                dot.push_str("];\n");
+                if let Some(parent) = open.last() {
+                    dot.push_str(&format!("  n{} -> n{};\n", parent, count));
+                }
+                if let walk::Visit::Begin(_) = visit {
+                    open.push(count);
+                }
+                count += 1;
+            }
+            if let walk::Visit::End(_) = visit {
+                open.pop();
+            }
+        }
+        dot.push('}');
+        dot
+    }
+
+}
+
+
+
+// a deep clone in progress, see `Tree::deep_clone_chunked`
+#[derive(Debug)]
+pub struct CloneState<T> {
+    clone: Tree<T>,
+    // child indices from the source root down to the next node to copy
+    next: Vec<usize>,
+}
+
+pub enum ChunkedClone<T> {
+    Pending(CloneState<T>),
+    Done(Tree<T>),
+}
+
+impl<T: Clone> Tree<T> {
+    // copies at most `budget_nodes` nodes, at least one, in preorder and picks up where `resume` left off,
+    // so a huge tree can be cloned a slice at a time. The next node is found again by its child indices
+    // on every call, a tree modified in between ends up as a clone mixing both versions
+    pub fn deep_clone_chunked(&self, budget_nodes: usize, resume: Option<CloneState<T>>) -> ChunkedClone<T> {
+        let mut budget = budget_nodes.max(1);
+        let mut state = match resume {
+            Some(state) => state,
+            None => {
+                budget -= 1;
+                CloneState { clone: Tree::new(self.root().data().clone()), next: vec![0] }
+            },
+        };
+
+        // the children of `src_parent` are copied under `dst`, `src` is the next of them
+        let mut src_parent = self.root();
+        let mut dst = NonNull::from(state.clone.root_mut_());
+        let (&last, ancestors) = state.next.split_last().unwrap();
+        for &index in ancestors {
+            match src_parent.iter().nth(index) {
+                Some(node) => src_parent = node,
+                None => return ChunkedClone::Done(state.clone),
+            }
+            let child = unsafe { dst.as_mut() }.iter_mut().nth(index).unwrap();
+            dst = NonNull::from(unsafe { Pin::get_unchecked_mut(child) });
+        }
+        let mut src = src_parent.iter().nth(last);
+
+        loop {
+            match src {
+                Some(node) => {
+                    if budget == 0 {
+                        return ChunkedClone::Pending(state)
+                    }
+                    budget -= 1;
+                    let dst_node = unsafe { dst.as_mut() };
+                    dst_node.push_back(Tree::new(node.data().clone()));
+                    if node.has_no_child() {
+                        *state.next.last_mut().unwrap() += 1;
+                        src = node.next.map(|next| unsafe { &*next.as_ptr() });
+                    } else {
+                        state.next.push(0);
+                        src_parent = node;
+                        dst = dst_node.tail.unwrap();
+                        src = node.front();
+                    }
+                },
+                None => {
+                    // every child of `src_parent` is copied, go on with its next sibling
+                    state.next.pop();
+                    if state.next.is_empty() {
+                        return ChunkedClone::Done(state.clone)
+                    }
+                    *state.next.last_mut().unwrap() += 1;
+                    src = src_parent.next.map(|next| unsafe { &*next.as_ptr() });
+                    src_parent = src_parent.parent().unwrap();
+                    dst = unsafe { dst.as_ref() }.up.unwrap();
+                },
+            }
+        }
+    }
 }
 
 
+// the other children of self's parent in order, see `Node::siblings`
+pub struct Siblings<'a, T> {
+    iter: Iter<'a, T>,
+    skip: Option<*const Node<T>>,
+}
+
+impl<'a, T: 'a> Iterator for Siblings<'a, T> {
+    type Item = &'a Node<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.iter.next()?;
+        if self.skip == Some(node as *const Node<T>) {
+            return self.iter.next()
+        }
+        Some(node)
+    }
+}
+
+
+// quoted and escaped as a JSON string, which DOT reads the same way, see `Node::to_json`.
+// Public for the JSON users build around trees
+pub fn push_json_string(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+
+// 64-bit FNV-1a, see `Node::subtree_hash`
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+
+// lazy left to right iterator over the leaves, see `Node::leaves`
+pub struct Leaves<'a, T> {
+    start: Option<&'a Node<T>>,
+    stack: Vec<Iter<'a, T>>,
+}
+
+impl<'a, T: 'a> Iterator for Leaves<'a, T> {
+    type Item = &'a Node<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            if start.has_no_child() {
+                return Some(start)
+            }
+            self.stack.push(start.iter());
+        }
+
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(node) if node.has_no_child() => return Some(node),
+                Some(node) => self.stack.push(node.iter()),
+                None => { self.stack.pop(); },
+            }
+        }
+    }
+}
+
+
+// bfs visits along with their level, see `Node::bfs_levels`. A level ends once its nodes
+// were visited, the next one is as wide as their degrees summed up
+pub struct BfsLevels<I> {
+    iter: I,
+    level: usize,
+    level_remaining: usize,
+    next_level_width: usize,
+}
+
+impl<D, I: Iterator<Item = Visit<D>>> Iterator for BfsLevels<I> {
+    type Item = (usize, Visit<D>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let visit = self.iter.next()?;
+        if self.level_remaining == 0 {
+            self.level += 1;
+            self.level_remaining = self.next_level_width;
+            self.next_level_width = 0;
+        }
+        self.level_remaining -= 1;
+        self.next_level_width += visit.size.degree;
+        Some((self.level, visit))
+    }
+}
+
+
+// preorder iterator over the nodes along with their paths, see `Node::iter_paths`
+pub struct IterPaths<'a, T> {
+    start: Option<&'a Node<T>>,
+    // children still to visit, each with the length of their parent's path
+    stack: Vec<(Iter<'a, T>, usize)>,
+    path: String,
+    separator: String,
+}
+
+impl<'a, T: 'a + Display> Iterator for IterPaths<'a, T> {
+    type Item = (String, &'a Node<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            for (i, data) in start.path().into_iter().enumerate() {
+                if i > 0 {
+                    self.path.push_str(&self.separator);
+                }
+                write!(self.path, "{}", data).unwrap();
+            }
+            self.stack.push((start.iter(), self.path.len()));
+            return Some((self.path.clone(), start))
+        }
+
+        loop {
+            let (children, parent_len) = self.stack.last_mut()?;
+            match children.next() {
+                Some(node) => {
+                    self.path.truncate(*parent_len);
+                    self.path.push_str(&self.separator);
+                    write!(self.path, "{}", node.data()).unwrap();
+                    self.stack.push((node.iter(), self.path.len()));
+                    return Some((self.path.clone(), node))
+                },
+                None => { self.stack.pop(); },
+            }
+        }
+    }
+}
+
+
+// preorder iterator over the nodes matching a predicate, see `Node::locate_all_by`
+pub struct LocateAll<'a, T, F> {
+    stack: Vec<&'a Node<T>>,
+    matches: F,
+}
+
+impl<'a, T, F> Iterator for LocateAll<'a, T, F>
+    where F: Fn(&T) -> bool
+{
+    type Item = &'a Node<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            let children = node.iter().collect::<Vec<_>>();
+            self.stack.extend(children.into_iter().rev());
+            if (self.matches)(node.data()) {
+                return Some(node);
+            }
+        }
+        None
+    }
+}
+
 
 use std::convert::{TryFrom};
+use std::ops::Range;
 use crate::rust::Formatter;
 
 
 impl TryFrom<&str> for Tree<String> {
     type Error = Error;
     fn try_from(item: &str) -> Result<Self, Self::Error> {
-        Tree::<String>::try_from(item.to_string())
+        let mut forest = parse_forest(item.as_bytes(), true, utf8_value)?;
+        Ok(forest.pop_front().unwrap())
     }
 }
 
@@ -331,66 +1027,589 @@ impl TryFrom<String> for Tree<String> {
     type Error = Error;
 
     fn try_from(item: String) -> Result<Self, Self::Error> {
-        let tree_string = item.trim();
+        Tree::<String>::try_from(item.as_str())
+    }
+}
 
-        if tree_string.starts_with("(") {
-            return Err("no root in tree string".into())
-        }
+// values of any bytes, \xHH escapes and raw bytes outside UTF-8 alike
+impl TryFrom<&[u8]> for Tree<Vec<u8>> {
+    type Error = Error;
 
+    fn try_from(item: &[u8]) -> Result<Self, Self::Error> {
+        let mut forest = parse_forest(item, true, |value| Some(value.to_vec()))?;
+        Ok(forest.pop_front().unwrap())
+    }
+}
 
-        let mut tokens = Vec::new();
-        let mut legal = 0;
+impl TryFrom<&str> for Forest<String> {
+    type Error = Error;
+    fn try_from(item: &str) -> Result<Self, Self::Error> {
+        parse_forest(item.as_bytes(), false, utf8_value)
+    }
+}
 
-        let mut t = String::from("");
-        tree_string.chars().for_each(|v| {
-            match v {
-                '(' | ')' | ' ' => {
-                    if !t.is_empty() {
-                        tokens.push(t.clone());
-                        t = "".to_string();
-                    }
-                    if v !=' ' {
-                        tokens.push(v.to_string());
-                    }
-                    legal = if v == '(' {
-                        legal + 1
-                    } else if v == ')' {
-                        legal - 1
-                    } else {
-                        legal
+// same syntax as a tree string, but any number of roots side by side: "a( b ) c"
+impl TryFrom<String> for Forest<String> {
+    type Error = Error;
+
+    fn try_from(item: String) -> Result<Self, Self::Error> {
+        Forest::<String>::try_from(item.as_str())
+    }
+}
+
+impl TryFrom<&[u8]> for Forest<Vec<u8>> {
+    type Error = Error;
+
+    fn try_from(item: &[u8]) -> Result<Self, Self::Error> {
+        parse_forest(item, false, |value| Some(value.to_vec()))
+    }
+}
+
+fn utf8_value(value: &[u8]) -> Option<String> {
+    String::from_utf8(value.to_vec()).ok()
+}
+
+#[derive(PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Value(Vec<u8>),
+}
+
+// a token and the bytes of the tree string it was read from
+type Spanned = (Range<usize>, Token);
+
+// where the character starting at byte i ends, a byte outside UTF-8 counting as one
+fn char_end(tree_string: &[u8], i: usize) -> usize {
+    i + 1 + tree_string[i + 1..].iter().take(3).take_while(|&&b| b & 0xc0 == 0x80).count()
+}
+
+// the error for the token at span, with the token and a few characters around it in the message
+fn parse_error(tree_string: &[u8], msg: &str, span: Range<usize>) -> Error {
+    const AROUND: usize = 10;
+    let starts = |range: Range<usize>| range.filter(|&i| tree_string[i] & 0xc0 != 0x80).collect::<Vec<_>>();
+    let from = starts(0..span.start).into_iter().rev().nth(AROUND - 1).unwrap_or(0);
+    let to = starts(span.end..tree_string.len()).get(AROUND).copied().unwrap_or(tree_string.len());
+    let mut excerpt = String::new();
+    if from > 0 {
+        excerpt.push_str("...");
+    }
+    excerpt.push_str(&String::from_utf8_lossy(&tree_string[from..to]));
+    if to < tree_string.len() {
+        excerpt.push_str("...");
+    }
+    Error {
+        msg: msg.to_string(),
+        position: Some(ErrorPosition { offset: span.start, token: String::from_utf8_lossy(&tree_string[span]).into_owned(), excerpt }),
+    }
+}
+
+fn hex_digit(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|digit| digit as u8)
+}
+
+// a value is a run of anything but spaces and parens, or a double quoted string in which
+// \n, \r and \t stand for line breaks and tabs, \xHH for the byte of that hex value and a backslash
+// keeps any other character as it is, whitespace around the whole string is left out
+fn tokenize(tree_string: &[u8]) -> Result<Vec<Spanned>, Error> {
+    let mut tokens = Vec::new();
+    let start = tree_string.len() - tree_string.trim_ascii_start().len();
+    let end = tree_string.trim_ascii_end().len().max(start);
+    let mut bytes = tree_string[start..end].iter().copied().enumerate().map(|(i, b)| (start + i, b)).peekable();
+    let unquoted = |b: &(usize, u8)| b.1 != b' ' && b.1 != b'(' && b.1 != b')';
+
+    while let Some((offset, v)) = bytes.next() {
+        match v {
+            b' ' => {},
+            b'(' => tokens.push((offset..offset + 1, Token::Open)),
+            b')' => tokens.push((offset..offset + 1, Token::Close)),
+            b'"' => {
+                let unclosed = || parse_error(tree_string, "unclosed quote in tree string", offset..offset + 1);
+                let mut t = Vec::new();
+                let close = loop {
+                    match bytes.next() {
+                        Some((i, b'"')) => break i,
+                        Some((i, b'\\')) => t.push(match bytes.next() {
+                            Some((_, b'n')) => b'\n',
+                            Some((_, b'r')) => b'\r',
+                            Some((_, b't')) => b'\t',
+                            Some((_, b'x')) => match (bytes.next(), bytes.next()) {
+                                (Some((_, high)), Some((_, low))) => match (hex_digit(high), hex_digit(low)) {
+                                    (Some(high), Some(low)) => high << 4 | low,
+                                    _ => return Err(parse_error(tree_string, "invalid \\x escape in tree string", i..i + 4)),
+                                },
+                                _ => return Err(unclosed()),
+                            },
+                            Some((_, b)) => b,
+                            None => return Err(unclosed()),
+                        }),
+                        Some((_, b)) => t.push(b),
+                        None => return Err(unclosed()),
                     }
+                };
+                if let Some(&(i, _)) = bytes.peek().filter(|b| unquoted(b)) {
+                    return Err(parse_error(tree_string, "unexpected character after quoted value", i..char_end(tree_string, i)))
+                }
+                tokens.push((offset..close + 1, Token::Value(t)));
+            },
+            _ => {
+                let mut t = vec![v];
+                while let Some((_, b)) = bytes.next_if(unquoted) {
+                    t.push(b);
+                }
+                tokens.push((offset..offset + t.len(), Token::Value(t)));
+            },
+        }
+    }
+    Ok(tokens)
+}
+/// Writes `value` the way the tree string parser reads it back as one value: as it is,
+/// or double quoted when it is empty, starts with a quote, holds spaces, parens or control characters,
+/// or is not UTF-8. Inside the quotes, control characters and bytes outside UTF-8 are written as \xHH.
+pub fn quote(value: impl AsRef<[u8]>) -> String {
+    let value = value.as_ref();
+    if let Ok(text) = std::str::from_utf8(value) {
+        if !text.is_empty() && !text.starts_with('"') && !text.contains(|c: char| c == '(' || c == ')' || c.is_whitespace() || c.is_control()) {
+            return text.to_string()
+        }
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' | '\\' => { quoted.push('\\'); quoted.push(c); },
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_ascii_control() => quoted.push_str(&format!("\\x{:02x}", c as u8)),
+                _ => quoted.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            quoted.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// node data as Display shows it in a tree string, quoted where the parser needs it
+pub(crate) struct Quoted<'a, T>(pub(crate) &'a T);
+
+impl<T: Display> Display for Quoted<'_, T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&quote(self.0.to_string()))
+    }
+}
+
+// one root only when single_root, which names the second one in the error. `data` makes the node data
+// of a value, none for a value the data can not hold
+fn parse_forest<T: Unpin>(tree_string: &[u8], single_root: bool, data: impl Fn(&[u8]) -> Option<T>) -> Result<Forest<T>, Error> {
+    let tokens = tokenize(tree_string)?;
+    if single_root && tokens.is_empty() {
+        return Err("empty tree string".into())
+    }
+    // forests[0] holds the roots, every open '(' stacks the children of the node before it
+    let mut forests: Vec<Forest<T>> = vec![Forest::new()];
+    // where each of those '(' is
+    let mut opens: Vec<Range<usize>> = Vec::new();
+    let mut prev: Option<&Token> = None;
+
+    for (span, v) in &tokens {
+        let err = |msg: &str| Err(parse_error(tree_string, msg, span.clone()));
+        match v {
+            Token::Open => match prev {
+                None => return err("no root in tree string"),
+                Some(Token::Close) if forests.len() == 1 => return err("unexpected ( after the tree"),
+                Some(Token::Open) | Some(Token::Close) => return err("( must follow a node"),
+                _ => {
+                    forests.push(Forest::new());
+                    opens.push(span.clone());
                 },
-                _ => t.push(v)
+            },
+            Token::Close => {
+                if prev == Some(&Token::Open) {
+                    return err("empty () in tree string")
+                }
+                if forests.len() == 1 {
+                    return err("unexpected ) after the tree")
+                }
+                let children = forests.pop().unwrap();
+                opens.pop();
+                forests.last_mut().unwrap().back_mut().unwrap().append(children);
+            },
+            Token::Value(value) => {
+                if single_root && forests.len() == 1 && !forests[0].has_no_child() {
+                    return err("multiple roots in tree string")
+                }
+                match data(value) {
+                    Some(data) => forests.last_mut().unwrap().push_back(Tree::new(data)),
+                    None => return err("value is not UTF-8 in tree string"),
+                }
+            },
+        }
+        prev = Some(v);
+    }
+
+    // the number of '(' is not equal to the number of ')', the innermost one left open is named
+    if let Some(span) = opens.pop() {
+        return Err(parse_error(tree_string, "() is not closed", span))
+    }
+    Ok(forests.pop().unwrap())
+}
+
+
+/// Node data the JSON format of `Node::to_json` and `Tree::from_json` carries: a value of any bytes,
+/// and string attributes and a finite number as weight for data that has them.
+pub trait JsonData: Sized {
+    fn json_value(&self) -> &[u8];
+
+    fn json_attributes(&self) -> Vec<(&str, &str)> {
+        Vec::new()
+    }
+
+    fn json_weight(&self) -> Option<f64> {
+        None
+    }
+
+    fn from_json_parts(value: Vec<u8>, attributes: Vec<(String, String)>) -> Result<Self, Error>;
+
+    fn with_json_weight(self, _weight: f64) -> Result<Self, Error> {
+        Err(format!("node {} can not hold a weight", String::from_utf8_lossy(self.json_value())).into())
+    }
+}
+
+impl JsonData for String {
+    fn json_value(&self) -> &[u8] {
+        self.as_bytes()
+    }
+
+    fn from_json_parts(value: Vec<u8>, attributes: Vec<(String, String)>) -> Result<Self, Error> {
+        let value = String::from_utf8(value).map_err(|_| "node value is not UTF-8 in JSON tree")?;
+        match attributes.is_empty() {
+            true => Ok(value),
+            false => Err(format!("node {} can not hold attributes", value).into()),
+        }
+    }
+}
+
+impl JsonData for Vec<u8> {
+    fn json_value(&self) -> &[u8] {
+        self
+    }
+
+    fn from_json_parts(value: Vec<u8>, attributes: Vec<(String, String)>) -> Result<Self, Error> {
+        match attributes.is_empty() {
+            true => Ok(value),
+            false => Err(format!("node {} can not hold attributes", String::from_utf8_lossy(&value)).into()),
+        }
+    }
+}
+
+impl<T: JsonData> Tree<T> {
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let mut forest = parse_json(json, false)?;
+        Ok(forest.pop_front().unwrap())
+    }
+}
+
+impl<T: JsonData> Forest<T> {
+    /// A JSON array of trees as `Node::to_json` writes them, `[]` being the empty forest.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        parse_json(json, true)
+    }
+}
+
+// one JSON tree, or an array of them when `array`
+fn parse_json<T: JsonData>(json: &str, array: bool) -> Result<Forest<T>, Error> {
+    // where the parser stands, objects under construction are kept on a stack instead of the call stack
+    enum Expect { Object, FirstMember, Member, AfterMember, AfterChild }
+    // an object being read, with the members read so far
+    struct Open<T> { value: Option<Vec<u8>>, attributes: Vec<(String, String)>, weight: Option<f64>, children: Forest<T> }
+
+    let mut tokens = JsonTokens { chars: json.chars().peekable() };
+    let mut stack: Vec<Open<T>> = Vec::new();
+    let mut expect = Expect::Object;
+    // the roots of an array are the children of an object without members at the bottom of the stack
+    if array {
+        tokens.expect_punct('[')?;
+        stack.push(Open { value: None, attributes: Vec::new(), weight: None, children: Forest::new() });
+        if tokens.peek_punct(']') {
+            tokens.next();
+            return match tokens.next().transpose()? {
+                None => Ok(Forest::new()),
+                Some(token) => Err(format!("unexpected {} after the JSON trees", token).into()),
             }
-        });
+        }
+    }
 
-        if !t.is_empty() { tokens.push(t) }
+    loop {
+        let token = tokens.next().transpose()?;
+        expect = match (expect, token) {
+            (Expect::Object, Some(JsonToken::Punct('{'))) => {
+                stack.push(Open { value: None, attributes: Vec::new(), weight: None, children: Forest::new() });
+                Expect::FirstMember
+            },
+            (Expect::FirstMember, Some(JsonToken::Punct('}'))) |
+            (Expect::AfterMember, Some(JsonToken::Punct('}'))) => {
+                let open = stack.pop().unwrap();
+                let value = open.value.ok_or("node without value in JSON tree")?;
+                let mut data = T::from_json_parts(value, open.attributes)?;
+                if let Some(weight) = open.weight {
+                    data = data.with_json_weight(weight)?;
+                }
+                let mut tree = Tree::new(data);
+                tree.append(open.children);
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push_back(tree),
+                    None => return match tokens.next().transpose()? {
+                        None => {
+                            let mut forest = Forest::new();
+                            forest.push_back(tree);
+                            Ok(forest)
+                        },
+                        Some(token) => Err(format!("unexpected {} after the JSON tree", token).into()),
+                    },
+                }
+                Expect::AfterChild
+            },
+            (Expect::FirstMember, Some(JsonToken::Str(key))) |
+            (Expect::Member, Some(JsonToken::Str(key))) => {
+                match tokens.next().transpose()? {
+                    Some(JsonToken::Punct(':')) => (),
+                    token => return Err(unexpected_json(token)),
+                }
+                match (key.as_str(), tokens.next().transpose()?) {
+                    ("value", Some(JsonToken::Str(value))) => {
+                        stack.last_mut().unwrap().value = Some(value.into_bytes());
+                        Expect::AfterMember
+                    },
+                    ("value_hex", Some(JsonToken::Str(hex))) => {
+                        let digits = hex.bytes().map(hex_digit).collect::<Option<Vec<_>>>().filter(|digits| digits.len() % 2 == 0)
+                            .ok_or_else(|| Error::from(format!("invalid value_hex {} in JSON tree", hex)))?;
+                        stack.last_mut().unwrap().value = Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect());
+                        Expect::AfterMember
+                    },
+                    // a flat object of strings, read in one go
+                    ("attributes", Some(JsonToken::Punct('{'))) => {
+                        let attributes = &mut stack.last_mut().unwrap().attributes;
+                        if tokens.peek_punct('}') {
+                            tokens.next();
+                        } else {
+                            loop {
+                                let field = match tokens.next().transpose()? {
+                                    Some(JsonToken::Str(field)) => field,
+                                    token => return Err(unexpected_json(token)),
+                                };
+                                match (tokens.next().transpose()?, tokens.next().transpose()?) {
+                                    (Some(JsonToken::Punct(':')), Some(JsonToken::Str(value))) => attributes.push((field, value)),
+                                    (Some(JsonToken::Punct(':')), token) | (token, _) => return Err(unexpected_json(token)),
+                                }
+                                match tokens.next().transpose()? {
+                                    Some(JsonToken::Punct(',')) => (),
+                                    Some(JsonToken::Punct('}')) => break,
+                                    token => return Err(unexpected_json(token)),
+                                }
+                            }
+                        }
+                        Expect::AfterMember
+                    },
+                    ("weight", Some(JsonToken::Other(number))) => {
+                        let weight = number.parse::<f64>().ok().filter(|weight| weight.is_finite())
+                            .ok_or_else(|| Error::from(format!("invalid weight {} in JSON tree", number)))?;
+                        stack.last_mut().unwrap().weight = Some(weight);
+                        Expect::AfterMember
+                    },
+                    ("children", Some(JsonToken::Punct('['))) => {
+                        if tokens.peek_punct(']') {
+                            tokens.next();
+                            Expect::AfterMember
+                        } else {
+                            Expect::Object
+                        }
+                    },
+                    ("value", token) | ("value_hex", token) | ("children", token) | ("attributes", token) | ("weight", token) => return Err(unexpected_json(token)),
+                    _ => return Err(format!("unknown field {} in JSON tree", key).into()),
+                }
+            },
+            (Expect::AfterMember, Some(JsonToken::Punct(','))) => Expect::Member,
+            (Expect::AfterChild, Some(JsonToken::Punct(','))) => Expect::Object,
+            (Expect::AfterChild, Some(JsonToken::Punct(']'))) if array && stack.len() == 1 => {
+                return match tokens.next().transpose()? {
+                    None => Ok(stack.pop().unwrap().children),
+                    Some(token) => Err(format!("unexpected {} after the JSON trees", token).into()),
+                }
+            },
+            (Expect::AfterChild, Some(JsonToken::Punct(']'))) => Expect::AfterMember,
+            (_, token) => return Err(unexpected_json(token)),
+        };
+    }
+}
 
-        // the number of '(' is not equal to the number of ')'
-        if legal !=0 || tokens.len() == 0 {
-            return Err("() is not closed".into())
+/// A node path with the fields to set on its node, `None` for a field to remove.
+pub type JsonPatch = (String, Vec<(String, Option<String>)>);
+
+/// The `{"path": {"field": "value", ..}, ..}` object of attribute patches by node path, in the order
+/// written, a `null` in place of a value standing for the field to remove.
+pub fn json_patches(json: &str) -> Result<Vec<JsonPatch>, Error> {
+    let mut tokens = JsonTokens { chars: json.chars().peekable() };
+    let mut patches = Vec::new();
+    tokens.expect_punct('{')?;
+    while tokens.more_members(patches.is_empty())? {
+        let path = tokens.expect_str()?;
+        tokens.expect_punct(':')?;
+        tokens.expect_punct('{')?;
+        let mut fields = Vec::new();
+        while tokens.more_members(fields.is_empty())? {
+            let field = tokens.expect_str()?;
+            tokens.expect_punct(':')?;
+            let value = match tokens.next().transpose()? {
+                Some(JsonToken::Str(value)) => Some(value),
+                Some(JsonToken::Other(ref other)) if other == "null" => None,
+                token => return Err(unexpected_json(token)),
+            };
+            fields.push((field, value));
         }
+        patches.push((path, fields));
+    }
+    match tokens.next().transpose()? {
+        None => Ok(patches),
+        Some(token) => Err(format!("unexpected {} after the JSON patches", token).into()),
+    }
+}
 
-        let mut tree = Tree::new(String::from(&tokens[0]));
-        let mut forests: Vec<Forest<String>> = Vec::new();
-        tokens.iter().skip(1).for_each(|v| {
-            match v.as_str() {
-                "(" => forests.push(Forest::new()),
-                ")" => {
-                    let last_forest = forests.pop().unwrap();
-                    if let Some(father_forest) = forests.last_mut() {
-                        last_forest.into_iter().for_each(|v| {
-                            father_forest.back_mut().unwrap().push_back(v)
-                        })
-                    } else {
-                        // stack emtpy, append forest to root
-                        tree.root_mut().prepend(last_forest)
-                    }
+fn unexpected_json(token: Option<JsonToken>) -> Error {
+    match token {
+        Some(token) => format!("unexpected {} in JSON tree", token).into(),
+        None => "unexpected end of JSON tree".into(),
+    }
+}
+
+enum JsonToken {
+    Punct(char),
+    Str(String),
+    // numbers, true, false and null, which a JSON tree never holds
+    Other(String),
+}
+
+impl Display for JsonToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonToken::Punct(c) => write!(f, "{}", c),
+            JsonToken::Str(s) => write!(f, "\"{}\"", s),
+            JsonToken::Other(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+struct JsonTokens<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> JsonTokens<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn peek_punct(&mut self, punct: char) -> bool {
+        self.skip_whitespace();
+        self.chars.peek() == Some(&punct)
+    }
+
+    fn expect_punct(&mut self, punct: char) -> Result<(), Error> {
+        match self.next().transpose()? {
+            Some(JsonToken::Punct(c)) if c == punct => Ok(()),
+            token => Err(unexpected_json(token)),
+        }
+    }
+
+    fn expect_str(&mut self) -> Result<String, Error> {
+        match self.next().transpose()? {
+            Some(JsonToken::Str(s)) => Ok(s),
+            token => Err(unexpected_json(token)),
+        }
+    }
+
+    // whether another member of the object follows, past the comma in between unless `first`,
+    // false once the object is closed
+    fn more_members(&mut self, first: bool) -> Result<bool, Error> {
+        if first {
+            if self.peek_punct('}') {
+                self.next();
+                return Ok(false)
+            }
+            return Ok(true)
+        }
+        match self.next().transpose()? {
+            Some(JsonToken::Punct(',')) => Ok(true),
+            Some(JsonToken::Punct('}')) => Ok(false),
+            token => Err(unexpected_json(token)),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.chars.next().and_then(|c| c.to_digit(16)).ok_or("invalid \\u escape in JSON tree")?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next().ok_or("unterminated string in JSON tree")? {
+                '"' => return Ok(s),
+                '\\' => match self.chars.next().ok_or("unterminated string in JSON tree")? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // a surrogate pair spells a code point above the basic plane
+                        if (0xd800..0xdc00).contains(&code) && self.chars.next() == Some('\\') && self.chars.next() == Some('u') {
+                            code = 0x10000 + ((code - 0xd800) << 10) + (self.hex4()?.wrapping_sub(0xdc00) & 0x3ff);
+                        }
+                        s.push(char::from_u32(code).ok_or("invalid \\u escape in JSON tree")?);
+                    },
+                    c => return Err(format!("invalid escape \\{} in JSON tree", c).into()),
                 },
-                _ => forests.last_mut().unwrap().push_back(Tree::new(String::from(v)))
+                c => s.push(c),
             }
-        });
-        Ok(tree)
+        }
+    }
+}
+
+impl<'a> Iterator for JsonTokens<'a> {
+    type Item = Result<JsonToken, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip_whitespace();
+        let c = self.chars.next()?;
+        Some(match c {
+            '{' | '}' | '[' | ']' | ':' | ',' => Ok(JsonToken::Punct(c)),
+            '"' => self.string().map(JsonToken::Str),
+            _ => {
+                let mut other = c.to_string();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_ascii_whitespace() || "{}[]:,\"".contains(c) {
+                        break
+                    }
+                    other.push(c);
+                    self.chars.next();
+                }
+                Ok(JsonToken::Other(other))
+            },
+        })
     }
 }
 
@@ -415,6 +1634,110 @@ mod extend_tests {
         assert!(Tree::try_from(wrong_string).is_err());
     }
 
+    #[test] fn test_try_from_string_rejects_malformed() {
+        let err = |s: &str| Tree::try_from(s).unwrap_err().msg;
+
+        assert_eq!(err("a b"), "multiple roots in tree string");
+        assert_eq!(err("a( b ) c( d )"), "multiple roots in tree string");
+        assert_eq!(err("a( b ) ( c )"), "unexpected ( after the tree");
+        assert_eq!(err("a( b ) )"), "unexpected ) after the tree");
+        assert_eq!(err("a( b ( c ) ( d ) )"), "( must follow a node");
+        assert_eq!(err("a( b () )"), "empty () in tree string");
+        assert_eq!(err("a()"), "empty () in tree string");
+        assert_eq!(err("a( b ( c )"), "() is not closed");
+        assert_eq!(err("   "), "empty tree string");
+        assert_eq!(Tree::try_from("a( b( c ) d )").unwrap().to_string(), "a( b( c ) d )");
+    }
+
+    #[test] fn test_parse_error_position() {
+        let position = |s: &str| Tree::try_from(s).unwrap_err().position.map(|p| (p.offset, p.token, p.excerpt));
+        let at = |offset, token: &str, excerpt: &str| Some((offset, token.to_string(), excerpt.to_string()));
+
+        assert_eq!(position("a( b ) c"), at(7, "c", "a( b ) c"));
+        assert_eq!(position("  a( b ) )"), at(9, ")", "  a( b ) )"));
+        assert_eq!(position("( a )"), at(0, "(", "( a )"));
+        // the innermost ( left open
+        assert_eq!(position("a( b( c( d ) e"), at(4, "(", "a( b( c( d ) e"));
+        assert_eq!(position("root( one two three four( five ) ) ( six )"), at(35, "(", "... five ) ) ( six )"));
+        assert_eq!(position(r#"a( "bé \" c )"#), at(3, "\"", r#"a( "bé \" c )"#));
+        assert_eq!(position("a( \"b\"c )"), at(6, "c", "a( \"b\"c )"));
+        assert_eq!(position("   "), None);
+
+        let long = format!("r( {} ) )", "x ".repeat(20));
+        assert_eq!(position(&long), at(46, ")", "... x x x  ) )"));
+        assert_eq!(Tree::try_from("a b").unwrap_err().to_string(), "multiple roots in tree string: b at byte 2 near \"a b\"");
+        assert_eq!(Forest::try_from("a ( b ) )").unwrap_err().to_string(), "unexpected ) after the tree: ) at byte 8 near \"a ( b ) )\"");
+    }
+
+    #[test] fn test_try_from_quoted_string() {
+        let tree = Tree::try_from(r#""New York"( "(1)" "say \"hi\"" C:\ a"b "" )"#).unwrap();
+        let values = tree.bfs().iter.map(|visit| visit.data.as_str()).collect::<Vec<_>>();
+        assert_eq!(values, vec!["New York", "(1)", "say \"hi\"", "C:\\", "a\"b", ""]);
+
+        let err = |s: &str| Tree::try_from(s).unwrap_err().msg;
+        assert_eq!(err(r#"a( "b )"#), "unclosed quote in tree string");
+        assert_eq!(err(r#"a( "b\"#), "unclosed quote in tree string");
+        assert_eq!(err(r#"a( "b"c )"#), "unexpected character after quoted value");
+    }
+
+    #[test] fn test_quote() {
+        assert_eq!(quote("a"), "a");
+        assert_eq!(quote("a\"b"), "a\"b");
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote("New York"), r#""New York""#);
+        assert_eq!(quote("(1)"), r#""(1)""#);
+        assert_eq!(quote(r#""hi" \"#), r#""\"hi\" \\""#);
+        for value in ["", "a b", "(", "\"", "\\ \"", "tab\there"] {
+            let tree = Tree::try_from(format!("r( {} )", quote(value))).unwrap();
+            assert_eq!(tree.front().unwrap().data(), value);
+        }
+        assert_eq!(quote("two\nlines\ttab\r"), r#""two\nlines\ttab\r""#);
+        assert_eq!(quote("bell\u{7}"), r#""bell\x07""#);
+        assert_eq!(quote(b"nul\0"), r#""nul\x00""#);
+        assert_eq!(quote(b"\xffhi\xc3"), r#""\xffhi\xc3""#);
+    }
+
+    #[test] fn test_try_from_bytes() {
+        let tree = Tree::<Vec<u8>>::try_from(&b"r( \"a\\x00b\" \"\\xff\\xFE\" \xc3( x ) )"[..]).unwrap();
+        let values = tree.bfs().iter.map(|visit| visit.data.clone()).collect::<Vec<_>>();
+        assert_eq!(values, vec![b"r".to_vec(), b"a\0b".to_vec(), b"\xff\xfe".to_vec(), b"\xc3".to_vec(), b"x".to_vec()]);
+        for value in [&b"a\0b"[..], b"\xff", b"\xe2\x82 (", b"\x01\x7f\\x41"] {
+            let tree = Tree::<Vec<u8>>::try_from(format!("r( {} )", quote(value)).as_bytes()).unwrap();
+            assert_eq!(tree.front().unwrap().data(), value);
+        }
+        assert_eq!(Forest::<Vec<u8>>::try_from(&b"\xff a"[..]).unwrap().iter().count(), 2);
+
+        let err = |s: &[u8]| Tree::<Vec<u8>>::try_from(s).unwrap_err().to_string();
+        assert_eq!(err(br#"a( "b\xg0" )"#), r#"invalid \x escape in tree string: \xg0 at byte 5 near "a( "b\xg0" )""#);
+        assert_eq!(err(br#"a( "b\x0"#), "unclosed quote in tree string: \" at byte 3 near \"a( \"b\\x0\"");
+        assert_eq!(err(b"a( \"b\"\xff )"), "unexpected character after quoted value: \u{fffd} at byte 6 near \"a( \"b\"\u{fffd} )\"");
+        assert_eq!(Tree::try_from(r#"a( "\xff" )"#).unwrap_err().msg, "value is not UTF-8 in tree string");
+        assert_eq!(Tree::try_from(r#"a( "\xc3\xa9" )"#).unwrap().front().unwrap().data(), "é");
+    }
+
+    #[test] fn test_display_round_trips_quoted_values() {
+        let mut tree = Tree::new("North America".to_string());
+        for value in ["f(x)", "", "\"hi\"", "C:\\", "line\nbreak", "plain"] {
+            tree.push_back(Tree::new(value.to_string()));
+        }
+        let printed = tree.to_string();
+        assert_eq!(printed, r#""North America"( "f(x)" "" "\"hi\"" C:\ "line\nbreak" plain )"#);
+        assert_eq!(Tree::try_from(printed).unwrap(), tree);
+
+        let forest = Forest::try_from(r#""a b" c( "d\te" )"#).unwrap();
+        assert_eq!(forest.to_string(), r#"( "a b" c( "d\te" ) )"#);
+        assert_eq!(Tree::try_from(r#"a( "x\qy" )"#).unwrap().front().unwrap().data(), "xqy");
+        assert_eq!((tr(1) /tr(2)).to_string(), "1( 2 )");
+    }
+
+    #[test] fn test_forest_try_from_string() {
+        assert_eq!(Forest::try_from("a( b ) c( d e )").unwrap().to_string(), "( a( b ) c( d e ) )");
+        assert_eq!(Forest::try_from("a").unwrap().to_string(), "( a )");
+        assert_eq!(Forest::try_from("").unwrap().to_string(), "()");
+        assert!(Forest::try_from("( a )").is_err());
+        assert!(Forest::try_from("a( b ) ( c )").is_err());
+    }
+
 
     #[test] fn test_node_locate_by_path() {
         let mut tree = tr(0) /(tr(1)/tr(2)) /(tr(3)/tr(4));
@@ -445,17 +1768,425 @@ mod extend_tests {
 
     }
 
+    #[test] fn test_node_locate_by() {
+        let mut t = Tree::try_from("   Root( Food( Fruit Meat) Toys )  ".to_owned()).unwrap();
+        let node = t.root().locate_first_by(&|v: &String| v.to_lowercase() == "fruit");
+        assert_eq!(node.unwrap().data(), "Fruit");
+        assert!(t.root().locate_first_by(&|v: &String| v == "fruit").is_none());
+
+        let mut root = t.root_mut();
+        let mut node = root.locate_first_mut_by(&|v: &String| v.eq_ignore_ascii_case("TOYS")).unwrap();
+        node.push_back(Tree::new("Ball".to_string()));
+        assert_eq!(t.to_string(), "Root( Food( Fruit Meat ) Toys( Ball ) )");
+    }
+
+    #[test] fn test_node_locate_all() {
+        let t = Tree::try_from("a( b( x c( x ) ) x d )".to_owned()).unwrap();
+        let paths = t.root().locate_all_by_data(&"x".to_string())
+            .map(|node| node.path())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![vec!["a", "b", "x"], vec!["a", "b", "c", "x"], vec!["a", "x"]]);
+
+        assert_eq!(t.root().locate_all_by_data(&"y".to_string()).count(), 0);
+        assert_eq!(t.root().locate_all_by(|v: &String| v.len() == 1).count(), 7);
+    }
+
     #[test] fn test_ancestors() {
         let mut t = Tree::try_from("   0( 1( 2 3bc) 4( 5 6 ) )  ".to_owned()).unwrap();
         println!("{:?}", t.root().locate_first_by_data(&"3bc".to_string()).unwrap().ancestors());
         println!("{:?}", t.to_string());
     }
 
+    #[test] fn test_path() {
+        let t = Tree::try_from("   0( 1( 2 3bc) 4( 5 6 ) )  ".to_owned()).unwrap();
+        assert_eq!(t.root().locate_first_by_data(&"3bc".to_string()).unwrap().path(), vec!["0", "1", "3bc"]);
+        assert_eq!(t.root().path(), vec!["0"]);
+    }
+
+    #[test] fn test_lowest_common_ancestor() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) )".to_owned()).unwrap();
+        let node = |v: &str| t.root().locate_first_by_data(&v.to_string()).unwrap();
+
+        assert_eq!(node("2").lowest_common_ancestor(node("7")).unwrap().data(), "1");
+        assert_eq!(node("7").lowest_common_ancestor(node("6")).unwrap().data(), "0");
+        assert_eq!(node("3").lowest_common_ancestor(node("7")).unwrap().data(), "3");
+        assert_eq!(node("7").lowest_common_ancestor(node("3")).unwrap().data(), "3");
+        assert_eq!(node("5").lowest_common_ancestor(node("5")).unwrap().data(), "5");
+
+        let other = Tree::new("0".to_string());
+        assert!(node("5").lowest_common_ancestor(other.root()).is_none());
+    }
+
+    #[test] fn test_update_all_by() {
+        let mut t = Tree::try_from("a( b( x ) x( c ) )").unwrap();
+        let mut order = Vec::new();
+        let updated = t.root_mut().update_all_by(|v| v == "x" || v == "a", |v| {
+            order.push(v.clone());
+            *v = v.to_uppercase();
+        });
+        assert_eq!(updated, 3);
+        assert_eq!(order, vec!["a", "x", "x"]);
+        assert_eq!(t.to_string(), "A( b( X ) X( c ) )");
+    }
+
+    #[test] fn test_path_to() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) )".to_owned()).unwrap();
+        let node = |v: &str| t.root().locate_first_by_data(&v.to_string()).unwrap();
+
+        assert_eq!(node("7").path_to(node("5")).unwrap(), vec!["7", "3", "1", "0", "4", "5"]);
+        assert_eq!(node("2").path_to(node("7")).unwrap(), vec!["2", "1", "3", "7"]);
+        assert_eq!(node("0").path_to(node("7")).unwrap(), vec!["0", "1", "3", "7"]);
+        assert_eq!(node("7").path_to(node("1")).unwrap(), vec!["7", "3", "1"]);
+        assert_eq!(node("6").path_to(node("6")).unwrap(), vec!["6"]);
+    }
+
     #[test] fn test_descendants() {
         let mut t = Tree::try_from("   0( 1( 2 3bc) 4( 5 6 ) )  ".to_owned()).unwrap();
         println!("{:?}", t.root().locate_first_by_data(&"1".to_string()).unwrap().descendants());
         println!("{:?}", t.to_string());
     }
 
+    #[test] fn test_subtree_hash() {
+        let hash = |s: &str| Tree::try_from(s).unwrap().root().subtree_hash();
+
+        assert_eq!(hash("a( b c )"), hash("a( b c )"));
+        assert_ne!(hash("a( b c )"), hash("a( b( c ) )"));
+        assert_ne!(hash("a( b c )"), hash("a( c b )"));
+        assert_ne!(hash("a( bc )"), hash("a( b c )"));
+        // pinned, hashes cached by clients have to survive upgrades
+        assert_eq!(hash("WhiteHouse( Biden )"), 0x38397e38d545966f);
+
+        let t = Tree::try_from("x( a( b c ) )").unwrap();
+        assert_eq!(t.root().iter().next().unwrap().subtree_hash(), hash("a( b c )"));
+    }
+
+    #[test] fn test_shape() {
+        let t = Tree::try_from("r( x y( secret z ) )").unwrap();
+        assert_eq!(t.root().shape().to_string(), "0( 0 1( 0 1 ) )");
+        assert_eq!(Tree::try_from("a").unwrap().root().shape().to_string(), "0");
+        assert_eq!(Tree::try_from("a( b( c( d ) e ) f( g h ) )").unwrap().root().shape().to_string(),
+            "0( 0( 0( 0 ) 1 ) 1( 0 1 ) )");
+
+        let hash = |s: &str| Tree::try_from(s).unwrap().root().shape_hash();
+        assert_eq!(hash("r( x y( secret z ) )"), hash("a( b c( d e ) )"));
+        assert_ne!(hash("r( x y( secret z ) )"), hash("a( b( d e ) c )"));
+    }
+
+    #[test] fn test_depth_and_height() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4 5 )".to_owned()).unwrap();
+        let node = |v: &str| t.root().locate_first_by_data(&v.to_string()).unwrap();
+
+        assert_eq!(t.root().depth(), 0);
+        assert_eq!(node("3").depth(), 2);
+        assert_eq!(node("7").depth(), 3);
+        assert_eq!(t.root().height(), 3);
+        assert_eq!(node("1").height(), 2);
+        assert_eq!(node("4").height(), 0);
+    }
+
+    #[test] fn test_child_index() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4 5 )".to_owned()).unwrap();
+        let node = |v: &str| t.root().locate_first_by_data(&v.to_string()).unwrap();
+
+        assert_eq!(node("1").child_index(), Some(0));
+        assert_eq!(node("5").child_index(), Some(2));
+        assert_eq!(node("3").child_index(), Some(1));
+        assert_eq!(node("7").child_index(), Some(0));
+        assert_eq!(t.root().child_index(), None);
+    }
+
+    #[test] fn test_leaves() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) 8 )".to_owned()).unwrap();
+        let node = |v: &str| t.root().locate_first_by_data(&v.to_string()).unwrap();
+        let leaves = |n: &Node<String>| n.leaves().map(|n| n.data().clone()).collect::<Vec<_>>();
+
+        assert_eq!(leaves(t.root()), vec!["2", "7", "5", "6", "8"]);
+        assert_eq!(leaves(node("1")), vec!["2", "7"]);
+        assert_eq!(leaves(node("5")), vec!["5"]);
+    }
+
+    #[test] fn test_iter_paths() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4 )").unwrap();
+        let paths = |n: &Node<String>, sep: &str| n.iter_paths(sep).map(|(path, n)| {
+            assert_eq!(path, n.path().into_iter().map(String::as_str).collect::<Vec<_>>().join(sep));
+            path
+        }).collect::<Vec<_>>();
+
+        assert_eq!(paths(t.root(), "/"), vec!["0", "0/1", "0/1/2", "0/1/3", "0/1/3/7", "0/4"]);
+        let node = t.root().locate_first_by_data(&"3".to_string()).unwrap();
+        assert_eq!(paths(node, "::"), vec!["0::1::3", "0::1::3::7"]);
+    }
+
+    #[test] fn test_sort_children_by() {
+        let mut t = Tree::try_from("0( 3( z x y ) 1 2( b a ) 1 )".to_owned()).unwrap();
+        assert!(t.root_mut().sort_children_by(false, |a, b| a.cmp(b)));
+        assert_eq!(t.to_string(), "0( 1 1 2( b a ) 3( z x y ) )");
+        assert!(!t.root_mut().sort_children_by(false, |a, b| a.cmp(b)));
+        assert!(t.root_mut().sort_children_by(true, |a, b| b.cmp(a)));
+        assert_eq!(t.to_string(), "0( 3( z y x ) 2( b a ) 1 1 )");
+        assert!(!t.root_mut().sort_children_by(true, |a, b| b.cmp(a)));
+        assert_eq!(t.root().degree(), 4);
+        assert_eq!(t.root().node_count(), 10);
+        let node = t.root().locate_first_by_data(&"x".to_string()).unwrap();
+        assert_eq!(node.father(), Some(&"3".to_string()));
+    }
+
+    #[test] fn test_nth_in_preorder() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) 8 )".to_owned()).unwrap();
+        let preorder = (0..t.root().node_count())
+            .map(|n| t.root().nth_in_preorder(n).unwrap().data().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(preorder, vec!["0", "1", "2", "3", "7", "4", "5", "6", "8"]);
+        assert!(t.root().nth_in_preorder(9).is_none());
+        let node = t.root().locate_first_by_data(&"4".to_string()).unwrap();
+        assert_eq!(node.nth_in_preorder(2).unwrap().data(), "6");
+        assert!(node.nth_in_preorder(3).is_none());
+    }
+
+    #[test] fn test_siblings() {
+        let t = Tree::try_from("0( 1 2( 7 ) 3 )".to_owned()).unwrap();
+        let node = |v: &str| t.root().locate_first_by_data(&v.to_string()).unwrap();
+        fn data<'a>(nodes: Siblings<'a, String>) -> Vec<&'a str> {
+            nodes.map(|n| n.data().as_str()).collect::<Vec<_>>()
+        }
+
+        assert_eq!(data(node("2").siblings(false)), vec!["1", "3"]);
+        assert_eq!(data(node("1").siblings(true)), vec!["1", "2", "3"]);
+        assert_eq!(data(node("7").siblings(false)), Vec::<&str>::new());
+        assert_eq!(data(node("7").siblings(true)), vec!["7"]);
+        assert_eq!(data(t.root().siblings(true)), Vec::<&str>::new());
+
+        let f = Forest::try_from("a b c").unwrap();
+        assert_eq!(data(f.iter().nth(1).unwrap().siblings(false)), vec!["a", "c"]);
+    }
+
+    #[test] fn test_to_json() {
+        let t = Tree::try_from("0( 1( 2 ) 3 )").unwrap();
+        assert_eq!(t.root().to_json(),
+            r#"{"value":"0","children":[{"value":"1","children":[{"value":"2","children":[]}]},{"value":"3","children":[]}]}"#);
+        assert_eq!(t.root().iter().next().unwrap().to_json(), r#"{"value":"1","children":[{"value":"2","children":[]}]}"#);
+        assert_eq!(Tree::new("a\"b\\\u{1}".to_string()).root().to_json(), r#"{"value":"a\"b\\\u0001","children":[]}"#);
+    }
+
+    // a value with attributes, which String can not hold
+    #[derive(Debug, PartialEq)]
+    struct Tagged(String, Vec<(String, String)>);
+
+    impl JsonData for Tagged {
+        fn json_value(&self) -> &[u8] { self.0.as_bytes() }
+        fn json_attributes(&self) -> Vec<(&str, &str)> {
+            self.1.iter().map(|(field, value)| (field.as_str(), value.as_str())).collect()
+        }
+        fn from_json_parts(value: Vec<u8>, attributes: Vec<(String, String)>) -> Result<Self, Error> {
+            Ok(Tagged(String::from_utf8(value).unwrap(), attributes))
+        }
+    }
+
+    #[test] fn test_json_bytes() {
+        let t = Tree::<Vec<u8>>::try_from(&b"r( \"a\\x00\" \"\\xff\\x01\" )"[..]).unwrap();
+        let json = t.root().to_json();
+        assert_eq!(json, r#"{"value":"r","children":[{"value":"a\u0000","children":[]},{"value_hex":"ff01","children":[]}]}"#);
+        assert_eq!(Tree::<Vec<u8>>::from_json(&json).unwrap(), t);
+
+        let err = |s: &str| Tree::<Vec<u8>>::from_json(s).unwrap_err().to_string();
+        assert_eq!(err(r#"{"value_hex": "f"}"#), "invalid value_hex f in JSON tree");
+        assert_eq!(err(r#"{"value_hex": "zz"}"#), "invalid value_hex zz in JSON tree");
+        assert_eq!(Tree::<String>::from_json(r#"{"value_hex": "ff"}"#).unwrap_err().to_string(), "node value is not UTF-8 in JSON tree");
+        assert_eq!(Tree::<String>::from_json(r#"{"value_hex": "c3a9"}"#).unwrap().root().data(), "é");
+    }
+
+    #[test] fn test_json_attributes() {
+        let json = r#"{"value":"0","attributes":{"a":"1","b\"":""},"children":[{"value":"1","children":[]}]}"#;
+        let t = Tree::<Tagged>::from_json(json).unwrap();
+        assert_eq!(t.root().data(), &Tagged("0".into(), vec![("a".into(), "1".into()), ("b\"".into(), "".into())]));
+        assert_eq!(t.root().front().unwrap().data(), &Tagged("1".into(), vec![]));
+        assert_eq!(t.root().to_json(), json);
+        assert_eq!(Tree::<Tagged>::from_json(r#"{"attributes":{}, "value":"x"}"#).unwrap().root().data().1, vec![]);
+
+        let err = |s: &str| Tree::<Tagged>::from_json(s).unwrap_err().to_string();
+        assert_eq!(err(r#"{"value": "a", "attributes": {"b": 1}}"#), "unexpected 1 in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "attributes": {"b" "c"}}"#), "unexpected \"c\" in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "attributes": {"b": "c",}}"#), "unexpected } in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "attributes": ["b"]}"#), "unexpected [ in JSON tree");
+        assert_eq!(Tree::<String>::from_json(r#"{"value": "a", "attributes": {"b": "c"}}"#).unwrap_err().to_string(),
+            "node a can not hold attributes");
+    }
+
+    // a value with a weight, which String can not hold
+    #[derive(Debug, PartialEq)]
+    struct Weighted(String, Option<f64>);
+
+    impl JsonData for Weighted {
+        fn json_value(&self) -> &[u8] { self.0.as_bytes() }
+        fn json_weight(&self) -> Option<f64> { self.1 }
+        fn from_json_parts(value: Vec<u8>, _attributes: Vec<(String, String)>) -> Result<Self, Error> {
+            Ok(Weighted(String::from_utf8(value).unwrap(), None))
+        }
+        fn with_json_weight(self, weight: f64) -> Result<Self, Error> {
+            Ok(Weighted(self.0, Some(weight)))
+        }
+    }
+
+    #[test] fn test_json_weight() {
+        let json = r#"{"value":"0","children":[{"value":"1","weight":0.25,"children":[]},{"value":"2","weight":-3,"children":[]}]}"#;
+        let t = Tree::<Weighted>::from_json(json).unwrap();
+        assert_eq!(t.root().data(), &Weighted("0".into(), None));
+        assert_eq!(t.root().back().unwrap().data(), &Weighted("2".into(), Some(-3.0)));
+        assert_eq!(t.root().to_json(), json);
+        assert_eq!(Tree::<Weighted>::from_json(r#"{"value": "x", "weight": 1e3}"#).unwrap().root().data().1, Some(1000.0));
+
+        let err = |s: &str| Tree::<Weighted>::from_json(s).unwrap_err().to_string();
+        assert_eq!(err(r#"{"value": "a", "weight": "1"}"#), "unexpected \"1\" in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "weight": 1x}"#), "invalid weight 1x in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "weight": NaN}"#), "invalid weight NaN in JSON tree");
+        assert_eq!(Tree::<String>::from_json(r#"{"value": "a", "weight": 1}"#).unwrap_err().to_string(),
+            "node a can not hold a weight");
+    }
+
+    #[test] fn test_json_patches() {
+        let patches = json_patches(r#" {"a/b": {"x": "1", "y": null}, "a": {}} "#).unwrap();
+        assert_eq!(patches, vec![
+            ("a/b".to_string(), vec![("x".to_string(), Some("1".to_string())), ("y".to_string(), None)]),
+            ("a".to_string(), vec![]),
+        ]);
+        assert_eq!(json_patches("{}").unwrap(), vec![]);
+
+        let err = |s: &str| json_patches(s).unwrap_err().to_string();
+        assert_eq!(err(r#"{"a": {"x": 1}}"#), "unexpected 1 in JSON tree");
+        assert_eq!(err(r#"{"a": "x"}"#), "unexpected \"x\" in JSON tree");
+        assert_eq!(err(r#"{"a": {"x": "1",}}"#), "unexpected } in JSON tree");
+        assert_eq!(err(r#"{"a": {}} {}"#), "unexpected { after the JSON patches");
+        assert_eq!(err(r#"{"a": {}"#), "unexpected end of JSON tree");
+    }
+
+    #[test] fn test_to_dot() {
+        let t = Tree::try_from("a( b( a ) c )").unwrap();
+        assert_eq!(t.root().to_dot(), "digraph tree {\n  n0 [label=\"a\"];\n  n1 [label=\"b\"];\n  n0 -> n1;\n  \
+            n2 [label=\"a\"];\n  n1 -> n2;\n  n3 [label=\"c\"];\n  n0 -> n3;\n}");
+        assert_eq!(Tree::new("x\"y".to_string()).root().to_dot(), "digraph tree {\n  n0 [label=\"x\\\"y\"];\n}");
+    }
+
+    #[test] fn test_from_json() {
+        let t = Tree::try_from("0( 1( 2 ) 3 )").unwrap();
+        assert_eq!(Tree::from_json(&t.root().to_json()).unwrap(), t);
+        let t = Tree::<String>::from_json(r#" { "children" : [ {"value": "a\"b\u00e9\ud83c\udf33"} ], "value": "r" } "#).unwrap();
+        assert_eq!(t.root().data(), "r");
+        assert_eq!(t.root().front().unwrap().data(), "a\"b\u{e9}\u{1f333}");
+
+        let err = |s: &str| Tree::<String>::from_json(s).unwrap_err().to_string();
+        assert_eq!(err(""), "unexpected end of JSON tree");
+        assert_eq!(err(r#"{"value": "a""#), "unexpected end of JSON tree");
+        assert_eq!(err(r#"{"children": []}"#), "node without value in JSON tree");
+        assert_eq!(err(r#"{"value": 1}"#), "unexpected 1 in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "tags": []}"#), "unknown field tags in JSON tree");
+        assert_eq!(err(r#"{"value": "a", "children": [{"value": "b"},]}"#), "unexpected ] in JSON tree");
+        assert_eq!(err(r#"{"value": "a"} {"value": "b"}"#), "unexpected { after the JSON tree");
+        assert_eq!(err(r#"{"value": "a\q"}"#), "invalid escape \\q in JSON tree");
+        assert_eq!(err(r#"{"value": "a}"#), "unterminated string in JSON tree");
+
+        // deeper than the call stack would allow
+        let deep = r#"{"value":"n","children":["#.repeat(100_000) + r#"{"value":"leaf"}"# + &"]}".repeat(100_000);
+        let t = Tree::<String>::from_json(&deep).unwrap();
+        assert_eq!(t.root().node_count(), 100_001);
+        // dropping recurses as deep as the tree is
+        std::mem::forget(t);
+    }
+
+    #[test] fn test_forest_from_json() {
+        let forest = Forest::<String>::from_json(r#" [ {"value": "a", "children": [{"value": "b"}]}, {"value": "c"} ] "#).unwrap();
+        assert_eq!(forest.to_string(), "( a( b ) c )");
+        assert_eq!(Forest::<String>::from_json("[]").unwrap().to_string(), "()");
+
+        let err = |s: &str| Forest::<String>::from_json(s).unwrap_err().to_string();
+        assert_eq!(err(r#"{"value": "a"}"#), "unexpected { in JSON tree");
+        assert_eq!(err(r#"[{"value": "a"},]"#), "unexpected ] in JSON tree");
+        assert_eq!(err(r#"[{"value": "a"}] []"#), "unexpected [ after the JSON trees");
+        assert_eq!(err("[] {}"), "unexpected { after the JSON trees");
+        assert_eq!(err(r#"[{"value": "a"}"#), "unexpected end of JSON tree");
+    }
+
+    #[test] fn test_deep_clone_chunked() {
+        let t = Tree::try_from("0( 1( 2 3( 7 8 ) ) 4( 5 6 ) 9 )").unwrap();
+        for budget in 0..12 {
+            let mut chunks = 1;
+            let mut state = t.deep_clone_chunked(budget, None);
+            let clone = loop {
+                match state {
+                    ChunkedClone::Pending(resume) => state = t.deep_clone_chunked(budget, Some(resume)),
+                    ChunkedClone::Done(clone) => break clone,
+                }
+                chunks += 1;
+            };
+            assert_eq!(clone, t);
+            assert_eq!(clone.root().node_count(), 10);
+            assert_eq!(chunks, 10_usize.div_ceil(budget.max(1)));
+        }
+
+        // the source changes between two chunks
+        let mut t = Tree::try_from("a( b( c d ) e( f ) )").unwrap();
+        let state = match t.deep_clone_chunked(3, None) {
+            ChunkedClone::Pending(state) => state,
+            ChunkedClone::Done(_) => unreachable!(),
+        };
+        t.root_mut().pop_front();
+        match t.deep_clone_chunked(10, Some(state)) {
+            ChunkedClone::Done(clone) => assert_eq!(clone.to_string(), "a( b( c ) )"),
+            ChunkedClone::Pending(_) => unreachable!(),
+        }
+    }
+
+    #[test] fn test_bfs_levels() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) )").unwrap();
+        fn levels(n: &Node<String>) -> Vec<(usize, &str)> {
+            n.bfs_levels().map(|(level, visit)| (level, visit.data.as_str())).collect()
+        }
+        assert_eq!(levels(t.root()), vec![(0, "0"), (1, "1"), (1, "4"), (2, "2"), (2, "3"), (2, "5"), (2, "6"), (3, "7")]);
+        let node = t.root().locate_first_by_data(&"1".to_string()).unwrap();
+        assert_eq!(levels(node), vec![(0, "1"), (1, "2"), (1, "3"), (2, "7")]);
+        assert_eq!(levels(t.root().back().unwrap().back().unwrap()), vec![(0, "6")]);
+    }
+
+    #[test] fn test_descendants_to_depth() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) )".to_owned()).unwrap();
+        assert_eq!(t.root().descendants_to_depth(0), vec!["0"]);
+        assert_eq!(t.root().descendants_to_depth(1), vec!["0", "1", "4"]);
+        assert_eq!(t.root().descendants_to_depth(2), vec!["0", "1", "4", "2", "3", "5", "6"]);
+        assert_eq!(t.root().descendants_to_depth(9), t.root().descendants());
+    }
+
+    #[test] fn test_filtered() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) 8 )".to_owned()).unwrap();
+        let kept = |values: &[&str]| t.root().filtered(|v| values.contains(&v.as_str())).map(|t| t.to_string());
+        assert_eq!(kept(&["7"]), Some("0( 1( 3( 7 ) ) )".to_owned()));
+        assert_eq!(kept(&["7", "5", "8"]), Some("0( 1( 3( 7 ) ) 4( 5 ) 8 )".to_owned()));
+        assert_eq!(kept(&["1"]), Some("0( 1 )".to_owned()));
+        assert_eq!(kept(&["0"]), Some("0".to_owned()));
+        assert_eq!(kept(&["x"]), None);
+
+        let node = t.root().iter().next().unwrap();
+        assert_eq!(node.filtered(|v| v == "2").map(|t| t.to_string()), Some("1( 2 )".to_owned()));
+    }
+
+    #[test] fn test_level_widths() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) 8 )".to_owned()).unwrap();
+        assert_eq!(t.root().level_widths(), vec![1, 3, 4, 1]);
+        assert_eq!(t.root().iter().nth(1).unwrap().level_widths(), vec![1, 2]);
+        assert_eq!(Tree::try_from("a".to_owned()).unwrap().root().level_widths(), vec![1]);
+    }
+
+    #[test] fn test_truncated() {
+        let t = Tree::try_from("0( 1( 2 3( 7 ) ) 4( 5 6 ) 8 )".to_owned()).unwrap();
+        let marker = "...".to_owned();
+        assert_eq!(t.root().truncated(0, None).to_string(), "0");
+        assert_eq!(t.root().truncated(0, Some(&marker)).to_string(), "0( ... )");
+        assert_eq!(t.root().truncated(1, None).to_string(), "0( 1 4 8 )");
+        assert_eq!(t.root().truncated(1, Some(&marker)).to_string(), "0( 1( ... ) 4( ... ) 8 )");
+        assert_eq!(t.root().truncated(2, Some(&marker)).to_string(), "0( 1( 2 3( ... ) ) 4( 5 6 ) 8 )");
+        assert_eq!(t.root().truncated(9, Some(&marker)), t);
+
+        let node = t.root().iter().next().unwrap();
+        assert_eq!(node.truncated(1, Some(&marker)).to_string(), "1( 2 3( ... ) )");
+    }
+
 
 }
\ No newline at end of file
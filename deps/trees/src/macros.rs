@@ -114,9 +114,9 @@ macro_rules! impl_debug_display_for_node {
         impl<T:Display> Display for $ty<T> {
             fn fmt( &self, f: &mut Formatter ) -> fmt::Result {
                 if self.has_no_child() {
-                    write!( f, "{}", self.$($data)+ )
+                    write!( f, "{}", crate::Quoted( &self.$($data)+ ))
                 } else {
-                    write!( f, "{}", self.$($data)+ )?;
+                    write!( f, "{}", crate::Quoted( &self.$($data)+ ))?;
                     write!( f, "( " )?;
                     for child in self.$iter() {
                         write!( f, "{} ", child )?;
@@ -14,6 +14,7 @@ pub(crate) struct NodeVec<T> {
 
 impl<T> NodeVec<T> {
     pub(crate) fn new_raw_non_null( cap: usize ) -> NonNull<NodeVec<T>> {
+        #[cfg( feature = "stats" )] crate::stats::count_allocations( cap );
         unsafe {
             NonNull::new_unchecked( Box::into_raw( Box::new( NodeVec::<T> {
                 buf: (0..cap).map( |_| Shared::new( RefCell::new( Node::default() ))).collect::<Vec<_>>(),
@@ -453,10 +453,51 @@ impl<T> RcNode<T> {
     pub fn downgrade( &self ) -> WeakNode<T> {
         match self {
             RcNode::Scattered( ScatteredRcNode( rc )) => WeakNode::Scattered( ScatteredWeakNode( Rc::downgrade( &rc ))),
-            RcNode::Piled( PiledRcNode( node_vec, index )) => WeakNode::Piled( PiledWeakNode( *node_vec, *index )),
+            RcNode::Piled( PiledRcNode( node_vec, index )) => {
+                // released again in `WeakNode::drop()`
+                unsafe{ node_vec.as_ref().ref_cnt.incr(); }
+                WeakNode::Piled( PiledWeakNode( *node_vec, *index ))
+            },
         }
     }
 
+    /// Creates weak pointers to the child nodes, which do not keep them alive once detached.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trees::{RcNode, tr};
+    ///
+    /// let root = RcNode::from( tr(0) /tr(1) /tr(2) );
+    /// let children = root.children_weak();
+    /// assert_eq!( children.len(), 2 );
+    /// assert_eq!( *children[1].upgrade().unwrap().data(), 2 );
+    ///
+    /// drop( root.pop_back() );
+    /// assert!( children[1].upgrade().is_none() );
+    /// ```
+    pub fn children_weak( &self ) -> Vec<WeakNode<T>> {
+        self.iter_rc().map( |child| child.downgrade() ).collect()
+    }
+
+    /// Creates a weak pointer to the parent node,
+    /// or None if it is the root node.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use trees::{RcNode, tr};
+    ///
+    /// let root = RcNode::from( tr(0) /tr(1) );
+    /// assert!( root.parent_weak().is_none() );
+    ///
+    /// let parent = root.front().unwrap().parent_weak().unwrap();
+    /// assert_eq!( parent.upgrade(), Some( root ));
+    /// ```
+    pub fn parent_weak( &self ) -> Option<WeakNode<T>> {
+        self.parent().map( |parent| parent.downgrade() )
+    }
+
     /// Converts to a tree which disables reference-counting.
     ///
     /// # Panics
@@ -542,7 +583,9 @@ impl<T> WeakNode<T> {
                 if node.count.get() == 0 {
                     None
                 } else {
+                    // the strong pointer releases both in `RcNode::drop()`
                     node.count.incr();
+                    node_vec.as_ref().ref_cnt.incr();
                     Some( RcNode::Piled( PiledRcNode( *node_vec, *index )))
                 }
             },
@@ -585,6 +628,28 @@ mod tests {
         assert_eq!( *rc_1.data(), 4 );
         assert_eq!( *rc_2.data(), 5 );
     }
+
+    #[test]
+    fn weak_links_work_for_piled_nodes() {
+        use super::super::{RcNode, Tree};
+
+        let root = RcNode::from( Tree::<i32>::from_tuple(( 0, (1, 2), 3 )));
+        let children = root.children_weak();
+        let grand_child = root.front().unwrap().front().unwrap();
+        let parent = grand_child.parent_weak().unwrap();
+
+        for _ in 0..3 {
+            assert_eq!( *parent.upgrade().unwrap().data(), 1 );
+            drop( root.front().unwrap().downgrade() );
+        }
+        assert_eq!( children.iter().map( |child| *child.upgrade().unwrap().data() ).collect::<Vec<_>>(), vec![ 1, 3 ]);
+
+        drop( root.pop_back() );
+        assert!( children[1].upgrade().is_none() );
+        drop( root );
+        assert_eq!( *grand_child.data(), 2 );
+        drop( children );
+    }
 }
 
 #[cfg( miri )]
@@ -0,0 +1,64 @@
+//! Thread local counters of node allocations, drops and traversal steps, compiled in with the `stats` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use trees::{stats, tr};
+//!
+//! stats::reset();
+//! let tree = tr(0) /tr(1) /tr(2);
+//! assert_eq!( tree.iter().count(), 2 );
+//! drop( tree );
+//!
+//! let stats = stats::snapshot();
+//! assert_eq!( stats.allocations, 3 );
+//! assert_eq!( stats.drops, 3 );
+//! assert_eq!( stats.traversal_steps, 2 );
+//! ```
+
+use std::cell::Cell;
+
+thread_local! {
+    static ALLOCATIONS     : Cell<u64> = const { Cell::new( 0 ) };
+    static DROPS           : Cell<u64> = const { Cell::new( 0 ) };
+    static TRAVERSAL_STEPS : Cell<u64> = const { Cell::new( 0 ) };
+}
+
+/// Counters of the calling thread at some point, see `snapshot()`.
+#[derive( Copy, Clone, Debug, Default, Eq, PartialEq )]
+pub struct Stats {
+    /// Nodes allocated, one by one for scattered trees or all at once for piled ones.
+    pub allocations     : u64,
+    /// Nodes dropped.
+    pub drops           : u64,
+    /// Steps taken by child iterators and depth first walks.
+    pub traversal_steps : u64,
+}
+
+/// Returns the counters of the calling thread.
+pub fn snapshot() -> Stats {
+    Stats {
+        allocations     : ALLOCATIONS.with( Cell::get ),
+        drops           : DROPS.with( Cell::get ),
+        traversal_steps : TRAVERSAL_STEPS.with( Cell::get ),
+    }
+}
+
+/// Sets the counters of the calling thread back to zero.
+pub fn reset() {
+    ALLOCATIONS.with( |cnt| cnt.set( 0 ));
+    DROPS.with( |cnt| cnt.set( 0 ));
+    TRAVERSAL_STEPS.with( |cnt| cnt.set( 0 ));
+}
+
+pub(crate) fn count_allocations( nodes: usize ) {
+    ALLOCATIONS.with( |cnt| cnt.set( cnt.get().wrapping_add( nodes as u64 )));
+}
+
+pub(crate) fn count_drop() {
+    DROPS.with( |cnt| cnt.set( cnt.get().wrapping_add( 1 )));
+}
+
+pub(crate) fn count_traversal_step() {
+    TRAVERSAL_STEPS.with( |cnt| cnt.set( cnt.get().wrapping_add( 1 )));
+}
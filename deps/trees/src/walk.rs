@@ -118,6 +118,7 @@ impl<T> Walk<T> {
 
     /// Advances the cursor in the traversal.
     fn forward( &mut self ) {
+        #[cfg( feature = "stats" )] crate::stats::count_traversal_step();
         loop {
             match self.direction {
                 Direction::Up => {
@@ -446,6 +447,47 @@ impl<T> From<TreeWalk<T>> for Tree<T> {
     }
 }
 
+/// Depth first search on a borrowed `Node` and its descendants, yielding every visit from the node's own `Begin`/`Leaf` on.
+pub struct NodeWalk<'a, T> {
+    walk    : Walk<T>,
+    started : bool,
+    mark    : PhantomData<&'a Node<T>>,
+}
+
+impl<'a, T:'a> Iterator for NodeWalk<'a,T> {
+    type Item = Visit<'a,T>;
+
+    fn next( &mut self ) -> Option<Visit<'a,T>> {
+        if self.started {
+            self.walk.forward();
+        } else {
+            self.started = true;
+        }
+        self.walk.get().map( |visit| unsafe { match visit {
+            Visit::Begin( node ) => Visit::Begin( &*( node as *const Node<T> )),
+            Visit::End  ( node ) => Visit::End  ( &*( node as *const Node<T> )),
+            Visit::Leaf ( node ) => Visit::Leaf ( &*( node as *const Node<T> )),
+        }})
+    }
+}
+
+impl<'a, T> From<&'a Node<T>> for NodeWalk<'a,T> {
+    /// # Examples
+    ///
+    /// ```
+    /// use trees::{tr, walk::{NodeWalk, Visit}};
+    /// let tree = tr(0) /( tr(1)/tr(2) ) /tr(3);
+    /// let sub = tree.root().iter().next().unwrap();
+    /// let visits = NodeWalk::from( sub ).collect::<Vec<_>>();
+    /// assert_eq!( visits, vec![ Visit::Begin( sub ), Visit::Leaf( tr(2).root() ), Visit::End( sub ) ]);
+    /// ```
+    fn from( node: &'a Node<T> ) -> Self {
+        let mut walk = Walk::<T>::default();
+        walk.on_node( Some( node.non_null() ));
+        NodeWalk{ walk, started: false, mark: PhantomData }
+    }
+}
+
 /// Depth first search in forest.
 #[derive( Default )]
 pub struct ForestWalk<T> {
@@ -1,13 +1,13 @@
-#[macro_use]
 extern crate redis_module;
 
 use redis_module::native_types::RedisType;
-use redis_module::{raw, Context, NextArg, RedisResult, RedisValue, RedisString, REDIS_OK};
+use redis_module::key::RedisKeyWritable;
+use redis_module::{raw, Context, RedisError, RedisResult, RedisValue, RedisString, REDIS_OK};
 use redis_module::logging::{log as redis_log};
 use redis_module::LogLevel;
-use std::os::raw::{c_void, c_int, c_char};
+use std::os::raw::{c_void, c_int, c_char, c_long, c_longlong, c_uint};
 use std::ptr;
-use std::ffi::{CStr, CString};
+use std::ffi::CString;
 use trees::*;
 use std::convert::TryFrom;
 
@@ -39,6 +39,13 @@ impl From<&str> for Error {
     }
 }
 
+// tree string errors come with the offending token and where it is
+impl From<trees::Error> for Error {
+    fn from(e: trees::Error) -> Self {
+        Error { msg: e.to_string() }
+    }
+}
+
 impl From<Error> for redis_module::RedisError {
     fn from(e: Error) -> Self {
         redis_module::RedisError::String(e.msg)
@@ -46,267 +53,5794 @@ impl From<Error> for redis_module::RedisError {
 }
 
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::Peekable;
+use std::pin::Pin;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// what a node holds: the value commands address it by and the attributes set on it,
+// kept in the node so that they move, copy and go away together with it
+#[derive(Debug, Clone)]
+struct NodeData {
+    // any bytes, UTF-8 or not
+    value: Vec<u8>,
+    // most nodes carry none and pay a pointer for it rather than an empty map,
+    // sorted so replies and saves are deterministic
+    #[allow(clippy::box_collection)]
+    attributes: Option<Box<BTreeMap<String, String>>>,
+    // of the edge from the parent, set with tree.set_weight and always finite
+    weight: Option<f64>,
+}
+
+impl NodeData {
+    fn new(value: Vec<u8>) -> Self {
+        NodeData { value, attributes: None, weight: None }
+    }
+
+    fn attribute(&self, field: &str) -> Option<&String> {
+        self.attributes.as_ref()?.get(field)
+    }
+
+    fn attributes(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.attributes.iter().flat_map(|attributes| attributes.iter())
+    }
+
+    // true when the field is new
+    fn set_attribute(&mut self, field: String, value: String) -> bool {
+        self.attributes.get_or_insert_with(Default::default).insert(field, value).is_none()
+    }
+
+    // the root a forest keeps its roots under, no node value is ever empty
+    fn is_hidden_root(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    // true when the field was there
+    fn remove_attribute(&mut self, field: &str) -> bool {
+        let attributes = match self.attributes.as_mut() {
+            Some(attributes) => attributes,
+            None => return false,
+        };
+        let removed = attributes.remove(field).is_some();
+        if attributes.is_empty() {
+            self.attributes = None;
+        }
+        removed
+    }
+}
+
+// for messages and DOT labels, a value that is not UTF-8 shows the way the tree string quotes it
+impl fmt::Display for NodeData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&display_value(&self.value))
+    }
+}
+
+// a value as errors and logs show it, in tree string quotes when it is not UTF-8
+fn display_value(value: &[u8]) -> String {
+    String::from_utf8(value.to_vec()).unwrap_or_else(|_| quote(value))
+}
+
+// tree.hash and tree.equals cover what the tree string shows, attributes left out
+impl Hash for NodeData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state)
+    }
+}
+
+impl PartialEq for NodeData {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl From<&NodeData> for Reply {
+    fn from(data: &NodeData) -> Self {
+        Reply::Bytes(data.value.clone())
+    }
+}
+
+impl JsonData for NodeData {
+    fn json_value(&self) -> &[u8] {
+        &self.value
+    }
+
+    fn json_attributes(&self) -> Vec<(&str, &str)> {
+        self.attributes().map(|(field, value)| (field.as_str(), value.as_str())).collect()
+    }
+
+    fn json_weight(&self) -> Option<f64> {
+        self.weight
+    }
+
+    fn from_json_parts(value: Vec<u8>, attributes: Vec<(String, String)>) -> Result<Self, trees::Error> {
+        let mut data = NodeData::new(value);
+        for (field, value) in attributes {
+            data.set_attribute(field, value);
+        }
+        Ok(data)
+    }
+
+    fn with_json_weight(self, weight: f64) -> Result<Self, trees::Error> {
+        Ok(NodeData { weight: Some(weight), ..self })
+    }
+}
+
+// the nodes of a parsed tree string, none of them has attributes yet
+fn node_tree(tree: Tree<Vec<u8>>) -> Tree<NodeData> {
+    Tree::from(tree.into_bfs().map(NodeData::new))
+}
+
+// child indices from the root down to `node`, which sort the way preorder visits the nodes
+fn preorder_position(mut node: &Node<NodeData>) -> Vec<usize> {
+    let mut position = Vec::new();
+    while let Some(index) = node.child_index() {
+        position.push(index);
+        node = node.parent().unwrap();
+    }
+    position.reverse();
+    position
+}
+
+// the parent of a node, none for the roots of a forest as for the root of a tree
+fn node_parent(node: &Node<NodeData>) -> Option<&Node<NodeData>> {
+    node.parent().filter(|parent| !parent.data().is_hidden_root())
+}
+
+// parent first, the root the node is under last
+fn node_ancestors(node: &Node<NodeData>) -> Vec<&Node<NodeData>> {
+    std::iter::successors(node_parent(node), |node| node_parent(node)).collect()
+}
+
+// node counts of the levels from the node down, from the roots down for the hidden root of a forest
+fn level_widths(node: &Node<NodeData>) -> Vec<usize> {
+    let mut widths = node.level_widths();
+    if node.data().is_hidden_root() {
+        widths.remove(0);
+    }
+    widths
+}
+
+// edges between the node and the root it is under
+fn node_depth(node: &Node<NodeData>) -> usize {
+    node_ancestors(node).len()
+}
+
+// the depth a new child of `parent` gets, a new root for the hidden root of a forest
+fn child_depth(parent: &Node<NodeData>) -> usize {
+    if parent.data().is_hidden_root() { 0 } else { node_depth(parent) + 1 }
+}
+
+// (root-to-node path, node) for the node and every node below it in preorder, as join_path has them,
+// without the hidden root of a forest
+fn node_paths<'a>(node: &'a Node<NodeData>, separator: &'a str) -> impl Iterator<Item = (Vec<u8>, &'a Node<NodeData>)> {
+    // nodes still to visit with their paths, children pushed last to first
+    let mut stack = vec![(join_path(node, separator), node)];
+    std::iter::from_fn(move || loop {
+        let (path, node) = stack.pop()?;
+        for child in node.iter().collect::<Vec<_>>().into_iter().rev() {
+            let mut child_path = path.clone();
+            if !node.data().is_hidden_root() {
+                child_path.extend_from_slice(separator.as_bytes());
+            }
+            push_path_segment(&mut child_path, &child.data().value, separator);
+            stack.push((child_path, child));
+        }
+        if !node.data().is_hidden_root() {
+            return Some((path, node))
+        }
+    })
+}
+
+// the tree string of the node, a forest string of its roots side by side for the hidden root of a forest
+fn tree_string(node: &Node<NodeData>) -> String {
+    tree_string_chunk(node, 0, usize::MAX).0
+}
+
+// the JSON tree of the node, an array of the JSON trees of its roots for the hidden root of a forest
+fn json_string(node: &Node<NodeData>) -> String {
+    if node.data().is_hidden_root() {
+        return format!("[{}]", node.iter().map(|root| root.to_json()).collect::<Vec<_>>().join(","))
+    }
+    node.to_json()
+}
+
+// A tree.init given FOREST holds several roots under one key. They are the children of a hidden root
+// valued "", which no command ever shows, finds or counts: paths and depths start at the roots, which
+// have no father, and what the whole key replies goes over the roots side by side.
+#[derive(Debug)]
+struct RedisTreeType {
+    // a forest has the hidden root at the top
+    data: Tree<NodeData>,
+    // compare node values ignoring case when locating nodes
+    case_insensitive: bool,
+    // no two nodes are located by the same value, writes adding one fail instead
+    unique: bool,
+    // every node under the value it is located by, lowercased on case-insensitive trees.
+    // Writes keep it in step with data: index_subtree what they insert, unindex_subtree what
+    // they take out, and relabel rather than assigning a value. Nodes never move once
+    // allocated, so the pointers stay good for as long as their node is in the tree.
+    index: HashMap<Vec<u8>, Vec<NonNull<Node<NodeData>>>>,
+    // deadlines of the subtrees tree.expire_node was given, kept in step by unindex_node
+    expires: Expires,
+    // counts the changes since the key was created, every write adds one next to replicating itself.
+    // Starts at 1, IFVERSION 0 stands for no tree at all
+    version: u64,
+    // where tree.notify has the changes published
+    notify: Option<NotifyTarget>,
+    // how far active defrag got with the tree
+    defrag: Defrag,
+}
+
+// a tree active defrag is done with stays done until a write, which changes the version
+#[derive(Debug)]
+enum Defrag {
+    Fragmented,
+    // a compacting copy part way through, taken up again while the tree is still at the version
+    Copying(u64, CloneState<NodeData>),
+    // moved into fresh allocations at the version
+    Compacted(u64),
+}
+
+impl RedisTreeType {
+    fn new(data: Tree<NodeData>, case_insensitive: bool) -> Self {
+        let mut value = RedisTreeType {
+            data, case_insensitive, unique: false, index: HashMap::new(), expires: Expires::default(), version: 1, notify: None,
+            defrag: Defrag::Fragmented,
+        };
+        value.index_subtree(NonNull::from(value.data.root()));
+        value
+    }
+
+    fn index_key(&self, node_data: &[u8]) -> Vec<u8> {
+        if self.case_insensitive { lowercase(node_data) } else { node_data.to_vec() }
+    }
+
+    // adds the nodes of a subtree now in the tree, `subtree` may be taken before inserting it
+    fn index_subtree(&mut self, subtree: NonNull<Node<NodeData>>) {
+        for node in unsafe { subtree.as_ref() }.locate_all_by(|data| !data.is_hidden_root()) {
+            let key = self.index_key(&node.data().value);
+            self.index.entry(key).or_default().push(NonNull::from(node));
+        }
+    }
+
+    // drops the nodes of a subtree taken out of the tree, before it is dropped itself
+    fn unindex_subtree(&mut self, subtree: &Node<NodeData>) {
+        for node in subtree.locate_all_by(|_| true) {
+            self.unindex_node(node);
+        }
+    }
+
+    // drops a node taken out of the tree, deadline and all
+    fn unindex_node(&mut self, node: &Node<NodeData>) {
+        self.expires.remove(NonNull::from(node));
+        self.unindex_value(node);
+    }
+
+    // drops the index entry of a node under its current value, it stays in the tree otherwise
+    fn unindex_value(&mut self, node: &Node<NodeData>) {
+        let key = self.index_key(&node.data().value);
+        if let Some(nodes) = self.index.get_mut(&key) {
+            nodes.retain(|indexed| !ptr::eq(indexed.as_ptr(), node));
+            if nodes.is_empty() {
+                self.index.remove(&key);
+            }
+        }
+    }
+
+    // gives a node of the tree a new value, moving it in the index, its deadline stays
+    fn relabel(&mut self, node: NonNull<Node<NodeData>>, new_data: Vec<u8>) {
+        let node = unsafe { &mut *node.as_ptr() };
+        self.unindex_value(node);
+        node.data_mut().value = new_data;
+        let key = self.index_key(&node.data().value);
+        self.index.entry(key).or_default().push(NonNull::from(&*node));
+    }
+
+    // the nodes located by node_data, in no particular order
+    fn indexed(&self, node_data: &[u8]) -> &[NonNull<Node<NodeData>>] {
+        self.index.get(&self.index_key(node_data)).map_or(&[], Vec::as_slice)
+    }
+
+    // a value located by more than one node, which a UNIQUE tree never has
+    fn duplicate(&self) -> Option<&[u8]> {
+        let nodes = self.index.values().find(|nodes| nodes.len() > 1)?;
+        Some(&unsafe { nodes[0].as_ref() }.data().value)
+    }
+
+    // on UNIQUE trees, fails unless the values to be added are neither in the tree nor repeated
+    fn check_unique<'v>(&self, values: impl IntoIterator<Item = &'v [u8]>) -> Result<(), RedisError> {
+        if !self.unique {
+            return Ok(())
+        }
+        let mut seen = HashSet::new();
+        for node_data in values {
+            let key = self.index_key(node_data);
+            if self.index.contains_key(&key) || !seen.insert(key) {
+                return Err(reply::duplicate(node_data))
+            }
+        }
+        Ok(())
+    }
+
+    // check_unique for renaming the node located by old_data, new_data may differ from it in case only
+    fn check_unique_rename(&self, old_data: &[u8], new_data: &[u8]) -> Result<(), RedisError> {
+        if self.index_key(old_data) == self.index_key(new_data) {
+            return Ok(())
+        }
+        self.check_unique(std::iter::once(new_data))
+    }
+
+    // bytes held by the nodes, their strings and the index, going by capacities and leaving
+    // out allocator overhead and the spare room of the maps
+    fn memory_usage(&self) -> usize {
+        use std::mem::size_of;
+        let strings = |s: &String| s.capacity();
+        let mut bytes = size_of::<Self>();
+        for node in self.data.root().locate_all_by(|_| true) {
+            let data = node.data();
+            bytes += size_of::<Node<NodeData>>() + data.value.capacity();
+            if let Some(attributes) = &data.attributes {
+                bytes += size_of::<BTreeMap<String, String>>();
+                bytes += attributes.iter().map(|(field, value)| size_of::<(String, String)>() + strings(field) + strings(value)).sum::<usize>();
+            }
+        }
+        for (key, nodes) in &self.index {
+            bytes += size_of::<(Vec<u8>, Vec<NonNull<Node<NodeData>>>)>() + key.capacity();
+            bytes += nodes.capacity() * size_of::<NonNull<Node<NodeData>>>();
+        }
+        // each deadline is held twice, by node and in the order they come due
+        bytes += self.expires.len() * 2 * size_of::<(NonNull<Node<NodeData>>, i64)>();
+        bytes
+    }
+
+    // the flags rdb_save writes after the tree
+    fn flags(&self) -> u64 {
+        let mut flags = 0;
+        if self.case_insensitive {
+            flags |= FLAG_CASE_INSENSITIVE;
+        }
+        if self.unique {
+            flags |= FLAG_UNIQUE;
+        }
+        if self.forest() {
+            flags |= FLAG_FOREST;
+        }
+        flags
+    }
+
+    fn forest(&self) -> bool {
+        self.data.root().data().is_hidden_root()
+    }
+
+    // the nodes in the key, the hidden root of a forest left out
+    fn node_count(&self) -> usize {
+        self.data.root().node_count() - self.forest() as usize
+    }
+
+    // edges on the longest path down from a root
+    fn height(&self) -> usize {
+        self.data.root().height() - self.forest() as usize
+    }
+
+    // the first in preorder of the nodes located by node_data
+    fn locate_ptr(&self, node_data: &[u8]) -> Option<NonNull<Node<NodeData>>> {
+        match self.indexed(node_data) {
+            [] => None,
+            [node] => Some(*node),
+            nodes => nodes.iter().copied().min_by_key(|node| preorder_position(unsafe { node.as_ref() })),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        tree_string(self.data.root())
+    }
+
+    // a copy sharing no node with self, deadlines included
+    fn deep_clone(&self) -> Self {
+        self.with_data(self.data.root().deep_clone())
+    }
+
+    // the same tree over `data`, a copy of its nodes in the same order
+    fn with_data(&self, data: Tree<NodeData>) -> Self {
+        let mut value = RedisTreeType::new(data, self.case_insensitive);
+        value.unique = self.unique;
+        value.version = self.version;
+        value.notify = self.notify.clone();
+        value.set_expires_in_preorder(self.expires_in_preorder());
+        value
+    }
+
+    // [(position in preorder, deadline)] of the nodes with one, which unlike pointers survive a copy or a save
+    fn expires_in_preorder(&self) -> Vec<(usize, i64)> {
+        if self.expires.is_empty() {
+            return Vec::new()
+        }
+        self.data.root().locate_all_by(|_| true).enumerate()
+            .filter_map(|(position, node)| Some((position, self.expires.get(NonNull::from(node))?)))
+            .collect()
+    }
+
+    // the deadlines in preorder with the argument tree.expire_node names their node by: the path
+    // where it leads there, every node on it being the first of its value among its siblings, else
+    // the value where that finds it. A node neither names is logged and left out
+    fn named_deadlines(&self) -> Vec<(Vec<u8>, bool, String)> {
+        let mut deadlines = Vec::new();
+        if self.expires.is_empty() {
+            return deadlines
+        }
+        // nodes to visit with whether their path leads to them, children pushed last to first
+        let root = self.data.root();
+        let mut stack = vec![(root, true)];
+        while let Some((node, by_path)) = stack.pop() {
+            if let Some(at) = self.expires.get(NonNull::from(node)) {
+                if by_path {
+                    deadlines.push((join_path(node, PATH_SEPARATOR), true, at.to_string()));
+                } else if self.locate(&node.data().value).is_some_and(|n| ptr::eq(n, node)) {
+                    deadlines.push((node.data().value.clone(), false, at.to_string()));
+                } else {
+                    log(&format!("aof_rewrite: no way to name node {}, its deadline is left out", display_value(&join_path(node, PATH_SEPARATOR))));
+                }
+            }
+            let mut seen = HashSet::new();
+            let children = node.iter().map(|child| {
+                let first = seen.insert(self.index_key(&child.data().value));
+                (child, by_path && first)
+            }).collect::<Vec<_>>();
+            stack.extend(children.into_iter().rev());
+        }
+        deadlines
+    }
+
+    fn set_expires_in_preorder(&mut self, expires: Vec<(usize, i64)>) {
+        if expires.is_empty() {
+            return
+        }
+        let nodes = self.data.root().locate_all_by(|_| true).map(NonNull::from).collect::<Vec<_>>();
+        for (position, at) in expires {
+            if let Some(&node) = nodes.get(position) {
+                self.expires.set(node, at);
+            }
+        }
+    }
+
+    // takes out the subtrees due at `at`, earliest first, and replies how many nodes went. A root due
+    // stays, the caller deletes the key instead, and the second half of the reply tells it to
+    fn purge_expired(&mut self, at: i64) -> (usize, bool) {
+        let mut removed = 0;
+        while let Some(node) = self.expires.due(at) {
+            let mut node = unsafe { Pin::new_unchecked(&mut *node.as_ptr()) };
+            if node.parent().is_none() {
+                return (removed + node.node_count(), true)
+            }
+            let sub_tree = node.detach();
+            removed += sub_tree.root().node_count();
+            self.unindex_subtree(sub_tree.root());
+            // and so does the last root of a forest
+            if self.forest() && self.data.root().has_no_child() {
+                return (removed, true)
+            }
+            self.version += 1;
+        }
+        (removed, false)
+    }
+
+    // takes over from the tree it replaces at a key what belongs to the key: the version, counted
+    // on, and the notify target
+    fn succeed(&mut self, previous: Option<&mut RedisTreeType>) {
+        if let Some(previous) = previous {
+            self.version = previous.version + 1;
+            self.notify = previous.notify.take();
+        }
+    }
+
+    fn matcher(&self, node_data: &[u8]) -> impl Fn(&NodeData) -> bool {
+        let case_insensitive = self.case_insensitive;
+        let expected = self.index_key(node_data);
+        // the hidden root of a forest matches no value
+        move |v: &NodeData| {
+            if v.is_hidden_root() {
+                false
+            } else if case_insensitive {
+                lowercase(&v.value) == expected
+            } else {
+                v.value == expected
+            }
+        }
+    }
+
+    fn locate(&self, node_data: &[u8]) -> Option<&Node<NodeData>> {
+        self.locate_ptr(node_data).map(|node| unsafe { &*node.as_ptr() })
+    }
+
+    // node addressed by a root-to-node path joined with PATH_SEPARATOR, as WITHPATH prints it.
+    // On a forest it starts at one of the roots below the hidden one
+    fn locate_by_path(&self, path: &[u8]) -> Option<&Node<NodeData>> {
+        let segments = split_path(path);
+        let mut segments = segments.iter();
+        let mut node = self.data.root();
+        if !self.forest() && !self.matcher(segments.next()?)(node.data()) {
+            return None
+        }
+        for segment in segments {
+            let matches = self.matcher(segment);
+            node = node.iter().find(|child| matches(child.data()))?;
+        }
+        Some(node)
+    }
+
+    // whether some root-to-node path reads `path`, trying every sibling with a matching value
+    // the way locate_first_by_path does, rather than the first one only, through the matcher
+    // so that CASEINSENSITIVE trees match as well
+    fn contains_path(&self, path: &[u8]) -> bool {
+        let matchers = split_path(path).iter().map(|segment| self.matcher(segment)).collect::<Vec<_>>();
+        let root = self.data.root();
+        let start = match self.forest() {
+            true => 0,
+            false if matchers[0](root.data()) => 1,
+            false => return false,
+        };
+        let mut pending = vec![(root, start)];
+        while let Some((node, depth)) = pending.pop() {
+            if depth == matchers.len() {
+                return true
+            }
+            pending.extend(node.iter().filter(|child| matchers[depth](child.data())).map(|child| (child, depth + 1)));
+        }
+        false
+    }
+
+    fn locate_by_path_mut(&mut self, path: &[u8]) -> Option<Pin<&mut Node<NodeData>>> {
+        let indices = preorder_position(self.locate_by_path(path)?);
+        let mut node = Pin::into_inner(self.data.root_mut());
+        for index in indices {
+            node = unsafe { Pin::get_unchecked_mut(node.iter_mut().nth(index)?) };
+        }
+        Some(unsafe { Pin::new_unchecked(node) })
+    }
+
+    // commands which recreate the subtree under `node` as the tree stored at `key`
+    fn recreate_commands(&self, key: &str, node: &Node<NodeData>) -> Vec<Vec<String>> {
+        let mut init = vec!["tree.init".to_string(), key.to_string()];
+        init.extend(self.init_args(node));
+        vec![init]
+    }
+
+    // what follows the key in a tree.init recreating the subtree under `node`,
+    // the tree string unless some node carries attributes or a weight, which only JSON holds.
+    // The hidden root of a forest recreates the forest. A lone node valued like an option of
+    // tree.init is quoted, FROMPATHS would otherwise take the arguments after it for paths
+    fn init_args(&self, node: &Node<NodeData>) -> Vec<String> {
+        let with_json = node.locate_all_by(|v| v.attributes.is_some() || v.weight.is_some()).next().is_some();
+        let mut args = if with_json {
+            vec![json_string(node), "FORMAT".to_string(), "JSON".to_string()]
+        } else {
+            let tree_value = tree_string(node);
+            match INIT_OPTIONS.contains(&tree_value.to_uppercase().as_str()) {
+                true => vec![format!("\"{}\"", tree_value)],
+                false => vec![tree_value],
+            }
+        };
+        if self.case_insensitive {
+            args.push("CASEINSENSITIVE".to_string());
+        }
+        if self.unique {
+            args.push("UNIQUE".to_string());
+        }
+        if node.data().is_hidden_root() {
+            args.push("FOREST".to_string());
+        }
+        args
+    }
+
+    fn locate_mut(&mut self, node_data: &[u8]) -> Option<Pin<&mut Node<NodeData>>> {
+        self.locate_ptr(node_data).map(|node| unsafe { Pin::new_unchecked(&mut *node.as_ptr()) })
+    }
+
+    // the node a command names, replying the missing node error when it is not there
+    fn node(&self, node_data: &[u8]) -> Result<&Node<NodeData>, RedisError> {
+        self.locate(node_data).ok_or_else(|| reply::no_node(node_data))
+    }
+
+    fn node_mut(&mut self, node_data: &[u8]) -> Result<Pin<&mut Node<NodeData>>, RedisError> {
+        self.locate_mut(node_data).ok_or_else(|| reply::no_node(node_data))
+    }
+
+    // for commands whose node argument is optional and defaults to the root
+    fn node_or_root(&self, node_data: Option<&[u8]>) -> Result<&Node<NodeData>, RedisError> {
+        match node_data {
+            Some(node_data) => self.node(node_data),
+            None => Ok(self.data.root()),
+        }
+    }
+
+    fn node_at_path(&self, path: &[u8]) -> Result<&Node<NodeData>, RedisError> {
+        self.locate_by_path(path).ok_or_else(|| reply::no_node_at_path(path))
+    }
+
+    fn node_at_path_mut(&mut self, path: &[u8]) -> Result<Pin<&mut Node<NodeData>>, RedisError> {
+        self.locate_by_path_mut(path).ok_or_else(|| reply::no_node_at_path(path))
+    }
+}
+
+
+// =================================================================================================
+// REPLICATION
+// =================================================================================================
+// Module commands reach replicas and the AOF only when they ask to. Every write asks with
+// ctx.replicate_verbatim() once it changed the tree, and not when it failed or found no key.
+// Replaying the command is safe because a write depends on nothing but the tree it runs on:
+// nodes named by value are the first match in preorder, the case and unique flags are stored
+// with the tree, and no write picks at random. Replicas hold the same tree, so
+// they pick the same nodes. A write that ever breaks this has to replicate its effect instead, with
+// raw::replicate and node paths (tree.apply_diff) rather than the command it was given.
+// tree.expire_node is the one write reading the clock, it replicates the deadline it computed.
+
+// raw::replicate for arguments of any bytes, such as node values, which it would cut at a NUL
+fn replicate_bytes(ctx: &Context, command: &str, args: &[&[u8]]) {
+    let raw_ctx = raw_context(ctx);
+    let (command, format) = (CString::new(command).unwrap(), CString::new("v").unwrap());
+    unsafe {
+        let strings = args.iter()
+            .map(|arg| raw::RedisModule_CreateString.unwrap()(raw_ctx, arg.as_ptr() as *const c_char, arg.len()))
+            .collect::<Vec<_>>();
+        raw::RedisModule_Replicate.unwrap()(raw_ctx, command.as_ptr(), format.as_ptr(), strings.as_ptr(), strings.len());
+        strings.into_iter().for_each(|string| raw::RedisModule_FreeString.unwrap()(raw_ctx, string));
+    }
+}
+
+
+// =================================================================================================
+// NODE EXPIRY
+// =================================================================================================
+// tree.expire_node gives a subtree a deadline in unix time milliseconds. Once it passed, the master
+// takes the subtree out before any command gets to the tree, and a timer walking the keyspace takes
+// out those no command gets to. Either way the removal goes to replicas and the AOF as
+// tree.purge_expired with the time it happened at, so they remove the very same subtrees.
+// Replicas and loads never remove a subtree on their own, they wait for the master's purge.
+const REDISMODULE_CTX_FLAGS_MASTER: c_int = 1 << 2;
+const REDISMODULE_CTX_FLAGS_REPLICATED: c_int = 1 << 12;
+const REDISMODULE_CTX_FLAGS_LOADING: c_int = 1 << 13;
+
+// how often the timer runs and how many keys it looks at each time
+const EXPIRE_CYCLE_PERIOD: Duration = Duration::from_millis(100);
+const EXPIRE_CYCLE_KEYS: usize = 1000;
+
+// trees with a deadline set, the timer has nothing to look for while there are none.
+// Atomic as lazy free drops trees on a background thread
+static TREES_WITH_EXPIRES: AtomicUsize = AtomicUsize::new(0);
+
+// the deadlines of a tree by node and in the order they come due
+#[derive(Debug, Default)]
+struct Expires {
+    deadlines: HashMap<NonNull<Node<NodeData>>, i64>,
+    due: BTreeSet<(i64, NonNull<Node<NodeData>>)>,
+}
+
+impl Expires {
+    fn set(&mut self, node: NonNull<Node<NodeData>>, at: i64) {
+        if self.deadlines.is_empty() {
+            TREES_WITH_EXPIRES.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(previous) = self.deadlines.insert(node, at) {
+            self.due.remove(&(previous, node));
+        }
+        self.due.insert((at, node));
+    }
+
+    fn remove(&mut self, node: NonNull<Node<NodeData>>) -> Option<i64> {
+        let at = self.deadlines.remove(&node)?;
+        self.due.remove(&(at, node));
+        if self.deadlines.is_empty() {
+            TREES_WITH_EXPIRES.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(at)
+    }
+
+    fn get(&self, node: NonNull<Node<NodeData>>) -> Option<i64> {
+        self.deadlines.get(&node).copied()
+    }
+
+    // the earliest node with a deadline no later than `at`
+    fn due(&self, at: i64) -> Option<NonNull<Node<NodeData>>> {
+        self.due.first().filter(|(deadline, _)| *deadline <= at).map(|(_, node)| *node)
+    }
+
+    fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+}
+
+impl Drop for Expires {
+    fn drop(&mut self) {
+        if !self.deadlines.is_empty() {
+            TREES_WITH_EXPIRES.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    unsafe { raw::RedisModule_Milliseconds.unwrap()() }
+}
+
+fn context_flags(ctx: &Context) -> c_int {
+    unsafe { raw::RedisModule_GetContextFlags.unwrap()(raw_context(ctx)) }
+}
+
+// a replica applying what its master sent, or a load replaying the AOF
+fn replaying(ctx: &Context) -> bool {
+    context_flags(ctx) & (REDISMODULE_CTX_FLAGS_REPLICATED | REDISMODULE_CTX_FLAGS_LOADING) != 0
+}
+
+// a master running a command of its own clients, or its timer
+fn may_expire(ctx: &Context) -> bool {
+    context_flags(ctx) & REDISMODULE_CTX_FLAGS_MASTER != 0 && !replaying(ctx)
+}
+
+// the argument positions of the keys of a command, as its first, last and step key say
+fn key_positions(args: &[Arg], first: i32, last: i32, step: i32) -> impl Iterator<Item = usize> {
+    let last = if last < 0 { args.len() as i32 + last } else { last.min(args.len() as i32 - 1) };
+    (first.max(1)..=last).step_by(step.max(1) as usize).map(|position| position as usize)
+}
+
+// removes what is due at `at` from the tree at key and replicates that, replies how many nodes went.
+// Opened for reading first, as opening a key for writing counts as changing it for WATCH
+fn expire_key(ctx: &Context, key_name: &str, at: i64) -> Result<usize, RedisError> {
+    let due = match ctx.open_key(key_name).get_value::<RedisTreeType>(&TREE_TYPE) {
+        Ok(Some(value)) => value.expires.due(at).is_some(),
+        _ => false,
+    };
+    if !due {
+        return Ok(0)
+    }
+    purge_due(ctx, &ctx.open_key_writable(key_name), key_name, at)
+}
+
+// expire_key on a key already open for writing, which must hold a tree
+fn purge_due(ctx: &Context, key: &RedisKeyWritable, key_name: &str, at: i64) -> Result<usize, RedisError> {
+    let value = key.get_value::<RedisTreeType>(&TREE_TYPE)?.unwrap();
+    let notify = value.notify.clone();
+    let (removed, root) = value.purge_expired(at);
+    let version = if root { 0 } else { value.version };
+    if root {
+        key.delete()?;
+    }
+    let at = at.to_string();
+    raw::replicate(raw_context(ctx), "tree.purge_expired", &[key_name, &at]);
+    if let Some(target) = notify {
+        target.send(ctx, &notify_message("tree.purge_expired", key_name, version, &[Arg(at.into_bytes())]));
+    }
+    Ok(removed)
+}
+
+// runs ahead of every command on the keys at its key positions, so no command finds a subtree past
+// its deadline. tree.purge_expired is left out, it does the same and replies what it did
+fn expire_keys(ctx: &Context, name: &str, args: &[Arg], first: i32, last: i32, step: i32) {
+    if first <= 0 || name == "tree.purge_expired" || TREES_WITH_EXPIRES.load(Ordering::Relaxed) == 0 || !may_expire(ctx) {
+        return
+    }
+    let now = now_ms();
+    for position in key_positions(args, first, last, step) {
+        if let Ok(key_name) = key_name(args[position].clone()) {
+            let _ = expire_key(ctx, &key_name, now);
+        }
+    }
+}
+
+// where the timer left off, the database and the RM_Scan cursor in it
+struct ExpireCycle {
+    db: c_int,
+    cursor: *mut c_void,
+}
+
+static mut EXPIRE_CYCLE: ExpireCycle = ExpireCycle { db: 0, cursor: ptr::null_mut() };
+
+struct ExpireScan {
+    at: i64,
+    seen: usize,
+    due: Vec<String>,
+}
+
+// notes the trees with something due, they are expired once the scan step is over as RM_Scan
+// must not see keys change under it
+extern "C" fn note_due_tree(_ctx: *mut raw::RedisModuleCtx, key_name: *mut raw::RedisModuleString, key: *mut raw::RedisModuleKey, privdata: *mut c_void) {
+    let scan = unsafe { &mut *(privdata as *mut ExpireScan) };
+    scan.seen += 1;
+    if key.is_null() || unsafe { raw::RedisModule_ModuleTypeGetType.unwrap()(key) } != *TREE_TYPE.raw_type.borrow() {
+        return
+    }
+    let value = unsafe { &*(raw::RedisModule_ModuleTypeGetValue.unwrap()(key) as *const RedisTreeType) };
+    if value.expires.due(scan.at).is_some() {
+        if let Ok(key_name) = RedisString::from_ptr(key_name) {
+            scan.due.push(key_name.to_string());
+        }
+    }
+}
+
+// walks EXPIRE_CYCLE_KEYS keys on from where it left off, database after database, takes out what
+// is due in the trees among them and sets itself up to run again. Idle walk cursors go here too
+fn expire_cycle(ctx: &Context) {
+    expire_walk_cursors(now_ms());
+    if capabilities().scan && TREES_WITH_EXPIRES.load(Ordering::Relaxed) > 0 && may_expire(ctx) {
+        type ScanCursorCreate = unsafe extern "C" fn() -> *mut c_void;
+        type ScanCursorRestart = unsafe extern "C" fn(*mut c_void);
+        type ScanCallback = extern "C" fn(*mut raw::RedisModuleCtx, *mut raw::RedisModuleString, *mut raw::RedisModuleKey, *mut c_void);
+        type Scan = unsafe extern "C" fn(*mut raw::RedisModuleCtx, *mut c_void, ScanCallback, *mut c_void) -> c_int;
+        let mut scan = ExpireScan { at: now_ms(), seen: 0, due: Vec::new() };
+        unsafe {
+            let cycle = &mut *ptr::addr_of_mut!(EXPIRE_CYCLE);
+            let restart = std::mem::transmute::<*mut c_void, ScanCursorRestart>(api_function("ScanCursorRestart").unwrap());
+            let run_scan = std::mem::transmute::<*mut c_void, Scan>(api_function("Scan").unwrap());
+            if cycle.cursor.is_null() {
+                cycle.cursor = std::mem::transmute::<*mut c_void, ScanCursorCreate>(api_function("ScanCursorCreate").unwrap())();
+            }
+            // past the last database SelectDb fails, and the walk starts over
+            if raw::RedisModule_SelectDb.unwrap()(raw_context(ctx), cycle.db) != raw::Status::Ok as c_int {
+                cycle.db = 0;
+                restart(cycle.cursor);
+                raw::RedisModule_SelectDb.unwrap()(raw_context(ctx), 0);
+            }
+            while scan.seen < EXPIRE_CYCLE_KEYS {
+                if run_scan(raw_context(ctx), cycle.cursor, note_due_tree, &mut scan as *mut ExpireScan as *mut c_void) == 0 {
+                    cycle.db += 1;
+                    restart(cycle.cursor);
+                    break
+                }
+            }
+        }
+        for key_name in &scan.due {
+            let _ = expire_key(ctx, key_name, scan.at);
+        }
+    }
+    start_expire_cycle(raw_context(ctx));
+}
+
+unsafe extern "C" fn run_expire_cycle(ctx: *mut raw::RedisModuleCtx, _data: *mut c_void) {
+    with_context(ctx, expire_cycle);
+}
+
+fn start_expire_cycle(ctx: *mut raw::RedisModuleCtx) {
+    unsafe {
+        raw::RedisModule_CreateTimer.unwrap()(ctx, EXPIRE_CYCLE_PERIOD.as_millis() as raw::mstime_t, Some(run_expire_cycle), ptr::null_mut());
+    }
+}
+
+
+// =================================================================================================
+// VERSIONS
+// =================================================================================================
+// Every tree counts its changes, tree.version hands the count out and a write given IFVERSION n only
+// goes ahead while the tree it writes to, dst_key for graft and copy_subtree, is still at version n,
+// so an editor saves what it read without WATCH. Writes take it with their options, or ahead of the
+// list for those taking fields, labels, edges or ops, so no list item is ever read as it. The count
+// is kept by the writes themselves and saved in the RDB and in AOF rewrites, replicas and reloads end
+// up with the same one. They do not check IFVERSION again, the master did.
+// the version of what get_value found at a key, 0 for no tree there
+fn version_of<V: std::ops::Deref<Target = RedisTreeType>>(value: Result<Option<V>, RedisError>) -> u64 {
+    value.ok().flatten().map_or(0, |value| value.version)
+}
+
+// the n of IFVERSION n
+fn next_if_version(args: &mut impl Iterator<Item = Arg>) -> Result<u64, RedisError> {
+    args.next_string()?.parse::<u64>().map_err(|_| Error::from("IFVERSION takes a version number").into())
+}
+
+// an IFVERSION n ahead of the list of a write
+fn leading_if_version<I: Iterator<Item = Arg>>(args: &mut Peekable<I>) -> Result<Option<u64>, RedisError> {
+    if !args.peek().is_some_and(|arg| arg.eq_ignore_ascii_case("IFVERSION")) {
+        return Ok(None)
+    }
+    args.next();
+    next_if_version(args).map(Some)
+}
+
+// the IFVERSION n a write without other options may end with, and nothing after it
+fn trailing_if_version(args: &mut impl Iterator<Item = Arg>) -> Result<Option<u64>, RedisError> {
+    let if_version = match args.next() {
+        Some(arg) if arg.eq_ignore_ascii_case("IFVERSION") => Some(next_if_version(args)?),
+        Some(_) => return Err(RedisError::WrongArity),
+        None => None,
+    };
+    args.done()?;
+    Ok(if_version)
+}
+
+// fails a write given IFVERSION when what get_value found at its key is at any other version
+fn check_if_version<V: std::ops::Deref<Target = RedisTreeType>>(ctx: &Context, value: Result<Option<V>, RedisError>, expected: Option<u64>) -> Result<(), RedisError> {
+    let version = version_of(value);
+    match expected {
+        Some(expected) if expected != version && !replaying(ctx) => {
+            Err(Error::from(format!("version mismatch, the tree is at version {}", version)).into())
+        },
+        _ => Ok(()),
+    }
+}
+
+
+// =================================================================================================
+// NOTIFICATIONS
+// =================================================================================================
+// tree.notify has a tree publish every change to it as one JSON object, to a channel or as the
+// event field of a stream entry, for consumers mirroring the tree as it changes:
+//   {"op":"set_subtree","key":"hello","version":5,"parent":"a","subtree":"b( c )","args":["FRONT"]}
+// op is the write with the tree. prefix dropped, version the one it left the tree at, 0 once the key
+// is gone, then the arguments after the key under the names notify_fields gives them and the rest
+// in args. Expired subtrees come as purge_expired with the time in args.
+// Masters publish after the write went through. Stream entries are replicated with the id they got,
+// replicas and loads publish nothing of their own.
+#[derive(Debug, Clone, PartialEq)]
+enum NotifyTarget {
+    Channel(String),
+    Stream(String),
+}
+
+impl NotifyTarget {
+    fn send(&self, ctx: &Context, message: &str) {
+        let sent = match self {
+            NotifyTarget::Channel(channel) => ctx.call("PUBLISH", &[channel, message]).map(|_| ()),
+            NotifyTarget::Stream(stream) => ctx.call("XADD", &[stream, "*", "event", message]).map(|id| {
+                if let RedisValue::SimpleString(id) = id {
+                    raw::replicate(raw_context(ctx), "XADD", &[stream, &id, "event", message]);
+                }
+            }),
+        };
+        if let Err(e) = sent {
+            log(&format!("notify {:?} failed: {}", self, e));
+        }
+    }
+
+    // the kind and name tree.notify takes and replies
+    fn describe(&self) -> (&str, &str) {
+        match self {
+            NotifyTarget::Channel(channel) => ("CHANNEL", channel),
+            NotifyTarget::Stream(stream) => ("STREAM", stream),
+        }
+    }
+}
+
+// names for the arguments following the key in the messages of a write
+fn notify_fields(op: &str) -> &'static [&'static str] {
+    match op {
+        "tree.init" | "tree.getset" => &["tree"],
+        "tree.fromedges" => &["root"],
+        "tree.del_subtree" | "tree.del_children" | "tree.collapse_node" | "tree.sort_children" | "tree.setattr" | "tree.delattr"
+            | "tree.expire_node" | "tree.persist_node" => &["node"],
+        "tree.prune" => &["pattern"],
+        "tree.set_subtree" => &["parent", "subtree"],
+        "tree.append_leaves" | "tree.add_path" => &["path"],
+        "tree.graft" => &["parent", "src_key"],
+        "tree.copy_subtree" => &["node", "dst_key", "dst_parent"],
+        "tree.restorenode" => &["parent", "dump"],
+        "tree.wrap_node" => &["node", "new_parent"],
+        "tree.move_child" => &["parent", "child"],
+        "tree.set_weight" => &["parent", "child", "weight"],
+        "tree.node_hmset_bulk" => &["patches"],
+        "tree.rename_node" => &["node", "new_value"],
+        _ => &[],
+    }
+}
+
+// arguments outside UTF-8 go as JSON can carry them, with U+FFFD for the bytes it can not
+fn notify_message(op: &str, key_name: &str, version: u64, args: &[Arg]) -> String {
+    let mut message = String::from("{\"op\":");
+    push_json_string(&mut message, op.trim_start_matches("tree."));
+    message.push_str(",\"key\":");
+    push_json_string(&mut message, key_name);
+    message.push_str(&format!(",\"version\":{}", version));
+    let fields = notify_fields(op);
+    for (field, arg) in fields.iter().zip(args) {
+        message.push_str(&format!(",\"{}\":", field));
+        push_json_string(&mut message, &String::from_utf8_lossy(arg));
+    }
+    if args.len() > fields.len() {
+        message.push_str(",\"args\":[");
+        for (i, arg) in args[fields.len()..].iter().enumerate() {
+            if i > 0 {
+                message.push(',');
+            }
+            push_json_string(&mut message, &String::from_utf8_lossy(arg));
+        }
+        message.push(']');
+    }
+    message.push('}');
+    message
+}
+
+// the trees with a target at the keys of a write, taken before it runs as it may delete them
+struct Notification {
+    op: &'static str,
+    args: Vec<Arg>,
+    // key position, target and version before the write
+    watched: Vec<(usize, NotifyTarget, u64)>,
+}
+
+impl Notification {
+    fn before(ctx: &Context, op: &'static str, flags: &str, args: &[Arg], first: i32, last: i32, step: i32) -> Option<Self> {
+        if first <= 0 || !flags.split(' ').any(|flag| flag == "write") || replaying(ctx) {
+            return None
+        }
+        let watched = key_positions(args, first, last, step).filter_map(|position| {
+            let key = ctx.open_key(&key_name(args[position].clone()).ok()?);
+            let value = key.get_value::<RedisTreeType>(&TREE_TYPE).ok()??;
+            Some((position, value.notify.clone()?, value.version))
+        }).collect::<Vec<_>>();
+        if watched.is_empty() {
+            return None
+        }
+        Some(Notification { op, args: args.to_vec(), watched })
+    }
+
+    // a message to every tree the write changed, which the version tells
+    fn after(self, ctx: &Context) {
+        for (position, target, before) in &self.watched {
+            let key_name = key_name(self.args[*position].clone()).unwrap();
+            let version = version_of(ctx.open_key(&key_name).get_value::<RedisTreeType>(&TREE_TYPE));
+            if version != *before {
+                target.send(ctx, &notify_message(self.op, &key_name, version, &self.args[2..]));
+            }
+        }
+    }
+}
+
+
+// =================================================================================================
+// WALK CURSORS
+// =================================================================================================
+// tree.walk_start hands out a cursor on a depth first walk of a subtree, the walk TreeWalk takes:
+// a node is visited once as a leaf, or at its begin and again at its end after its children.
+// The cursor holds child indices from the root and the version of the tree, never pointers, so any
+// change to the tree leaves it stale rather than dangling. Cursors belong to the client that
+// started them and go after WALK_CURSOR_IDLE without use, the timer sweeps them.
+// The commands moving a cursor take no key, so cluster routing and ACL key patterns never see the
+// tree they read: every move checks the key against the user the client runs as by then.
+const WALK_CURSOR_IDLE: i64 = 5 * 60 * 1000;
+const MAX_WALK_CURSORS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WalkVisit {
+    Begin,
+    End,
+    Leaf,
+}
+
+impl WalkVisit {
+    // where a walk lands on a node coming down to it
+    fn arriving(node: &Node<NodeData>) -> Self {
+        if node.has_no_child() { WalkVisit::Leaf } else { WalkVisit::Begin }
+    }
+}
+
+struct WalkCursor {
+    client: u64,
+    db: c_int,
+    key: String,
+    version: u64,
+    // child indices from the root to the node the walk started at, and to the node under visit
+    origin: Vec<usize>,
+    path: Vec<usize>,
+    visit: WalkVisit,
+    last_used: i64,
+}
+
+// what a move does to a cursor
+enum WalkStep {
+    To(Vec<usize>, WalkVisit),
+    Stay,
+    Done,
+}
+
+// only ever touched on the main thread, by commands and the timer
+static mut WALK_CURSORS: BTreeMap<u64, WalkCursor> = BTreeMap::new();
+static mut NEXT_WALK_CURSOR: u64 = 1;
+
+fn walk_cursors() -> &'static mut BTreeMap<u64, WalkCursor> {
+    unsafe { &mut *ptr::addr_of_mut!(WALK_CURSORS) }
+}
+
+fn expire_walk_cursors(now: i64) {
+    walk_cursors().retain(|_, cursor| now - cursor.last_used < WALK_CURSOR_IDLE);
+}
+
+fn node_at_indices<'a>(root: &'a Node<NodeData>, indices: &[usize]) -> Option<&'a Node<NodeData>> {
+    indices.iter().try_fold(root, |node, &index| node.iter().nth(index))
+}
+
+fn client_id(ctx: &Context) -> u64 {
+    unsafe { raw::RedisModule_GetClientId.unwrap()(raw_context(ctx)) }
+}
+
+fn selected_db(ctx: &Context) -> c_int {
+    unsafe { raw::RedisModule_GetSelectedDb.unwrap()(raw_context(ctx)) }
+}
+
+// REDISMODULE_CMD_KEY_ACCESS, the key is read
+const REDISMODULE_CMD_KEY_ACCESS: c_int = 1 << 4;
+
+// whether the user of the calling client may read the key. Servers before 7.0 have no api to ask,
+// there the cursor being of this client is all there is to check
+fn may_read_key(ctx: &Context, key_name: &str) -> bool {
+    type GetCurrentUserName = unsafe extern "C" fn(*mut raw::RedisModuleCtx) -> *mut raw::RedisModuleString;
+    type GetModuleUserFromUserName = unsafe extern "C" fn(*mut raw::RedisModuleString) -> *mut c_void;
+    type ACLCheckKeyPermissions = unsafe extern "C" fn(*mut c_void, *mut raw::RedisModuleString, c_int) -> c_int;
+    type FreeModuleUser = unsafe extern "C" fn(*mut c_void) -> c_int;
+    unsafe {
+        let apis = (api_function("GetCurrentUserName"), api_function("GetModuleUserFromUserName"),
+                    api_function("ACLCheckKeyPermissions"), api_function("FreeModuleUser"));
+        let (get_name, get_user, check, free_user) = match apis {
+            (Some(get_name), Some(get_user), Some(check), Some(free_user)) => (
+                std::mem::transmute::<*mut c_void, GetCurrentUserName>(get_name),
+                std::mem::transmute::<*mut c_void, GetModuleUserFromUserName>(get_user),
+                std::mem::transmute::<*mut c_void, ACLCheckKeyPermissions>(check),
+                std::mem::transmute::<*mut c_void, FreeModuleUser>(free_user),
+            ),
+            _ => return true,
+        };
+        let raw_ctx = raw_context(ctx);
+        let name = get_name(raw_ctx);
+        // no client behind the call, the server runs it on its own behalf
+        if name.is_null() {
+            return true
+        }
+        let user = get_user(name);
+        raw::RedisModule_FreeString.unwrap()(raw_ctx, name);
+        if user.is_null() {
+            return false
+        }
+        let key = raw::RedisModule_CreateString.unwrap()(raw_ctx, key_name.as_ptr() as *const c_char, key_name.len());
+        let allowed = check(user, key, REDISMODULE_CMD_KEY_ACCESS) == raw::Status::Ok as c_int;
+        raw::RedisModule_FreeString.unwrap()(raw_ctx, key);
+        free_user(user);
+        allowed
+    }
+}
+
+// {event, value, depth below where the walk started, children} of the visit the cursor is at
+fn walk_visit_reply(cursor: &WalkCursor, node: &Node<NodeData>) -> Reply {
+    let event = match cursor.visit {
+        WalkVisit::Begin => "begin",
+        WalkVisit::End => "end",
+        WalkVisit::Leaf => "leaf",
+    };
+    Reply::Map(vec![
+        ("event".to_string(), RedisValue::from(event).into()),
+        ("value".to_string(), node.data().into()),
+        ("depth".to_string(), reply::integer(cursor.path.len() - cursor.origin.len()).into()),
+        ("children".to_string(), reply::integer(node.degree()).into()),
+    ])
+}
+
+// moves the cursor of this client named by `id` and replies the visit it lands on, nil when it can
+// not move or walked off the end, which ends the cursor too
+fn walk_move(ctx: &Context, id: u64, step: impl FnOnce(&WalkCursor, &Node<NodeData>) -> WalkStep) -> RedisResult {
+    let cursors = walk_cursors();
+    // another client's cursor is as unknown as one never handed out
+    let cursor = cursors.get_mut(&id).filter(|cursor| cursor.client == client_id(ctx))
+        .ok_or_else(|| Error::from(format!("unknown walk cursor {}", id)))?;
+    if cursor.db != selected_db(ctx) {
+        return Err(Error::from(format!("walk cursor {} walks a tree of db {}", id, cursor.db)).into())
+    }
+    if !may_read_key(ctx, &cursor.key) {
+        return Err(Error::from(format!("no permission to read the tree walk cursor {} walks", id)).into())
+    }
+    let key = ctx.open_key(&cursor.key);
+    let root = match key.get_value::<RedisTreeType>(&TREE_TYPE) {
+        Ok(Some(value)) if value.version == cursor.version => value.data.root(),
+        _ => {
+            cursors.remove(&id);
+            return Err(Error::from(format!("walk cursor {} is stale, its tree changed", id)).into())
+        },
+    };
+    cursor.last_used = now_ms();
+    match step(cursor, node_at_indices(root, &cursor.path).unwrap()) {
+        WalkStep::To(path, visit) => {
+            cursor.path = path;
+            cursor.visit = visit;
+            walk_visit_reply(cursor, node_at_indices(root, &cursor.path).unwrap()).send(ctx)
+        },
+        WalkStep::Stay => Ok(RedisValue::Null),
+        WalkStep::Done => {
+            cursors.remove(&id);
+            Ok(RedisValue::Null)
+        },
+    }
+}
+
+
+// =================================================================================================
+// ARGUMENTS
+// =================================================================================================
+// commands get their arguments as the bytes the client sent. Node values and paths are taken as
+// they are, NUL and bytes outside UTF-8 included, anything else has to be UTF-8 and key names free of
+// NUL, which redis-module can not open a key by
+#[derive(Debug, Clone, PartialEq)]
+struct Arg(Vec<u8>);
+
+impl Arg {
+    fn to_uppercase(&self) -> String {
+        String::from_utf8_lossy(&self.0).to_uppercase()
+    }
+
+    fn eq_ignore_ascii_case(&self, other: &str) -> bool {
+        self.0.eq_ignore_ascii_case(other.as_bytes())
+    }
+
+    fn into_string(self) -> Result<String, RedisError> {
+        String::from_utf8(self.0).map_err(|e| Error::from(format!("argument {} is not UTF-8", quote(e.as_bytes()))).into())
+    }
+}
+
+impl std::ops::Deref for Arg {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&display_value(&self.0))
+    }
+}
+
+// NextArg of redis-module over Arg
+trait NextArg: Iterator<Item = Arg> {
+    fn next_arg(&mut self) -> Result<Arg, RedisError> {
+        self.next().ok_or(RedisError::WrongArity)
+    }
+
+    fn next_bytes(&mut self) -> Result<Vec<u8>, RedisError> {
+        Ok(self.next_arg()?.0)
+    }
+
+    fn next_string(&mut self) -> Result<String, RedisError> {
+        self.next_arg()?.into_string()
+    }
+
+    fn next_key(&mut self) -> Result<String, RedisError> {
+        key_name(self.next_arg()?)
+    }
+
+    fn next_i64(&mut self) -> Result<i64, RedisError> {
+        self.next_string()?.parse::<i64>().map_err(|_| RedisError::Str("Couldn't parse as integer"))
+    }
+
+    fn next_u64(&mut self) -> Result<u64, RedisError> {
+        self.next_string()?.parse::<u64>().map_err(|_| RedisError::Str("Couldn't parse as unsigned integer"))
+    }
+
+    fn done(&mut self) -> Result<(), RedisError> {
+        self.next().map_or(Ok(()), |_| Err(RedisError::WrongArity))
+    }
+}
+
+impl<T: Iterator<Item = Arg>> NextArg for T {}
+
+fn key_name(arg: Arg) -> Result<String, RedisError> {
+    if arg.contains(&0) {
+        return Err(Error::from("key names can not contain NUL").into())
+    }
+    arg.into_string()
+}
+
+
+// =================================================================================================
+// REPLIES
+// =================================================================================================
+// every handler replies through these, which keeps the commands uniform:
+//   a key that does not exist replies nil,
+//   a node named by the command that is not in the tree is an error,
+//   a result with nothing in it is an empty array.
+// The predicates tree.exists_node, tree.contains_path, tree.is_ancestor and tree.equals answer 0 for missing keys and nodes
+// alike, and asking a node for something it lacks, like the father of the root, replies nil.
+mod reply {
+    use super::{display_value, Error};
+    use redis_module::{RedisError, RedisResult, RedisValue};
+
+    pub fn no_key() -> RedisResult {
+        Ok(RedisValue::Null)
+    }
+
+    pub fn no_node(node_data: &[u8]) -> RedisError {
+        Error::from(format!("no node {}", display_value(node_data))).into()
+    }
+
+    pub fn no_node_at_path(path: &[u8]) -> RedisError {
+        Error::from(format!("no node at path {}", display_value(path))).into()
+    }
+
+    pub fn duplicate(node_data: &[u8]) -> RedisError {
+        Error::from(format!("duplicate node value {}", display_value(node_data))).into()
+    }
+
+    pub fn array<V: Into<RedisValue>>(items: impl IntoIterator<Item = V>) -> RedisValue {
+        RedisValue::Array(items.into_iter().map(Into::into).collect())
+    }
+
+    pub fn integer(n: usize) -> RedisValue {
+        RedisValue::Integer(n as i64)
+    }
+}
+
+
+// =================================================================================================
+// RESP3
+// =================================================================================================
+// replies RedisValue has no room for: maps go to RESP3 clients as maps and to the others as flat
+// [field, value, ...] arrays, verbatim text as verbatim strings and to the others as bulk strings,
+// node values as the bytes they are. Every reply goes out through here, bulk strings as buffers,
+// as redis-module would cut them at a NUL
+enum Reply {
+    Value(RedisValue),
+    Bytes(Vec<u8>),
+    Array(Vec<Reply>),
+    Map(Vec<(String, Reply)>),
+    Verbatim(String),
+}
+
+impl From<RedisValue> for Reply {
+    fn from(value: RedisValue) -> Self {
+        Reply::Value(value)
+    }
+}
+
+impl From<Vec<u8>> for Reply {
+    fn from(bytes: Vec<u8>) -> Self {
+        Reply::Bytes(bytes)
+    }
+}
+
+// nil for none, like RedisValue
+impl<T: Into<Reply>> From<Option<T>> for Reply {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Reply::Value(RedisValue::Null), Into::into)
+    }
+}
+
+const REDISMODULE_CTX_FLAGS_RESP3: c_int = 1 << 22;
+
+thread_local! {
+    // the Contexts our entry points made and the raw contexts behind them, innermost last
+    static RAW_CONTEXTS: RefCell<Vec<(*const Context, *mut raw::RedisModuleCtx)>> = const { RefCell::new(Vec::new()) };
+}
+
+// every Context the module works with comes from here, in the commands and the expire timer, as
+// redis-module keeps the raw context of a Context to itself
+fn with_context<R>(raw_ctx: *mut raw::RedisModuleCtx, f: impl FnOnce(&Context) -> R) -> R {
+    let ctx = Context::new(raw_ctx);
+    RAW_CONTEXTS.with(|contexts| contexts.borrow_mut().push((&ctx as *const Context, raw_ctx)));
+    let result = f(&ctx);
+    RAW_CONTEXTS.with(|contexts| contexts.borrow_mut().pop());
+    result
+}
+
+fn raw_context(ctx: &Context) -> *mut raw::RedisModuleCtx {
+    RAW_CONTEXTS.with(|contexts| {
+        contexts.borrow().iter().rev()
+            .find(|(known, _)| ptr::eq(*known, ctx))
+            .map(|(_, raw_ctx)| *raw_ctx)
+            .expect("a Context made by with_context")
+    })
+}
+
+// the client speaks RESP3 and the server has RM_ReplyWithMap to answer it
+fn resp3(ctx: &Context) -> bool {
+    capabilities().resp3 && unsafe { raw::RedisModule_GetContextFlags.unwrap()(raw_context(ctx)) } & REDISMODULE_CTX_FLAGS_RESP3 != 0
+}
+
+impl Reply {
+    fn fields(pairs: Vec<(&str, RedisValue)>) -> Reply {
+        Reply::Map(pairs.into_iter().map(|(field, value)| (field.to_string(), Reply::Value(value))).collect())
+    }
+
+    fn array<V: Into<Reply>>(items: impl IntoIterator<Item = V>) -> Reply {
+        Reply::Array(items.into_iter().map(Into::into).collect())
+    }
+
+    fn send(self, ctx: &Context) -> RedisResult {
+        let reply_with_map = if resp3(ctx) {
+            type ReplyWithMap = unsafe extern "C" fn(*mut raw::RedisModuleCtx, c_long) -> c_int;
+            Some(unsafe { std::mem::transmute::<*mut c_void, ReplyWithMap>(api_function("ReplyWithMap").unwrap()) })
+        } else {
+            None
+        };
+        self.write(ctx, reply_with_map);
+        Ok(RedisValue::NoReply)
+    }
+
+    // maps without reply_with_map go out as flat arrays
+    fn write(self, ctx: &Context, reply_with_map: Option<unsafe extern "C" fn(*mut raw::RedisModuleCtx, c_long) -> c_int>) {
+        let raw_ctx = raw_context(ctx);
+        let buffer = |bytes: &[u8]| unsafe { raw::RedisModule_ReplyWithStringBuffer.unwrap()(raw_ctx, bytes.as_ptr() as *const c_char, bytes.len()) };
+        match self {
+            Reply::Value(RedisValue::BulkString(text)) => {
+                buffer(text.as_bytes());
+            },
+            Reply::Value(RedisValue::Array(items)) => Reply::array(items).write(ctx, reply_with_map),
+            Reply::Value(RedisValue::NoReply) => {},
+            Reply::Value(value) => {
+                ctx.reply(Ok(value));
+            },
+            Reply::Bytes(bytes) => {
+                buffer(&bytes);
+            },
+            Reply::Array(items) => {
+                unsafe { raw::RedisModule_ReplyWithArray.unwrap()(raw_ctx, items.len() as c_long) };
+                items.into_iter().for_each(|item| item.write(ctx, reply_with_map));
+            },
+            Reply::Map(pairs) => {
+                match reply_with_map {
+                    Some(reply_with_map) => unsafe { reply_with_map(raw_ctx, pairs.len() as c_long) },
+                    None => unsafe { raw::RedisModule_ReplyWithArray.unwrap()(raw_ctx, 2 * pairs.len() as c_long) },
+                };
+                for (field, value) in pairs {
+                    buffer(field.as_bytes());
+                    value.write(ctx, reply_with_map);
+                }
+            },
+            Reply::Verbatim(text) if reply_with_map.is_none() => {
+                buffer(text.as_bytes());
+            },
+            Reply::Verbatim(text) => {
+                unsafe { raw::RedisModule_ReplyWithVerbatimString.unwrap()(raw_ctx, text.as_ptr() as *const c_char, text.len()) };
+            },
+        }
+    }
+}
+
+// the value, attributes and children of every node as a map, the way FORMAT JSON nests them
+fn nested_map(node: &Node<NodeData>) -> Reply {
+    type Fields = Vec<(String, Reply)>;
+    // the fields and children of the nodes being visited, innermost last
+    let mut open: Vec<(Fields, Vec<Reply>)> = vec![(Vec::new(), Vec::new())];
+    for visit in NodeWalk::from(node) {
+        if let walk::Visit::Begin(node) | walk::Visit::Leaf(node) = visit {
+            let mut fields = vec![("value".to_string(), node.data().into())];
+            let attributes = node.data().attributes().map(|(field, value)| (field.clone(), Reply::Value(value.into()))).collect::<Vec<_>>();
+            if !attributes.is_empty() {
+                fields.push(("attributes".to_string(), Reply::Map(attributes)));
+            }
+            if let Some(weight) = node.data().weight {
+                fields.push(("weight".to_string(), Reply::Value(number_reply(weight))));
+            }
+            open.push((fields, Vec::new()));
+        }
+        if let walk::Visit::End(_) | walk::Visit::Leaf(_) = visit {
+            let (mut fields, children) = open.pop().unwrap();
+            fields.push(("children".to_string(), Reply::Array(children)));
+            open.last_mut().unwrap().1.push(Reply::Map(fields));
+        }
+    }
+    open.pop().unwrap().1.pop().unwrap()
+}
+
+
+const PATH_SEPARATOR: &str = "/";
+
+// entries handed out per call by cursor based commands when no COUNT is given
+const DEFAULT_PAGE_SIZE: usize = 10;
+
+// the values from the root the node is under down to the node, each with the separator escaped
+fn join_path(node: &Node<NodeData>, separator: &str) -> Vec<u8> {
+    let mut path = Vec::new();
+    for (i, node) in node_ancestors(node).into_iter().rev().chain(std::iter::once(node)).enumerate() {
+        if i > 0 {
+            path.extend_from_slice(separator.as_bytes());
+        }
+        push_path_segment(&mut path, &node.data().value, separator);
+    }
+    path
+}
+
+// a value as a path segment: a backslash before every separator in it, and before every backslash
+// that would otherwise be read as escaping one, which keeps paths of values free of both unchanged
+fn push_path_segment(path: &mut Vec<u8>, value: &[u8], separator: &str) {
+    let separator = separator.as_bytes();
+    let mut i = 0;
+    while i < value.len() {
+        if !separator.is_empty() && value[i..].starts_with(separator) {
+            path.push(b'\\');
+            path.extend_from_slice(separator);
+            i += separator.len();
+            continue
+        }
+        if value[i] == b'\\' {
+            let rest = &value[i + 1..];
+            if rest.is_empty() || rest[0] == b'\\' || (!separator.is_empty() && rest.starts_with(separator)) {
+                path.push(b'\\');
+            }
+        }
+        path.push(value[i]);
+        i += 1;
+    }
+}
+
+// the values of a path joined with PATH_SEPARATOR, \/ and \\ read as the / and \ they escape
+fn split_path(path: &[u8]) -> Vec<Vec<u8>> {
+    let mut segments = vec![Vec::new()];
+    let mut i = 0;
+    while i < path.len() {
+        match (path[i], path.get(i + 1)) {
+            (b'\\', Some(&escaped)) if escaped == b'\\' || escaped == b'/' => {
+                segments.last_mut().unwrap().push(escaped);
+                i += 2;
+            },
+            (b'/', _) => {
+                segments.push(Vec::new());
+                i += 1;
+            },
+            (byte, _) => {
+                segments.last_mut().unwrap().push(byte);
+                i += 1;
+            },
+        }
+    }
+    segments
+}
+
+// the value as trees ignoring case compare it, bytes outside UTF-8 left as they are
+fn lowercase(value: &[u8]) -> Vec<u8> {
+    let mut lowered = Vec::with_capacity(value.len());
+    for chunk in value.utf8_chunks() {
+        lowered.extend_from_slice(chunk.valid().to_lowercase().as_bytes());
+        lowered.extend_from_slice(chunk.invalid());
+    }
+    lowered
+}
+
+// every value but the empty one, which the hidden root of a forest has and a tree string has no
+// way to write
+fn check_node_data(node_data: &[u8]) -> Result<(), Error> {
+    if node_data.is_empty() {
+        return Err(Error::from("invalid node value"))
+    }
+    Ok(())
+}
+
+// a tree made from client input, every value held to what the commands allow
+fn check_tree_data(tree: &Tree<NodeData>) -> Result<(), Error> {
+    for node in tree.root().locate_all_by(|_| true) {
+        check_node_data(&node.data().value)?;
+    }
+    Ok(())
+}
+
+// JSON is text, values outside UTF-8 come in it as value_hex
+fn json_text(json: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(json).map_err(|_| Error::from("JSON tree is not UTF-8"))
+}
+
+// hashes as 16 hex digits, integers would come out signed
+fn format_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+// how a tree is read from and rendered to clients, picked with FORMAT. Every command replying a
+// tree renders it through here, so they all take the same formats
+enum Format {
+    String,
+    Json,
+    Nested,
+    Dot,
+    Weighted,
+}
+
+impl Format {
+    fn parse(format: &str) -> Result<Format, Error> {
+        match format.to_uppercase().as_str() {
+            "STRING" => Ok(Format::String),
+            "JSON" => Ok(Format::Json),
+            "NESTED" => Ok(Format::Nested),
+            "DOT" => Ok(Format::Dot),
+            "WEIGHTED" => Ok(Format::Weighted),
+            _ => Err(Error::from(format!("unknown format {}", format))),
+        }
+    }
+
+    fn parse_tree(&self, tree_value: &[u8]) -> Result<Tree<NodeData>, Error> {
+        match self {
+            Format::String => {
+                let tree = node_tree(Tree::try_from(tree_value)?);
+                check_tree_data(&tree)?;
+                Ok(tree)
+            },
+            Format::Json => {
+                let tree = Tree::<NodeData>::from_json(json_text(tree_value)?)?;
+                check_tree_data(&tree)?;
+                Ok(tree)
+            },
+            Format::Nested => Err(Error::from("FORMAT NESTED is only for replies")),
+            Format::Dot => Err(Error::from("FORMAT DOT is only for replies")),
+            Format::Weighted => Err(Error::from("FORMAT WEIGHTED is only for replies")),
+        }
+    }
+
+    // the roots of a forest side by side in the tree string, a JSON array of them in JSON,
+    // put under a hidden root
+    fn parse_forest(&self, forest_value: &[u8]) -> Result<Tree<NodeData>, Error> {
+        let mut roots = match self {
+            Format::String => {
+                let mut forest = Forest::<Vec<u8>>::try_from(forest_value)?;
+                let mut roots = Forest::new();
+                while let Some(root) = forest.pop_front() {
+                    roots.push_back(node_tree(root));
+                }
+                roots
+            },
+            Format::Json => Forest::<NodeData>::from_json(json_text(forest_value)?)?,
+            _ => return self.parse_tree(forest_value),
+        };
+        if roots.has_no_child() {
+            return Err(Error::from("a forest needs at least one root"))
+        }
+        let mut tree = Tree::new(NodeData::new(Vec::new()));
+        while let Some(root) = roots.pop_front() {
+            check_tree_data(&root)?;
+            tree.push_back(root);
+        }
+        Ok(tree)
+    }
+
+    // NESTED nests maps for RESP3 clients and [value, child, ...] arrays for the others.
+    // The hidden root of a forest renders as its roots, in an array for the formats which are not text
+    fn render(&self, ctx: &Context, node: &Node<NodeData>) -> Reply {
+        if node.data().is_hidden_root() {
+            return match self {
+                Format::String => RedisValue::from(tree_string(node)).into(),
+                Format::Json => RedisValue::from(json_string(node)).into(),
+                // the hidden root is n0, the roots keep their numbers
+                Format::Dot => {
+                    let dot = node.to_dot().lines().filter(|line| !line.starts_with("  n0 ")).collect::<Vec<_>>().join("\n");
+                    Reply::Verbatim(dot)
+                },
+                _ => Reply::Array(node.iter().map(|root| self.render(ctx, root)).collect()),
+            }
+        }
+        match self {
+            Format::String => RedisValue::from(tree_string(node)).into(),
+            Format::Json => RedisValue::from(node.to_json()).into(),
+            Format::Nested if resp3(ctx) => nested_map(node),
+            Format::Nested => nested_reply(node),
+            Format::Dot => Reply::Verbatim(node.to_dot()),
+            Format::Weighted => weighted_reply(node),
+        }
+    }
+}
+
+// builds the tree holding every root-to-node path given, paths sharing a prefix share its nodes,
+// which are told apart the way the index tells values apart. For a forest the paths may start
+// at different roots, which go under the hidden one
+fn tree_from_paths(paths: &[Vec<u8>], case_insensitive: bool, forest: bool) -> Result<Tree<NodeData>, Error> {
+    let key = |node_data: &[u8]| if case_insensitive { lowercase(node_data) } else { node_data.to_vec() };
+    if paths.is_empty() {
+        return Err(Error::from("FROMPATHS needs at least one path"))
+    }
+    let mut tree: Option<Tree<NodeData>> = forest.then(|| Tree::new(NodeData::new(Vec::new())));
+    // every node added so far by its path
+    let mut nodes = HashMap::new();
+    for path in paths {
+        let check_node_data = |node_data: &[u8]| check_node_data(node_data).map_err(|_| Error::from(format!("invalid path {}", display_value(path))));
+        let segments = split_path(path);
+        let mut segments = segments.iter();
+        let mut prefix = Vec::new();
+        if !forest {
+            let root_data = segments.next().unwrap();
+            check_node_data(root_data)?;
+            let tree = tree.get_or_insert_with(|| Tree::new(NodeData::new(root_data.clone())));
+            if key(&tree.root().data().value) != key(root_data) {
+                return Err(Error::from(format!("path {} does not start at the root {}", display_value(path), tree.root().data())))
+            }
+            prefix.push(key(root_data));
+        }
+
+        let mut parent = NonNull::from(tree.as_ref().unwrap().root());
+        for node_data in segments {
+            check_node_data(node_data)?;
+            prefix.push(key(node_data));
+            parent = *nodes.entry(prefix.clone()).or_insert_with(|| {
+                let child = Tree::new(NodeData::new(node_data.clone()));
+                let node = NonNull::from(child.root());
+                unsafe { &mut *parent.as_ptr() }.push_back(child);
+                node
+            });
+        }
+    }
+    Ok(tree.unwrap())
+}
+
+// [value, child, child, ..] with every child nested the same way, a leaf being [value]
+fn nested_reply(node: &Node<NodeData>) -> Reply {
+    // the arrays of the nodes being visited, innermost last
+    let mut open: Vec<Vec<Reply>> = vec![Vec::new()];
+    for visit in NodeWalk::from(node) {
+        match visit {
+            walk::Visit::Begin(node) => open.push(vec![node.data().into()]),
+            walk::Visit::Leaf(node) => open.last_mut().unwrap().push(Reply::Array(vec![node.data().into()])),
+            walk::Visit::End(_) => {
+                let array = open.pop().unwrap();
+                open.last_mut().unwrap().push(Reply::Array(array));
+            },
+        }
+    }
+    open.pop().unwrap().pop().unwrap()
+}
+
+// the tree string of `root` from the node `start` steps into a preorder walk on, `count` nodes of it
+// with the parens closing after the last one, and whether that was the end. Chunks which follow on
+// one another concatenate to the tree string; finding the start takes O(depth * degree), the rest
+// O(count). The hidden root of a forest is never written, its children are the roots side by side
+fn tree_string_chunk(root: &Node<NodeData>, start: usize, count: usize) -> (String, bool) {
+    let hidden = root.data().is_hidden_root();
+    // the start comes from the client, any past the end is the end
+    let mut n = match start.checked_add(hidden as usize) {
+        Some(n) if n < root.node_count() => n,
+        _ => return (String::new(), true),
+    };
+    // the children left to write at every level down to the node, outermost first
+    let mut pending: Vec<Iter<NodeData>> = Vec::new();
+    let mut node = root;
+    while n > 0 {
+        n -= 1;
+        let mut children = node.iter();
+        for child in children.by_ref() {
+            if n < child.node_count() {
+                node = child;
+                break
+            }
+            n -= child.node_count();
+        }
+        pending.push(children);
+    }
+
+    let mut chunk = String::new();
+    for _ in 0..count {
+        chunk.push_str(&quote(&node.data().value));
+        let mut children = node.iter();
+        if let Some(child) = children.next() {
+            chunk.push_str("( ");
+            node = child;
+            pending.push(children);
+            continue
+        }
+        loop {
+            let siblings = match pending.last_mut() {
+                Some(siblings) => siblings,
+                None if hidden => {
+                    chunk.truncate(chunk.len() - " )".len());
+                    return (chunk, true)
+                },
+                None => return (chunk, true),
+            };
+            chunk.push(' ');
+            if let Some(sibling) = siblings.next() {
+                node = sibling;
+                break
+            }
+            chunk.push(')');
+            pending.pop();
+        }
+    }
+    (chunk, false)
+}
+
+// weights and sums go out as bulk strings, RESP2 has no doubles
+fn number_reply(number: f64) -> RedisValue {
+    RedisValue::from(number.to_string())
+}
+
+// [value, weight, child, child, ..] like FORMAT NESTED with the weight of the edge from the parent
+// after the value, nil for edges without one and for the root of the tree
+fn weighted_reply(node: &Node<NodeData>) -> Reply {
+    let head = |node: &Node<NodeData>| vec![node.data().into(), node.data().weight.map_or(RedisValue::Null, number_reply).into()];
+    // the arrays of the nodes being visited, innermost last
+    let mut open: Vec<Vec<Reply>> = vec![Vec::new()];
+    for visit in NodeWalk::from(node) {
+        match visit {
+            walk::Visit::Begin(node) => open.push(head(node)),
+            walk::Visit::Leaf(node) => open.last_mut().unwrap().push(Reply::Array(head(node))),
+            walk::Visit::End(_) => {
+                let array = open.pop().unwrap();
+                open.last_mut().unwrap().push(Reply::Array(array));
+            },
+        }
+    }
+    open.pop().unwrap().pop().unwrap()
+}
+
+// [next_cursor, [items]] for the `count` items starting at `cursor`, the next cursor is 0 once exhausted
+fn cursor_page<I: Iterator<Item = Reply>>(items: I, cursor: usize, count: usize) -> Reply {
+    let mut page = items.skip(cursor).take(count.saturating_add(1)).collect::<Vec<_>>();
+    let next_cursor = if page.len() > count {
+        page.pop();
+        cursor.saturating_add(count)
+    } else {
+        0
+    };
+    Reply::Array(vec![RedisValue::from(next_cursor.to_string()).into(), Reply::Array(page)])
+}
+
+
+// =================================================================================================
+// CAPABILITIES
+// =================================================================================================
+// oldest server the module runs on, encoded like RM_GetServerVersion: 0x00MMmmpp
+const MIN_REDIS_VERSION: c_int = 0x00_06_00_00;
+
+// apis newer than the redismodule.h the module is built with, probed once on load
+#[derive(Debug, Clone, Copy)]
+struct Capabilities {
+    server_version: Option<c_int>,
+    resp3: bool,
+    keyspec: bool,
+    config: bool,
+    acl: bool,
+    acl_category: bool,
+    scan: bool,
+}
+
+static mut CAPABILITIES: Capabilities = Capabilities {
+    server_version: None,
+    resp3: false,
+    keyspec: false,
+    config: false,
+    acl: false,
+    acl_category: false,
+    scan: false,
+};
+
+fn capabilities() -> Capabilities {
+    unsafe { CAPABILITIES }
+}
+
+// looks the api up by name, servers leave it unset when they do not have it
+unsafe fn api_function(name: &str) -> Option<*mut c_void> {
+    let get_api = raw::RedisModule_GetApi?;
+    let name = CString::new(format!("RedisModule_{}", name)).ok()?;
+    let mut func: *mut c_void = ptr::null_mut();
+    let status = get_api(name.as_ptr(), &mut func as *mut *mut c_void as *mut c_void);
+    if status == raw::Status::Ok as c_int && !func.is_null() {
+        Some(func)
+    } else {
+        None
+    }
+}
+
+const ACL_CATEGORY: &str = "tree";
+
+// a category made by an earlier load of the module is still there, which is as good
+fn add_acl_category(ctx: *mut raw::RedisModuleCtx) {
+    type AddACLCategory = unsafe extern "C" fn(*mut raw::RedisModuleCtx, *const c_char) -> c_int;
+    let name = CString::new(ACL_CATEGORY).unwrap();
+    let status = unsafe {
+        let add = std::mem::transmute::<*mut c_void, AddACLCategory>(api_function("AddACLCategory").unwrap());
+        add(ctx, name.as_ptr())
+    };
+    if status != raw::Status::Ok as c_int {
+        log(&format!("ACL category @{} exists already", ACL_CATEGORY));
+    }
+}
+
+// @write for commands flagged write and @read for the others, plus @tree, on servers that take them
+fn set_acl_categories(ctx: *mut raw::RedisModuleCtx, name: &str, flags: &str) -> c_int {
+    let capabilities = capabilities();
+    if !capabilities.acl {
+        return raw::Status::Ok as c_int
+    }
+    let mut categories = vec![if flags.split(' ').any(|flag| flag == "write") { "write" } else { "read" }];
+    if capabilities.acl_category {
+        categories.push(ACL_CATEGORY);
+    }
+    type GetCommand = unsafe extern "C" fn(*mut raw::RedisModuleCtx, *const c_char) -> *mut c_void;
+    type SetCommandACLCategories = unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int;
+    let status = unsafe {
+        let get_command = std::mem::transmute::<*mut c_void, GetCommand>(api_function("GetCommand").unwrap());
+        let set_categories = std::mem::transmute::<*mut c_void, SetCommandACLCategories>(api_function("SetCommandACLCategories").unwrap());
+        let name = CString::new(name).unwrap();
+        let categories = CString::new(categories.join(" ")).unwrap();
+        set_categories(get_command(ctx, name.as_ptr()), categories.as_ptr())
+    };
+    if status != raw::Status::Ok as c_int {
+        log(&format!("could not set the ACL categories of {}", name));
+    }
+    status
+}
+
+fn format_version(version: c_int) -> String {
+    format!("{}.{}.{}", (version >> 16) & 0xff, (version >> 8) & 0xff, version & 0xff)
+}
+
+
+// =================================================================================================
+// LIMITS
+// =================================================================================================
+// caps on what the commands adding nodes may build, 0 leaves one off. They are module configs,
+// retree.max-nodes and so on, which redis 6 lacks, so the load arguments can set them as well
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_nodes: i64,
+    max_depth: i64,
+    max_value_length: i64,
+}
+
+static mut LIMITS: Limits = Limits {
+    max_nodes: 0,
+    max_depth: 0,
+    max_value_length: 0,
+};
+
+fn limits() -> Limits {
+    unsafe { LIMITS }
+}
+
+impl Limits {
+    // a tree of `nodes` nodes whose deepest one is `depth` edges below the root, taking the new `values`
+    fn check<'a>(&self, nodes: usize, depth: usize, values: impl IntoIterator<Item = &'a [u8]>) -> Result<(), Error> {
+        let over = |limit: i64, n: usize| limit > 0 && n as u64 > limit as u64;
+        if over(self.max_nodes, nodes) {
+            return Err(Error::from(format!("tree would exceed max-nodes {}", self.max_nodes)))
+        }
+        if over(self.max_depth, depth) {
+            return Err(Error::from(format!("tree would exceed max-depth {}", self.max_depth)))
+        }
+        if let Some(value) = values.into_iter().find(|value| over(self.max_value_length, value.len())) {
+            return Err(Error::from(format!("value of {} bytes exceeds max-value-length {}", value.len(), self.max_value_length)))
+        }
+        Ok(())
+    }
+
+    // the hidden root of a forest counts as neither a node nor a level
+    fn check_tree(&self, tree: &Tree<NodeData>) -> Result<(), Error> {
+        let root = tree.root();
+        let hidden = root.data().is_hidden_root() as usize;
+        self.check(root.node_count() - hidden, root.height() - hidden, root.locate_all_by(|_| true).map(|node| node.data().value.as_slice()))
+    }
+
+    // a tree of `nodes` nodes taking `sub_tree` as a new child of `parent`, or its roots as new
+    // children when it is a forest
+    fn check_subtree(&self, nodes: usize, parent: &Node<NodeData>, sub_tree: &Node<NodeData>) -> Result<(), Error> {
+        let hidden = sub_tree.data().is_hidden_root() as usize;
+        let values = sub_tree.locate_all_by(|_| true).map(|node| node.data().value.as_slice());
+        self.check(nodes + sub_tree.node_count() - hidden, child_depth(parent) + sub_tree.height() - hidden, values)
+    }
+}
+
+// `loadmodule libretree.so max-nodes 1000000 max-depth 128`, a bad one failing the load
+fn load_limits(args: &[String]) -> Result<(), Error> {
+    for pair in args.chunks(2) {
+        let (name, limit) = match pair {
+            [name, limit] => (name, limit),
+            _ => return Err(Error::from("load arguments come as limit value pairs")),
+        };
+        let limit = limit.parse::<i64>().ok().filter(|limit| *limit >= 0)
+            .ok_or_else(|| Error::from(format!("invalid {} {}", name, limit)))?;
+        unsafe {
+            match name.to_lowercase().as_str() {
+                "max-nodes" => LIMITS.max_nodes = limit,
+                "max-depth" => LIMITS.max_depth = limit,
+                "max-value-length" => LIMITS.max_value_length = limit,
+                _ => return Err(Error::from(format!("unknown load argument {}", name))),
+            }
+        }
+    }
+    Ok(())
+}
+
+// each config reads and writes the field of LIMITS its privdata points at
+extern "C" fn get_limit(_name: *const c_char, privdata: *mut c_void) -> c_longlong {
+    unsafe { *(privdata as *const i64) }
+}
+
+extern "C" fn set_limit(_name: *const c_char, value: c_longlong, privdata: *mut c_void, _err: *mut *mut raw::RedisModuleString) -> c_int {
+    unsafe { *(privdata as *mut i64) = value };
+    raw::Status::Ok as c_int
+}
+
+// registers the limits and loads what redis.conf or MODULE LOADEX set them to, redis 7 and newer only
+fn register_limits(ctx: *mut raw::RedisModuleCtx) -> c_int {
+    type GetNumeric = extern "C" fn(*const c_char, *mut c_void) -> c_longlong;
+    type SetNumeric = extern "C" fn(*const c_char, c_longlong, *mut c_void, *mut *mut raw::RedisModuleString) -> c_int;
+    type RegisterNumericConfig = unsafe extern "C" fn(*mut raw::RedisModuleCtx, *const c_char, c_longlong, c_uint, c_longlong, c_longlong,
+        GetNumeric, SetNumeric, *const c_void, *mut c_void) -> c_int;
+    type LoadConfigs = unsafe extern "C" fn(*mut raw::RedisModuleCtx) -> c_int;
+
+    unsafe {
+        let (register, load) = match (api_function("RegisterNumericConfig"), api_function("LoadConfigs")) {
+            (Some(register), Some(load)) => (std::mem::transmute::<*mut c_void, RegisterNumericConfig>(register), std::mem::transmute::<*mut c_void, LoadConfigs>(load)),
+            _ => return raw::Status::Err as c_int,
+        };
+        let configs = [
+            ("max-nodes", ptr::addr_of_mut!(LIMITS.max_nodes)),
+            ("max-depth", ptr::addr_of_mut!(LIMITS.max_depth)),
+            ("max-value-length", ptr::addr_of_mut!(LIMITS.max_value_length)),
+        ];
+        for (name, field) in configs {
+            let name = CString::new(name).unwrap();
+            if register(ctx, name.as_ptr(), 0, 0, 0, i64::MAX, get_limit, set_limit, ptr::null(), field as *mut c_void) != raw::Status::Ok as c_int {
+                return raw::Status::Err as c_int
+            }
+        }
+        load(ctx)
+    }
+}
+
+
+fn init(ctx: *mut raw::RedisModuleCtx, args: &[String]) -> c_int {
+    let capabilities = unsafe {
+        let server_version = api_function("GetServerVersion").map(|func| {
+            let get_server_version: unsafe extern "C" fn() -> c_int = std::mem::transmute(func);
+            get_server_version()
+        });
+        Capabilities {
+            server_version,
+            resp3: api_function("ReplyWithMap").is_some(),
+            keyspec: api_function("SetCommandInfo").is_some(),
+            config: api_function("RegisterStringConfig").is_some(),
+            acl: api_function("SetCommandACLCategories").is_some(),
+            acl_category: api_function("AddACLCategory").is_some(),
+            scan: api_function("Scan").is_some(),
+        }
+    };
+
+    // RM_GetServerVersion only arrived in 6.0.9, RM_GetServerInfo is there since 6.0.0
+    let too_old = match capabilities.server_version {
+        Some(version) => version < MIN_REDIS_VERSION,
+        None => unsafe { raw::RedisModule_GetServerInfo }.is_none(),
+    };
+    if too_old {
+        log(&format!("redis {} or newer is required", format_version(MIN_REDIS_VERSION)));
+        return raw::Status::Err as c_int
+    }
+
+    log(&format!("capabilities {:?}", capabilities));
+    unsafe { CAPABILITIES = capabilities };
+    if capabilities.config {
+        if register_limits(ctx) == raw::Status::Err as c_int {
+            log("could not register the limits");
+            return raw::Status::Err as c_int
+        }
+    } else if args.is_empty() {
+        log("module configs need redis 7, the limits are off unless given as load arguments");
+    }
+    // after the configs, which load their defaults over whatever was there
+    if let Err(e) = load_limits(args) {
+        log(&e.msg);
+        return raw::Status::Err as c_int
+    }
+    if capabilities.acl_category {
+        add_acl_category(ctx);
+    }
+    if create_tree_type(ctx) == raw::Status::Err as c_int {
+        return raw::Status::Err as c_int
+    }
+    if !capabilities.scan {
+        log("RM_Scan needs redis 6.0.6, expired nodes wait for a command to reach them");
+    }
+    start_expire_cycle(ctx);
+    create_commands(ctx)
+}
+
+// =================================================================================================
+// RDB ENCODING
+// =================================================================================================
+// encver 1 saves one buffer: the node count, then every node in BFS order as its length prefixed
+// value, its child count, its attribute count and the length prefixed field and value of each
+// attribute, then 0 for no weight or 1 followed by the 8 little endian bytes of the weight, all
+// numbers LEB128 varints, followed by the flags, then the deadline count and the
+// preorder position and unix time in milliseconds of every node with a deadline, then the version
+// and the notify target as its kind (0 for none, 1 a channel, 2 a stream) followed by its name.
+// A forest saves its hidden root as the first node and sets FLAG_FOREST. encver 0 saved only the
+// tree string
+const ENCODING_VERSION: c_int = 1;
+
+// the version byte of tree.dumpnode, which holds no deadline and so stops at the encver 1 buffer
+const NODE_DUMP_VERSION: u8 = 1;
+
+const FLAG_CASE_INSENSITIVE: u64 = 1;
+const FLAG_UNIQUE: u64 = 2;
+const FLAG_FOREST: u64 = 4;
+
+fn push_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut n = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first().ok_or("truncated tree encoding")?;
+        *buf = rest;
+        n |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n)
+        }
+    }
+    Err(Error::from("overlong number in tree encoding"))
+}
+
+fn push_str(buf: &mut Vec<u8>, s: &str) {
+    push_bytes(buf, s.as_bytes());
+}
+
+fn push_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    push_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn read_str(buf: &mut &[u8]) -> Result<String, Error> {
+    String::from_utf8(read_bytes(buf)?).map_err(|_| Error::from("invalid UTF-8 string in tree encoding"))
+}
+
+// node values, which may be any bytes
+fn read_bytes(buf: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    let len = read_varint(buf)? as usize;
+    if len > buf.len() {
+        return Err(Error::from("truncated tree encoding"))
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes.to_vec())
+}
+
+fn encode_tree(root: &Node<NodeData>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    push_varint(&mut buf, root.node_count() as u64);
+    for visit in root.bfs().iter {
+        push_bytes(&mut buf, &visit.data.value);
+        push_varint(&mut buf, visit.size.degree as u64);
+        push_varint(&mut buf, visit.data.attributes().count() as u64);
+        for (field, value) in visit.data.attributes() {
+            push_str(&mut buf, field);
+            push_str(&mut buf, value);
+        }
+        match visit.data.weight {
+            Some(weight) => {
+                push_varint(&mut buf, 1);
+                buf.extend_from_slice(&weight.to_le_bytes());
+            },
+            None => push_varint(&mut buf, 0),
+        }
+    }
+    buf
+}
+
+fn read_weight(buf: &mut &[u8]) -> Result<Option<f64>, Error> {
+    match read_varint(buf)? {
+        0 => Ok(None),
+        1 if buf.len() >= 8 => {
+            let (bytes, rest) = buf.split_at(8);
+            *buf = rest;
+            let weight = f64::from_le_bytes(<[u8; 8]>::try_from(bytes).unwrap());
+            if !weight.is_finite() {
+                return Err(Error::from("invalid weight in tree encoding"))
+            }
+            Ok(Some(weight))
+        },
+        1 => Err(Error::from("truncated tree encoding")),
+        flag => Err(Error::from(format!("bad weight flag {} in tree encoding", flag))),
+    }
+}
+
+// anything truncated, left over or not adding up to one tree is an error, never a panic
+fn decode_tree(mut buf: &[u8]) -> Result<Tree<NodeData>, Error> {
+    let count = read_varint(&mut buf)? as usize;
+    // a node takes two bytes at least, a bigger count is corrupt and must not be allocated for
+    if count == 0 || count > buf.len() / 2 {
+        return Err(Error::from(format!("bad node count {} in tree encoding", count)))
+    }
+
+    let mut nodes = Vec::with_capacity(count);
+    // position of the first child of every node, the children of a node follow those of the nodes before it
+    let mut first_children = Vec::with_capacity(count);
+    let mut next_child = 1;
+    for i in 0..count {
+        if i > 0 && next_child <= i {
+            return Err(Error::from("node without parent in tree encoding"))
+        }
+        let mut data = NodeData::new(read_bytes(&mut buf)?);
+        let degree = read_varint(&mut buf)? as usize;
+        for _ in 0..read_varint(&mut buf)? {
+            let field = read_str(&mut buf)?;
+            data.set_attribute(field, read_str(&mut buf)?);
+        }
+        data.weight = read_weight(&mut buf)?;
+        first_children.push(next_child);
+        next_child = next_child.saturating_add(degree);
+        nodes.push((data, degree));
+    }
+    if next_child != count {
+        return Err(Error::from("child counts do not add up in tree encoding"))
+    }
+    if !buf.is_empty() {
+        return Err(Error::from("trailing bytes after tree encoding"))
+    }
+
+    // children come later in BFS order, so walking backwards sizes them before their parent
+    let mut descendants = vec![0; count];
+    for i in (0..count).rev() {
+        let children = first_children[i]..first_children[i] + nodes[i].1;
+        descendants[i] = children.map(|child| 1 + descendants[child]).sum();
+    }
+    let root_size = Size { degree: nodes[0].1, descendants: count - 1 };
+    let iter = nodes.into_iter().zip(descendants).map(|((data, degree), descendants)| {
+        bfs::Visit { data, size: Size { degree, descendants } }
+    });
+    Ok(Tree::from(bfs::BfsTree { iter, size: root_size }))
+}
+
+
+// tree.dumpnode hands out the encoding version as one byte followed by the encoded subtree,
+// in hex since command arguments have to be UTF-8
+fn dump_node(node: &Node<NodeData>) -> String {
+    let mut buf = vec![NODE_DUMP_VERSION];
+    buf.extend(encode_tree(node));
+    buf.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn restore_node(dump: &str) -> Result<Tree<NodeData>, Error> {
+    if dump.len() % 2 != 0 || !dump.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::from("invalid node dump"))
+    }
+    let buf = (0..dump.len()).step_by(2).map(|i| u8::from_str_radix(&dump[i..i + 2], 16).unwrap()).collect::<Vec<_>>();
+    let tree = match buf.split_first() {
+        Some((&NODE_DUMP_VERSION, encoded)) => decode_tree(encoded)?,
+        Some((version, _)) => return Err(Error::from(format!("node dump of unknown encoding version {}", version))),
+        None => return Err(Error::from("invalid node dump")),
+    };
+    // made by a client as far as the module can tell, so held to what the tree string allows
+    check_tree_data(&tree)?;
+    Ok(tree)
+}
+
+
+fn load_tree(rdb: *mut raw::RedisModuleIO, encver: c_int) -> Result<RedisTreeType, Error> {
+    let buffer = raw::load_string_buffer(rdb);
+    let data = match encver {
+        // encver 0 only saved the tree string
+        0 => {
+            return Ok(RedisTreeType::new(node_tree(Tree::try_from(buffer.as_ref())?), false))
+        },
+        1 => decode_tree(buffer.as_ref())?,
+        _ => return Err(Error::from(format!("unknown encoding version {}, saved by a newer module", encver))),
+    };
+
+    let flags = raw::load_unsigned(rdb);
+    if (flags & FLAG_FOREST != 0) != data.root().data().is_hidden_root() {
+        return Err(Error::from("forest flag does not match the tree encoding"))
+    }
+    let mut value = RedisTreeType::new(data, flags & FLAG_CASE_INSENSITIVE != 0);
+    value.unique = flags & FLAG_UNIQUE != 0;
+    let expires = (0..raw::load_unsigned(rdb)).map(|_| (raw::load_unsigned(rdb) as usize, raw::load_signed(rdb))).collect();
+    value.set_expires_in_preorder(expires);
+    value.version = raw::load_unsigned(rdb);
+    value.notify = match raw::load_unsigned(rdb) {
+        0 => None,
+        1 => Some(NotifyTarget::Channel(raw::load_string(rdb))),
+        2 => Some(NotifyTarget::Stream(raw::load_string(rdb))),
+        kind => return Err(Error::from(format!("unknown notify target kind {}", kind))),
+    };
+    Ok(value)
+}
+
+// a tree that does not load stops the whole load, the server then refuses to start on the file
+// rather than serving a dataset with a tree missing or made up
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn rdb_load(rdb: *mut raw::RedisModuleIO, encver: c_int) -> *mut c_void {
+    match load_tree(rdb, encver) {
+        Ok(value) => Box::into_raw(Box::new(value)) as *mut c_void,
+        Err(e) => {
+            log(&format!("rdb_load failed: {}", e.msg));
+            ptr::null_mut()
+        },
+    }
+}
+
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn rdb_save(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
+    let value = &*(value as *mut RedisTreeType);
+    let encoded = encode_tree(value.data.root());
+    raw::RedisModule_SaveStringBuffer.unwrap()(rdb, encoded.as_ptr() as *const c_char, encoded.len());
+    raw::save_unsigned(rdb, value.flags());
+    let expires = value.expires_in_preorder();
+    raw::save_unsigned(rdb, expires.len() as u64);
+    for (position, at) in expires {
+        raw::save_unsigned(rdb, position as u64);
+        raw::save_signed(rdb, at);
+    }
+    raw::save_unsigned(rdb, value.version);
+    match &value.notify {
+        None => raw::save_unsigned(rdb, 0),
+        Some(NotifyTarget::Channel(channel)) => {
+            raw::save_unsigned(rdb, 1);
+            raw::save_string(rdb, channel);
+        },
+        Some(NotifyTarget::Stream(stream)) => {
+            raw::save_unsigned(rdb, 2);
+            raw::save_string(rdb, stream);
+        },
+    }
+}
+
+
+// every node in preorder as its value, child count (which pins down the shape), attributes, weight
+// and deadline, then the flags
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn digest(md: *mut raw::RedisModuleDigest, value: *mut c_void) {
+    let value = &*(value as *mut RedisTreeType);
+    for visit in NodeWalk::from(value.data.root()) {
+        let node = match visit {
+            walk::Visit::Begin(node) | walk::Visit::Leaf(node) => node,
+            walk::Visit::End(_) => continue,
+        };
+        let add_string = |s: &[u8]| raw::RedisModule_DigestAddStringBuffer.unwrap()(md, s.as_ptr() as *mut _, s.len());
+        add_string(&node.data().value);
+        raw::RedisModule_DigestAddLongLong.unwrap()(md, node.degree() as i64);
+        for (field, value) in node.data().attributes() {
+            add_string(field.as_bytes());
+            add_string(value.as_bytes());
+        }
+        if let Some(weight) = node.data().weight {
+            raw::RedisModule_DigestAddLongLong.unwrap()(md, weight.to_bits() as i64);
+        }
+        if let Some(at) = value.expires.get(NonNull::from(node)) {
+            raw::RedisModule_DigestAddLongLong.unwrap()(md, at);
+        }
+        raw::RedisModule_DigestEndSequence.unwrap()(md);
+    }
+    raw::RedisModule_DigestAddLongLong.unwrap()(md, value.flags() as i64);
+    raw::RedisModule_DigestEndSequence.unwrap()(md);
+}
+
+
+// one tree.init recreating the tree, then tree.notify for its target and a tree.expire_node per deadline.
+// Each of those counts the version up by one, init sets it to what is left for them to reach the
+// version of the tree. Arguments go out as buffers so no value gets cut at a NUL
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn aof_rewrite(aof: *mut raw::RedisModuleIO, key: *mut raw::RedisModuleString, value: *mut c_void) {
+    let value = &*(value as *mut RedisTreeType);
+    let deadlines = value.named_deadlines();
+
+    let mut args = value.init_args(value.data.root());
+    args.push("VERSION".to_string());
+    args.push(value.version.saturating_sub(deadlines.len() as u64).to_string());
+    let emit = raw::RedisModule_EmitAOF.unwrap();
+    let cmd = CString::new("tree.init").unwrap();
+    let fmt = CString::new(format!("s{}", "b".repeat(args.len()))).unwrap();
+    let buffers = args.iter().map(|arg| (arg.as_ptr() as *const c_char, arg.len())).collect::<Vec<_>>();
+    // EmitAOF is variadic, one call per argument count
+    match buffers[..] {
+        [(a, a_len), (b, b_len), (c, c_len)] => emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, a, a_len, b, b_len, c, c_len),
+        [(a, a_len), (b, b_len), (c, c_len), (d, d_len)] => {
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, a, a_len, b, b_len, c, c_len, d, d_len)
+        },
+        [(a, a_len), (b, b_len), (c, c_len), (d, d_len), (e, e_len)] => {
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, a, a_len, b, b_len, c, c_len, d, d_len, e, e_len)
+        },
+        [(a, a_len), (b, b_len), (c, c_len), (d, d_len), (e, e_len), (f, f_len)] => {
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, a, a_len, b, b_len, c, c_len, d, d_len, e, e_len, f, f_len)
+        },
+        [(a, a_len), (b, b_len), (c, c_len), (d, d_len), (e, e_len), (f, f_len), (g, g_len)] => {
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, a, a_len, b, b_len, c, c_len, d, d_len, e, e_len, f, f_len, g, g_len)
+        },
+        [(a, a_len), (b, b_len), (c, c_len), (d, d_len), (e, e_len), (f, f_len), (g, g_len), (h, h_len)] => {
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, a, a_len, b, b_len, c, c_len, d, d_len, e, e_len, f, f_len, g, g_len, h, h_len)
+        },
+        _ => unreachable!("tree.init takes eight arguments after the key at most"),
+    }
+    if let Some(target) = &value.notify {
+        let (kind, name) = target.describe();
+        let (cmd, fmt, kind) = (CString::new("tree.notify").unwrap(), CString::new("scb").unwrap(), CString::new(kind).unwrap());
+        emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, kind.as_ptr(), name.as_ptr(), name.len());
+    }
+    let cmd = CString::new("tree.expire_node").unwrap();
+    let (pxat, path_flag) = (CString::new("PXAT").unwrap(), CString::new("PATH").unwrap());
+    for (node_arg, by_path, at) in deadlines {
+        if by_path {
+            let fmt = CString::new("sbcbc").unwrap();
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, node_arg.as_ptr(), node_arg.len(), pxat.as_ptr(), at.as_ptr(), at.len(), path_flag.as_ptr());
+        } else {
+            let fmt = CString::new("sbcb").unwrap();
+            emit(aof, cmd.as_ptr(), fmt.as_ptr(), key, node_arg.as_ptr(), node_arg.len(), pxat.as_ptr(), at.as_ptr(), at.len());
+        }
+    }
+}
+
+
+
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn free(value: *mut c_void) {
+    Box::from_raw(value as *mut RedisTreeType);
+}
+
+// what MEMORY USAGE reports, the estimate tree.info gives as memory_bytes
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn mem_usage(value: *const c_void) -> usize {
+    let value = &*(value as *const RedisTreeType);
+    value.memory_usage()
+}
+
+// the work free takes, one per node. Past lazyfree's 64 UNLINK, FLUSHALL ASYNC and the lazyfree-lazy-*
+// configs have free run on a background thread, which is safe as nothing else holds the tree by then
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn free_effort(key: *mut raw::RedisModuleString, value: *const c_void) -> usize {
+    let value = &*(value as *const RedisTreeType);
+    value.data.root().node_count()
+}
+
+
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn aux_load(rdb: *mut raw::RedisModuleIO, encver: i32, when: i32) -> i32 {
+    raw::Status::Ok as i32
+}
+
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn aux_save(rdb: *mut raw::RedisModuleIO, when: i32) {
+}
+
+
+// COPY, the copy shares no node with the source
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn copy(from_key: *mut raw::RedisModuleString, to_key: *mut raw::RedisModuleString, value: *const c_void) -> *mut c_void {
+    let value = &*(value as *const RedisTreeType);
+    Box::into_raw(Box::new(value.deep_clone())) as *mut c_void
+}
+
+// nodes copied between two looks at whether active defrag has to stop
+const DEFRAG_CHUNK_NODES: usize = 1000;
+
+// copies the tree a chunk of nodes at a time, into fresh allocations values and index included, and
+// moves it into the copy once done. True when `should_stop` cut it short, the next call goes on from there
+unsafe fn defrag_value(value: *mut *mut c_void, chunk: usize, mut should_stop: impl FnMut() -> bool) -> bool {
+    let tree = &mut *(*value as *mut RedisTreeType);
+    let mut resume = match std::mem::replace(&mut tree.defrag, Defrag::Fragmented) {
+        Defrag::Compacted(version) if version == tree.version => {
+            tree.defrag = Defrag::Compacted(version);
+            return false
+        },
+        Defrag::Copying(version, state) if version == tree.version => Some(state),
+        // a write since, the copy would mix both trees
+        _ => None,
+    };
+    loop {
+        match tree.data.deep_clone_chunked(chunk, resume) {
+            ChunkedClone::Done(data) => {
+                let mut rebuilt = tree.with_data(data);
+                rebuilt.defrag = Defrag::Compacted(tree.version);
+                free(*value);
+                *value = Box::into_raw(Box::new(rebuilt)) as *mut c_void;
+                return false
+            },
+            ChunkedClone::Pending(state) if should_stop() => {
+                tree.defrag = Defrag::Copying(tree.version, state);
+                return true
+            },
+            ChunkedClone::Pending(state) => resume = Some(state),
+        }
+    }
+}
+
+// Active defrag (6.2 or newer), within the time RM_DefragShouldStop allows. 1 asks the server to
+// call again later for the rest, which it does for trees whose free_effort is over active-defrag-max-scan-fields
+#[allow(non_snake_case, unused)]
+pub unsafe extern "C" fn defrag(ctx: *mut c_void, key: *mut raw::RedisModuleString, value: *mut *mut c_void) -> c_int {
+    type DefragShouldStop = unsafe extern "C" fn(*mut c_void) -> c_int;
+    let should_stop = api_function("DefragShouldStop").map(|func| std::mem::transmute::<*mut c_void, DefragShouldStop>(func));
+    defrag_value(value, DEFRAG_CHUNK_NODES, || should_stop.is_some_and(|should_stop| should_stop(ctx) != 0)) as c_int
+}
+
+
+const TYPE_NAME: &str = "ReTreeYou";
+
+const TYPE_METHODS: raw::RedisModuleTypeMethods = raw::RedisModuleTypeMethods {
+    version: raw::REDISMODULE_TYPE_METHOD_VERSION as u64,
+    rdb_load: Some(rdb_load),
+    rdb_save: Some(rdb_save),
+    aof_rewrite: Some(aof_rewrite),
+    free: Some(free),
+    mem_usage: Some(mem_usage),
+    digest: Some(digest),
+    aux_load: None,
+    aux_save: None,
+    aux_save_triggers: 0,
+};
+
+// created by create_tree_type rather than listed in data_types, RedisType only knows the version 2 methods
+static TREE_TYPE: RedisType = RedisType::new(TYPE_NAME, ENCODING_VERSION, TYPE_METHODS);
+
+// RedisModuleTypeMethods as of version 3 (6.2), the bindings stop at version 2.
+// Servers only read the fields of the versions they know, older ones never see the rest
+#[repr(C)]
+struct TypeMethodsV3 {
+    v2: raw::RedisModuleTypeMethods,
+    free_effort: Option<unsafe extern "C" fn(key: *mut raw::RedisModuleString, value: *const c_void) -> usize>,
+    unlink: Option<unsafe extern "C" fn(key: *mut raw::RedisModuleString, value: *const c_void)>,
+    copy: Option<unsafe extern "C" fn(from_key: *mut raw::RedisModuleString, to_key: *mut raw::RedisModuleString, value: *const c_void) -> *mut c_void>,
+    defrag: Option<unsafe extern "C" fn(ctx: *mut c_void, key: *mut raw::RedisModuleString, value: *mut *mut c_void) -> c_int>,
+}
+
+fn create_tree_type(ctx: *mut raw::RedisModuleCtx) -> c_int {
+    let mut methods = TypeMethodsV3 {
+        v2: raw::RedisModuleTypeMethods { version: 3, ..TYPE_METHODS },
+        free_effort: Some(free_effort),
+        unlink: None,
+        copy: Some(copy),
+        defrag: Some(defrag),
+    };
+    let name = CString::new(TYPE_NAME).unwrap();
+    let tree_type = unsafe {
+        raw::RedisModule_CreateDataType.unwrap()(
+            ctx,
+            name.as_ptr(),
+            ENCODING_VERSION,
+            &mut methods as *mut TypeMethodsV3 as *mut raw::RedisModuleTypeMethods,
+        )
+    };
+    if tree_type.is_null() {
+        log(&format!("could not create the {} type", TYPE_NAME));
+        return raw::Status::Err as c_int
+    }
+    *TREE_TYPE.raw_type.borrow_mut() = tree_type;
+    raw::Status::Ok as c_int
+}
+
+
+
+// the tree.init keywords a tree value must not read like to come first
+const INIT_OPTIONS: [&str; 7] = ["FROMPATHS", "CASEINSENSITIVE", "UNIQUE", "FOREST", "NX", "XX", "IFVERSION"];
+
+// where FROMPATHS is in the arguments of tree.init after the key, with only the options it takes
+// ahead of it. Every argument after it is a path, whatever it reads like. A tree value can not come
+// first then, as the arguments ahead of FROMPATHS would be unknown ones to it
+fn frompaths_at(args: &[Arg]) -> Option<usize> {
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "FROMPATHS" => return Some(i),
+            "CASEINSENSITIVE" | "UNIQUE" | "FOREST" | "NX" | "XX" => i += 1,
+            "IFVERSION" => i += 2,
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn init_tree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let mut rest = args.collect::<Vec<_>>();
+    let (tree_value, paths) = match frompaths_at(&rest) {
+        Some(at) => {
+            let paths = rest.split_off(at + 1).into_iter().map(|path| path.0).collect::<Vec<_>>();
+            rest.pop();
+            (Vec::new(), Some(paths))
+        },
+        None if rest.is_empty() => return Err(RedisError::WrongArity),
+        None => (rest.remove(0).0, None),
+    };
+    let mut args = rest.into_iter();
+
+    let mut case_insensitive = false;
+    let mut unique = false;
+    let mut forest = false;
+    let mut format = Format::String;
+    // like SET, NX only creates the key and XX only replaces it
+    let mut nx = false;
+    let mut xx = false;
+    let mut if_version = None;
+    // what an AOF rewrite leaves the tree at, once the deadlines after it counted up
+    let mut version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "CASEINSENSITIVE" => case_insensitive = true,
+            "UNIQUE" => unique = true,
+            "FOREST" => forest = true,
+            "FORMAT" => format = Format::parse(&args.next_string()?)?,
+            "NX" => nx = true,
+            "XX" => xx = true,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            "VERSION" if replaying(ctx) => version = Some(args.next_u64()?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    if nx && xx {
+        return Err(Error::from("NX and XX can not be combined").into())
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let data = match paths {
+        Some(paths) => tree_from_paths(&paths, case_insensitive, forest)?,
+        None if forest => format.parse_forest(&tree_value)?,
+        None => format.parse_tree(&tree_value)?,
+    };
+    limits().check_tree(&data)?;
+    let mut value = RedisTreeType::new(data, case_insensitive);
+    if unique {
+        if let Some(node_data) = value.duplicate() {
+            return Err(reply::duplicate(node_data))
+        }
+        value.unique = true;
+    }
+    // a skipped init replies nil, as SET does
+    if (nx && !key.is_empty()) || (xx && key.is_empty()) {
+        return Ok(RedisValue::Null)
+    }
+    value.succeed(key.get_value(&TREE_TYPE).ok().flatten());
+    if let Some(version) = version {
+        value.version = version;
+    }
+    key.set_value(&TREE_TYPE, value)?;
+    ctx.replicate_verbatim();
+    REDIS_OK
+}
+
+// builds a tree from parent child pairs as an adjacency list holds them, a value naming one node
+fn tree_from_edges(root_data: Vec<u8>, edges: &[(Vec<u8>, Vec<u8>)]) -> Result<Tree<NodeData>, Error> {
+    check_node_data(&root_data)?;
+    let mut parents = HashMap::new();
+    let mut children = HashMap::<&[u8], Vec<&[u8]>>::new();
+    for (parent, child) in edges {
+        check_node_data(parent)?;
+        check_node_data(child)?;
+        if parents.insert(child.as_slice(), parent.as_slice()).is_some() {
+            return Err(Error::from(format!("{} has more than one parent", display_value(child))))
+        }
+        children.entry(parent.as_slice()).or_default().push(child.as_slice());
+    }
+    if parents.contains_key(root_data.as_slice()) {
+        return Err(Error::from(format!("cycle through {}", display_value(&root_data))))
+    }
+
+    let tree = Tree::new(NodeData::new(root_data.clone()));
+    let mut reached = HashSet::from([root_data.as_slice()]);
+    let mut queue = VecDeque::from([(root_data.as_slice(), NonNull::from(tree.root()))]);
+    while let Some((parent, node)) = queue.pop_front() {
+        for &child in children.get(parent).into_iter().flatten() {
+            let sub_tree = Tree::new(NodeData::new(child.to_vec()));
+            queue.push_back((child, NonNull::from(sub_tree.root())));
+            unsafe { &mut *node.as_ptr() }.push_back(sub_tree);
+            reached.insert(child);
+        }
+    }
+
+    // a parent never reached is either on a cycle or below some other root
+    if let Some((parent, _)) = edges.iter().find(|(parent, _)| !reached.contains(parent.as_slice())) {
+        let mut seen = HashSet::new();
+        let mut node = parent.as_slice();
+        while let Some(&up) = parents.get(node) {
+            if !seen.insert(node) {
+                return Err(Error::from(format!("cycle through {}", display_value(node))))
+            }
+            node = up;
+        }
+        return Err(Error::from(format!("{} is not connected to the root {}", display_value(node), display_value(&root_data))))
+    }
+    Ok(tree)
+}
+
+
+fn from_edges(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let root_data = args.next_bytes()?;
+    let if_version = leading_if_version(&mut args)?;
+    if args.len() % 2 != 0 {
+        return Err(Error::from("edges come as parent child pairs").into())
+    }
+    let edges = std::iter::from_fn(|| Some((args.next()?.0, args.next()?.0))).collect::<Vec<_>>();
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let data = tree_from_edges(root_data, &edges)?;
+    limits().check_tree(&data)?;
+    let mut value = RedisTreeType::new(data, false);
+    value.succeed(key.get_value(&TREE_TYPE).ok().flatten());
+    key.set_value(&TREE_TYPE, value)?;
+    ctx.replicate_verbatim();
+    REDIS_OK
+}
+
+
+// parent child pairs, level by level so that tree.fromedges puts the children back in order
+fn to_edges(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let mut edges = Vec::new();
+            let mut queue = VecDeque::from([value.node_or_root(node_data.as_deref())?]);
+            while let Some(parent) = queue.pop_front() {
+                for child in parent.iter() {
+                    // the roots of a forest have no parent to pair with
+                    if !parent.data().is_hidden_root() {
+                        edges.push(parent.data());
+                        edges.push(child.data());
+                    }
+                    queue.push_back(child);
+                }
+            }
+            Reply::array(edges).send(ctx)
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// MARKER goes on the nodes DEPTH cut the children of, so it comes with DEPTH only
+fn check_marker(depth: Option<usize>, marker: Option<Vec<u8>>) -> Result<Option<NodeData>, Error> {
+    match marker {
+        Some(_) if depth.is_none() => Err(Error::from("MARKER needs DEPTH")),
+        Some(marker) => {
+            check_node_data(&marker)?;
+            Ok(Some(NodeData::new(marker)))
+        },
+        None => Ok(None),
+    }
+}
+
+
+fn get_tree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+
+    let mut with_hash = false;
+    let mut format = Format::String;
+    let mut depth = None;
+    let mut marker = None;
+    let mut chunked = None;
+    let mut cursor = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "WITHHASH" => with_hash = true,
+            "FORMAT" => format = Format::parse(&args.next_string()?)?,
+            "DEPTH" => depth = Some(args.next_u64()? as usize),
+            "MARKER" => marker = Some(args.next_bytes()?),
+            "CHUNKED" => chunked = Some(args.next_u64()? as usize),
+            "CURSOR" => cursor = Some(args.next_u64()? as usize),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    let marker = check_marker(depth, marker)?;
+    match chunked {
+        Some(0) => return Err(Error::from("CHUNKED takes a positive count").into()),
+        Some(_) if with_hash || depth.is_some() || !matches!(format, Format::String) => {
+            return Err(Error::from("CHUNKED can not be combined with WITHHASH, FORMAT or DEPTH").into())
+        },
+        None if cursor.is_some() => return Err(Error::from("CURSOR needs CHUNKED").into()),
+        _ => {},
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    if let Some(count) = chunked {
+        // [next_cursor, chunk], the cursor being where the chunk ends in preorder, 0 after the last one
+        let cursor = cursor.unwrap_or(0);
+        let (chunk, done) = tree_string_chunk(value.data.root(), cursor, count);
+        let next_cursor = if done { 0 } else { cursor + count };
+        return Ok(RedisValue::Array(vec![next_cursor.to_string().into(), chunk.into()]))
+    }
+    // the roots of a forest are a level below its hidden root
+    let truncated = depth.map(|depth| value.data.root().truncated(depth + value.forest() as usize, marker.as_ref()));
+    let tree = format.render(ctx, truncated.as_ref().map_or(value.data.root(), |t| t.root()));
+    if with_hash {
+        return Reply::Array(vec![tree, RedisValue::from(format_hash(value.data.root().subtree_hash())).into()]).send(ctx)
+    }
+    tree.send(ctx)
+}
+
+
+// replaces the tree in one go, replies the one it replaced in the same format or nil.
+// The flags stay those of the replaced tree, a new key gets none
+fn get_set(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let tree_value = args.next_bytes()?;
+
+    let mut format = Format::String;
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "FORMAT" => format = Format::parse(&args.next_string()?)?,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    // a forest is replaced by a forest
+    let data = match key.get_value::<RedisTreeType>(&TREE_TYPE) {
+        Ok(Some(value)) if value.forest() => format.parse_forest(&tree_value)?,
+        _ => format.parse_tree(&tree_value)?,
+    };
+    limits().check_tree(&data)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let previous = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let mut replacement = RedisTreeType::new(data, value.case_insensitive);
+            if value.unique {
+                if let Some(node_data) = replacement.duplicate() {
+                    return Err(reply::duplicate(node_data))
+                }
+                replacement.unique = true;
+            }
+            replacement.succeed(Some(value));
+            let previous = format.render(ctx, value.data.root());
+            *value = replacement;
+            previous
+        },
+        None => {
+            key.set_value(&TREE_TYPE, RedisTreeType::new(data, false))?;
+            RedisValue::Null.into()
+        },
+    };
+    ctx.replicate_verbatim();
+    previous.send(ctx)
+}
+
+
+// like MGET, nil in place of a key that is missing or holds something else than a tree
+fn get_multi(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let keys = args.into_iter().skip(1).map(key_name).collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err(RedisError::WrongArity)
+    }
+
+    Ok(reply::array(keys.iter().map(|key| {
+        match ctx.open_key(key).get_value::<RedisTreeType>(&TREE_TYPE) {
+            Ok(Some(value)) => value.to_string().into(),
+            _ => RedisValue::Null,
+        }
+    })))
+}
+
+
+fn get_shape(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = value.node_or_root(node_data.as_deref())?;
+            // the shapes of the roots of a forest side by side
+            if node.data().is_hidden_root() {
+                return Ok(node.iter().map(|root| root.shape().to_string()).collect::<Vec<_>>().join(" ").into())
+            }
+            Ok(node.shape().to_string().into())
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+fn get_shape_hash(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(format_hash(value.node_or_root(node_data.as_deref())?.shape_hash()).into()),
+        None => reply::no_key(),
+    }
+}
+
+
+fn export_commands(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key_name = args.next_key()?;
+    let key = ctx.open_key(&key_name);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = value.node_or_root(node_data.as_deref())?;
+            Ok(reply::array(value.recreate_commands(&key_name, node).into_iter().map(reply::array)))
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// tree.get_subtree with the node optional and FORMAT DOT by default
+fn to_dot(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+
+    // options come in pairs, so an odd count means the node was given
+    let node_data = if args.len() % 2 == 1 { Some(args.next_bytes()?) } else { None };
+    let mut format = Format::Dot;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "FORMAT" => format = Format::parse(&args.next_string()?)?,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => format.render(ctx, value.node_or_root(node_data.as_deref())?).send(ctx),
+        None => reply::no_key(),
+    }
+}
+
+
+fn get_hash(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(format_hash(value.node_or_root(node_data.as_deref())?.subtree_hash()).into()),
+        None => reply::no_key(),
+    }
+}
+
+// CHANNEL or STREAM has every later change to the tree published there, OFF stops it.
+// Without either replies the current target as [kind, name], an empty array when there is none
+fn notify(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let target = match args.next() {
+        None => None,
+        Some(kind) => Some(match kind.to_uppercase().as_str() {
+            "CHANNEL" => Some(NotifyTarget::Channel(args.next_key()?)),
+            "STREAM" => Some(NotifyTarget::Stream(args.next_key()?)),
+            "OFF" => None,
+            _ => return Err(Error::from(format!("unknown argument {}", kind)).into()),
+        }),
+    };
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    match target {
+        None => Ok(match &value.notify {
+            Some(target) => {
+                let (kind, name) = target.describe();
+                reply::array([kind.to_lowercase(), name.to_string()])
+            },
+            None => reply::array(Vec::<String>::new()),
+        }),
+        Some(target) => {
+            value.notify = target;
+            ctx.replicate_verbatim();
+            REDIS_OK
+        },
+    }
+}
+
+// the change count of the tree, what IFVERSION compares with
+fn get_version(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(RedisValue::Integer(value.version as i64)),
+        None => reply::no_key(),
+    }
+}
+
+fn get_subtree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let mut with_path = false;
+    let mut with_hash = false;
+    let mut format = Format::String;
+    let mut depth = None;
+    let mut marker = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "WITHPATH" => with_path = true,
+            "WITHHASH" => with_hash = true,
+            "FORMAT" => format = Format::parse(&args.next_string()?)?,
+            "DEPTH" => depth = Some(args.next_u64()? as usize),
+            "MARKER" => marker = Some(args.next_bytes()?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    let marker = check_marker(depth, marker)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = value.node(&node_data)?;
+    let truncated = depth.map(|depth| node.truncated(depth, marker.as_ref()));
+    let view = truncated.as_ref().map_or(node, |t| t.root());
+    if !with_path && !with_hash {
+        return format.render(ctx, view).send(ctx)
+    }
+
+    let mut reply = Vec::new();
+    if with_path {
+        reply.push(join_path(node, PATH_SEPARATOR).into());
+    }
+    reply.push(format.render(ctx, view));
+    if with_hash {
+        reply.push(RedisValue::from(format_hash(node.subtree_hash())).into());
+    }
+    Reply::Array(reply).send(ctx)
+}
+
+
+fn exists_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "BYPATH" => path = Some(args.next_bytes()?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        let under = match path {
+            Some(path) => value.locate_by_path(&path),
+            None => Some(value.data.root()),
+        };
+        if let Some(under) = under {
+            let exists = under.locate_first_by(&value.matcher(&node_data)).is_some();
+            return Ok(RedisValue::Integer(exists as i64))
+        }
+    }
+    Ok(RedisValue::Integer(0))
+}
+
+
+fn contains_path(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let path = args.next_bytes()?;
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(RedisValue::Integer(value.contains_path(&path) as i64)),
+        None => Ok(RedisValue::Integer(0)),
+    }
+}
+
+
+fn locate_all(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let matches = value.matcher(&node_data);
+            Reply::array(node_paths(value.data.root(), PATH_SEPARATOR).filter_map(|(path, node)| {
+                matches(node.data()).then_some(path)
+            })).send(ctx)
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// glob matching as SCAN MATCH does it: * any run, ? any one character, [abc] [^abc] [a-z] a set,
+// \ takes the next character as it is. A * only backtracks to where the last one started
+fn glob_match(pattern: &[u32], value: &[u32], nocase: bool) -> bool {
+    const STAR: u32 = '*' as u32;
+    const ANY: u32 = '?' as u32;
+    const SET: u32 = '[' as u32;
+    const ESCAPE: u32 = '\\' as u32;
+    let eq = |a: u32, b: u32| match (char::from_u32(a), char::from_u32(b)) {
+        (Some(a), Some(b)) if nocase => a.to_lowercase().eq(b.to_lowercase()),
+        _ => a == b,
+    };
+    let (mut p, mut v) = (0, 0);
+    // the pattern past the last * and how much of the value that * takes so far
+    let mut star: Option<(usize, usize)> = None;
+    while v < value.len() {
+        let next = match pattern.get(p) {
+            Some(&STAR) => {
+                star = Some((p + 1, v));
+                p += 1;
+                continue
+            },
+            Some(&ANY) => Some(p + 1),
+            Some(&SET) => glob_class(pattern, p, value[v], nocase),
+            Some(&ESCAPE) if p + 1 < pattern.len() => eq(pattern[p + 1], value[v]).then_some(p + 2),
+            Some(&c) => eq(c, value[v]).then_some(p + 1),
+            None => None,
+        };
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                v += 1;
+            },
+            (None, Some((after_star, taken))) => {
+                star = Some((after_star, taken + 1));
+                p = after_star;
+                v = taken + 1;
+            },
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == STAR)
+}
+
+// the set opening at pattern[p], where the pattern goes on after it when c is in the set
+fn glob_class(pattern: &[u32], mut p: usize, c: u32, nocase: bool) -> Option<usize> {
+    let fold = |u: u32| match char::from_u32(u) {
+        Some(c) if nocase => c.to_lowercase().next().unwrap_or(c) as u32,
+        _ => u,
+    };
+    let c = fold(c);
+    p += 1;
+    let negate = pattern.get(p) == Some(&('^' as u32));
+    if negate {
+        p += 1;
+    }
+    let mut found = false;
+    while p < pattern.len() && pattern[p] != ']' as u32 {
+        if pattern[p] == '\\' as u32 && p + 1 < pattern.len() {
+            p += 1;
+            found |= fold(pattern[p]) == c;
+        } else if p + 2 < pattern.len() && pattern[p + 1] == '-' as u32 {
+            let (start, end) = (fold(pattern[p]), fold(pattern[p + 2]));
+            found |= start.min(end) <= c && c <= start.max(end);
+            p += 2;
+        } else {
+            found |= fold(pattern[p]) == c;
+        }
+        p += 1;
+    }
+    (found != negate).then_some(p + 1)
+}
+
+// the characters of a value or pattern as glob_match takes them, a byte outside UTF-8 taken as
+// one character of its own, past every char
+fn glob_units(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes.utf8_chunks().flat_map(|chunk| {
+        chunk.valid().chars().map(u32::from).chain(chunk.invalid().iter().map(|&byte| 0x11_0000 + byte as u32))
+    })
+}
+
+// paths of the nodes whose value matches a glob pattern, in preorder, COUNT of them at most
+fn search(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let pattern = glob_units(&args.next_bytes()?).collect::<Vec<_>>();
+
+    let mut count = usize::MAX;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "COUNT" => count = args.next_u64()? as usize,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    if count == 0 {
+        return Err(Error::from("COUNT must be positive").into())
+    }
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let mut chars = Vec::new();
+            let found = node_paths(value.data.root(), PATH_SEPARATOR).filter_map(|(path, node)| {
+                chars.clear();
+                chars.extend(glob_units(&node.data().value));
+                glob_match(&pattern, &chars, value.case_insensitive).then_some(path)
+            });
+            Reply::array(found.take(count)).send(ctx)
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// how many nodes tree.search would find, the paths are never built
+fn count_match(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let pattern = glob_units(&args.next_bytes()?).collect::<Vec<_>>();
+
+    let mut under = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "UNDER" => under = Some(args.next_bytes()?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let mut chars = Vec::new();
+            let matched = value.node_or_root(under.as_deref())?.bfs().iter.filter(|visit| {
+                chars.clear();
+                chars.extend(glob_units(&visit.data.value));
+                !visit.data.is_hidden_root() && glob_match(&pattern, &chars, value.case_insensitive)
+            });
+            Ok(reply::integer(matched.count()))
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// the tree cut down to the nodes matching the pattern and their ancestors, nil when none match
+fn filter(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let pattern = glob_units(&args.next_bytes()?).collect::<Vec<_>>();
+
+    let mut format = Format::String;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "FORMAT" => format = Format::parse(&args.next_string()?)?,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let mut chars = Vec::new();
+    // the hidden root of a forest stays as the ancestor of every root kept
+    let filtered = value.data.root().filtered(|data| {
+        chars.clear();
+        chars.extend(glob_units(&data.value));
+        !data.is_hidden_root() && glob_match(&pattern, &chars, value.case_insensitive)
+    });
+    match filtered {
+        Some(tree) => format.render(ctx, tree.root()).send(ctx),
+        None => Ok(RedisValue::Null),
+    }
+}
+
+
+// xorshift64*, seeded from the keys std hashers get, plenty for sampling nodes
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        use std::hash::BuildHasher;
+        Rng(std::collections::hash_map::RandomState::new().build_hasher().finish() | 1)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % n as u64) as usize
+    }
+}
+
+// like SRANDMEMBER: one value, count distinct nodes, or with a negative count that many
+// picks which may repeat, every node being as likely as any other. Repeats are capped, a
+// huge negative count would otherwise build a reply of any size out of a one node tree
+const RANDNODE_MAX_REPEATS: u64 = 100_000;
+
+fn randnode(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let count = if args.len() > 0 { Some(args.next_i64()?) } else { None };
+    args.done()?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let root = value.data.root();
+    let node_count = value.node_count();
+    let mut rng = Rng::new();
+    // past the hidden root of a forest, which comes first in preorder
+    let nth = |n| root.nth_in_preorder(n + value.forest() as usize).unwrap().data();
+    match count {
+        None => Reply::from(nth(rng.below(node_count))).send(ctx),
+        Some(count) if count < 0 => {
+            if count.unsigned_abs() > RANDNODE_MAX_REPEATS {
+                return Err(Error::from(format!("count is out of range, at most {} picks with repeats", RANDNODE_MAX_REPEATS)).into())
+            }
+            Reply::array((0..count.unsigned_abs()).map(|_| nth(rng.below(node_count)))).send(ctx)
+        },
+        Some(count) => {
+            // Floyd's sampling, as many picks as values wanted whatever the tree size
+            let wanted = node_count.min(count as usize);
+            let mut picked = HashSet::new();
+            let mut values = Vec::with_capacity(wanted);
+            for j in node_count - wanted..node_count {
+                let n = rng.below(j + 1);
+                let n = if picked.insert(n) { n } else { picked.insert(j); j };
+                values.push(nth(n));
+            }
+            Reply::array(values).send(ctx)
+        },
+    }
+}
+
+
+fn leaf_paths(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+
+    // options come in pairs, so an odd count means the node was given
+    let node_data = if args.len() % 2 == 1 { Some(args.next_bytes()?) } else { None };
+    let mut separator = PATH_SEPARATOR.to_string();
+    let mut cursor = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "SEPARATOR" => separator = args.next_string()?,
+            "CURSOR" => cursor = Some(args.next_u64()? as usize),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let paths = node_paths(value.node_or_root(node_data.as_deref())?, &separator).filter_map(|(path, node)| {
+        node.has_no_child().then(|| Reply::from(path))
+    });
+
+    if let Some(cursor) = cursor {
+        return cursor_page(paths, cursor, DEFAULT_PAGE_SIZE).send(ctx)
+    }
+    Reply::array(paths).send(ctx)
+}
+
+
+fn get_leaves(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Reply::array(value.node_or_root(node_data.as_deref())?.leaves().map(|v| v.data())).send(ctx),
+        None => reply::no_key(),
+    }
+}
+
+
+fn scan(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let cursor = args.next_u64()? as usize;
+
+    let mut count = DEFAULT_PAGE_SIZE;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "COUNT" => count = args.next_u64()? as usize,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    if count == 0 {
+        return Err(Error::from("COUNT must be positive").into())
+    }
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let nodes = value.data.root().bfs().iter.filter(|visit| !visit.data.is_hidden_root()).map(|visit| Reply::from(visit.data));
+            cursor_page(nodes, cursor, count).send(ctx)
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// a walk cursor on the subtree under the node, or the whole tree, replies [cursor, first visit]
+fn walk_start(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key_name = args.next_key()?;
+    let key = ctx.open_key(&key_name);
+    let node_data = args.next();
+    args.done()?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = value.node_or_root(node_data.as_deref())?;
+    if node.data().is_hidden_root() {
+        return Err(Error::from("a forest is walked from one of its roots").into())
+    }
+    let now = now_ms();
+    expire_walk_cursors(now);
+    if walk_cursors().len() >= MAX_WALK_CURSORS {
+        return Err(Error::from(format!("{} walk cursors are open already", MAX_WALK_CURSORS)).into())
+    }
+    let origin = preorder_position(node);
+    let cursor = WalkCursor {
+        client: client_id(ctx),
+        db: selected_db(ctx),
+        key: key_name,
+        version: value.version,
+        path: origin.clone(),
+        origin,
+        visit: WalkVisit::arriving(node),
+        last_used: now,
+    };
+    let id = unsafe {
+        let id = NEXT_WALK_CURSOR;
+        NEXT_WALK_CURSOR += 1;
+        id
+    };
+    let visit = walk_visit_reply(&cursor, node);
+    walk_cursors().insert(id, cursor);
+    Reply::Array(vec![Reply::Value(RedisValue::Integer(id as i64)), visit]).send(ctx)
+}
+
+// the next visit in depth first order, nil once the walk is over
+fn walk_next(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let id = args.next_u64()?;
+    args.done()?;
+
+    walk_move(ctx, id, |cursor, node| match cursor.visit {
+        WalkVisit::Begin => {
+            let mut path = cursor.path.clone();
+            path.push(0);
+            WalkStep::To(path, WalkVisit::arriving(node.iter().next().unwrap()))
+        },
+        _ if cursor.path == cursor.origin => WalkStep::Done,
+        _ => {
+            let mut path = cursor.path.clone();
+            let index = path.pop().unwrap();
+            match node.parent().unwrap().iter().nth(index + 1) {
+                Some(sibling) => {
+                    path.push(index + 1);
+                    WalkStep::To(path, WalkVisit::arriving(sibling))
+                },
+                None => WalkStep::To(path, WalkVisit::End),
+            }
+        },
+    })
+}
+
+// down to the n-th child of the node under visit, counted from 0, nil and staying put without one
+fn walk_to_child(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let id = args.next_u64()?;
+    let n = args.next_u64()? as usize;
+    args.done()?;
+
+    walk_move(ctx, id, |cursor, node| match node.iter().nth(n) {
+        Some(child) => {
+            let mut path = cursor.path.clone();
+            path.push(n);
+            WalkStep::To(path, WalkVisit::arriving(child))
+        },
+        None => WalkStep::Stay,
+    })
+}
+
+// up to the end of the parent of the node under visit, nil and staying put where the walk started
+fn walk_to_parent(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let id = args.next_u64()?;
+    args.done()?;
+
+    walk_move(ctx, id, |cursor, _| {
+        if cursor.path == cursor.origin {
+            return WalkStep::Stay
+        }
+        let mut path = cursor.path.clone();
+        path.pop();
+        WalkStep::To(path, WalkVisit::End)
+    })
+}
+
+
+// every value of the tree in preorder, postorder or level by level
+fn flatten(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let order = args.next().map_or("PRE".to_string(), |order| order.to_uppercase());
+    args.done()?;
+    if !matches!(order.as_str(), "PRE" | "POST" | "BFS") {
+        return Err(Error::from(format!("unknown order {}", order)).into())
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let root = value.data.root();
+    let values = match order.as_str() {
+        "PRE" => NodeWalk::from(root).filter_map(|visit| match visit {
+            walk::Visit::Begin(node) | walk::Visit::Leaf(node) => Some(node.data()),
+            walk::Visit::End(_) => None,
+        }).collect(),
+        "POST" => NodeWalk::from(root).filter_map(|visit| match visit {
+            walk::Visit::End(node) | walk::Visit::Leaf(node) => Some(node.data()),
+            walk::Visit::Begin(_) => None,
+        }).collect(),
+        _ => root.bfs().iter.map(|visit| visit.data).collect::<Vec<_>>(),
+    };
+    Reply::array(values.into_iter().filter(|data| !data.is_hidden_root())).send(ctx)
+}
+
+
+fn del_tree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(_) => {
+            key.delete()?;
+            ctx.replicate_verbatim();
+            REDIS_OK
+        }
+        None => reply::no_key(),
+    }
+}
+
+fn del_subtree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    // the root has nothing to be detached from, removing it removes the whole tree,
+    // as removing the last root of a forest removes the forest
+    let node = value.node(&node_data)?;
+    if node.parent().is_none_or(|parent| parent.data().is_hidden_root() && parent.degree() == 1) {
+        let removed = value.to_string();
+        key.delete()?;
+        ctx.replicate_verbatim();
+        return Ok(removed.into())
+    }
+    let removed = value.node_mut(&node_data)?.detach();
+    value.unindex_subtree(removed.root());
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(tree_string(removed.root()).into())
+}
+
+fn del_children(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let mut node = value.node_mut(&node_data)?;
+            // a leaf has nothing to give up
+            if node.has_no_child() {
+                return Ok(reply::integer(0))
+            }
+            let removed = node.node_count() - 1;
+            let children = std::iter::from_fn(|| node.pop_front()).collect::<Vec<_>>();
+            for child in &children {
+                value.unindex_subtree(child.root());
+            }
+            value.version += 1;
+            ctx.replicate_verbatim();
+            Ok(reply::integer(removed))
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// removes every node matching the pattern together with its subtree, with LEAVESONLY the matching
+// leaves alone, and replies how many nodes went. A matching root takes the tree, and the key, along
+fn prune(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let pattern = glob_units(&args.next_bytes()?).collect::<Vec<_>>();
+
+    let mut leaves_only = false;
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "LEAVESONLY" => leaves_only = true,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let case_insensitive = value.case_insensitive;
+    let mut chars = Vec::new();
+    let mut matches = |node: &Node<NodeData>| {
+        chars.clear();
+        chars.extend(glob_units(&node.data().value));
+        !node.data().is_hidden_root() && (!leaves_only || node.has_no_child()) && glob_match(&pattern, &chars, case_insensitive)
+    };
+    if matches(value.data.root()) {
+        let removed = value.data.root().node_count();
+        key.delete()?;
+        ctx.replicate_verbatim();
+        return Ok(reply::integer(removed))
+    }
+    // in preorder, a match below one found before goes along with that one
+    let mut found = HashSet::new();
+    let mut pruned = Vec::new();
+    for node in value.data.root().locate_all_by(|_| true) {
+        if matches(node) && !std::iter::successors(node.parent(), |n| n.parent()).any(|n| found.contains(&(n as *const _))) {
+            found.insert(node as *const _);
+            pruned.push(NonNull::from(node));
+        }
+    }
+    let mut removed = 0;
+    for node in pruned {
+        let sub_tree = unsafe { Pin::new_unchecked(&mut *node.as_ptr()) }.detach();
+        removed += sub_tree.root().node_count();
+        value.unindex_subtree(sub_tree.root());
+    }
+    // a forest which lost every root goes, as a tree which lost its root does
+    if value.forest() && value.data.root().has_no_child() {
+        key.delete()?;
+        ctx.replicate_verbatim();
+        return Ok(reply::integer(removed))
+    }
+    if removed > 0 {
+        value.version += 1;
+        ctx.replicate_verbatim();
+    }
+    Ok(reply::integer(removed))
+}
+
+
+// gives the subtree under the node a deadline, seconds from now or PXAT a unix time in milliseconds,
+// in place of any it had. With PATH the node is named by its path from the root. Replicated in the
+// PXAT form, replicas hold the deadline the master computed
+fn expire_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key_name = args.next_key()?;
+    let key = ctx.open_key_writable(&key_name);
+    let node_data = args.next_bytes()?;
+    let at = match args.next_string()? {
+        arg if arg.eq_ignore_ascii_case("PXAT") => args.next_i64()?,
+        seconds => seconds.parse::<i64>().ok()
+            .and_then(|seconds| seconds.checked_mul(1000)?.checked_add(now_ms()))
+            .ok_or_else(|| Error::from(format!("invalid expire time {}", seconds)))?,
+    };
+
+    let mut by_path = false;
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "PATH" => by_path = true,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = NonNull::from(if by_path { value.node_at_path(&node_data)? } else { value.node(&node_data)? });
+    value.expires.set(node, at);
+    value.version += 1;
+    let at = at.to_string();
+    let mut replicated = vec![key_name.as_bytes(), &node_data, b"PXAT", at.as_bytes()];
+    if by_path {
+        replicated.push(b"PATH");
+    }
+    replicate_bytes(ctx, "tree.expire_node", &replicated);
+    // a deadline in the past takes the subtree out right away, where this server may, through the
+    // handle held here. It still replies 1, as EXPIRE does with a time in the past
+    let now = now_ms();
+    if may_expire(ctx) && value.expires.due(now).is_some() {
+        purge_due(ctx, &key, &key_name, now)?;
+    }
+    Ok(reply::integer(1))
+}
+
+// seconds until the subtree under the node goes, -1 when it has no deadline
+fn ttl_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = value.node(&node_data)?;
+            Ok(match value.expires.get(NonNull::from(node)) {
+                // rounded as TTL does, and never below zero on replicas waiting for the master
+                Some(at) => RedisValue::Integer(((at - now_ms()).max(0) + 500) / 1000),
+                None => RedisValue::Integer(-1),
+            })
+        },
+        None => reply::no_key(),
+    }
+}
+
+// takes the deadline off the node, replies 1 when it had one
+fn persist_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = NonNull::from(value.node(&node_data)?);
+            let removed = value.expires.remove(node).is_some();
+            if removed {
+                value.version += 1;
+                ctx.replicate_verbatim();
+            }
+            Ok(RedisValue::Integer(removed as i64))
+        },
+        None => reply::no_key(),
+    }
+}
+
+// takes out the subtrees whose deadline is no later than unix-ms, now when it is left out, and
+// replies how many nodes went. What masters send replicas and the AOF when they expire nodes
+fn purge_expired(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key_name = args.next_key()?;
+    let key = ctx.open_key_writable(&key_name);
+    let at = match args.peek() {
+        Some(arg) if !arg.eq_ignore_ascii_case("IFVERSION") => args.next_i64()?,
+        _ => now_ms(),
+    };
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let (removed, root) = value.purge_expired(at);
+    if root {
+        key.delete()?;
+    }
+    if removed > 0 {
+        raw::replicate(raw_context(ctx), "tree.purge_expired", &[&key_name, &at.to_string()]);
+    }
+    Ok(reply::integer(removed))
+}
+
+
+fn set_tail_child(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    // let path = args.next_string()?.split(".").map(|v| v.to_string()).collect::<Vec<String>>();
+    let mut sub_trees = vec![Format::String.parse_tree(&args.next_bytes()?)?];
+
+    // every subtree is parsed before the tree is touched, the position goes last
+    enum Position { Back, Front, Before(Vec<u8>), After(Vec<u8>) }
+    let mut position = Position::Back;
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "FRONT" => position = Position::Front,
+            "BEFORE" => position = Position::Before(args.next_bytes()?),
+            "AFTER" => position = Position::After(args.next_bytes()?),
+            "IFVERSION" => {
+                if_version = Some(next_if_version(&mut args)?);
+                continue
+            },
+            _ => {
+                sub_trees.push(Format::String.parse_tree(&arg)?);
+                continue
+            },
+        }
+        if let Some(version) = trailing_if_version(&mut args)? {
+            if_version = Some(version);
+        }
+        break
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let sibling_matcher = match &position {
+        Position::Before(sibling) | Position::After(sibling) => Some(value.matcher(sibling)),
+        _ => None,
+    };
+    let nodes = || sub_trees.iter().flat_map(|sub_tree| sub_tree.root().locate_all_by(|_| true)).map(|node| node.data().value.as_slice());
+    value.check_unique(nodes())?;
+    let node_count = value.node_count() + sub_trees.iter().map(|sub_tree| sub_tree.root().node_count()).sum::<usize>();
+    let depth = child_depth(value.node(&node_data)?) + sub_trees.iter().map(|sub_tree| sub_tree.root().height()).max().unwrap();
+    limits().check(node_count, depth, nodes())?;
+    let added = sub_trees.iter().map(|sub_tree| NonNull::from(sub_tree.root())).collect::<Vec<_>>();
+    let mut node = value.node_mut(&node_data)?;
+    // the subtrees end up next to each other in the order they were given
+    match position {
+        Position::Back => sub_trees.into_iter().for_each(|sub_tree| node.push_back(sub_tree)),
+        Position::Front => sub_trees.into_iter().rev().for_each(|sub_tree| node.push_front(sub_tree)),
+        Position::Before(ref sibling) | Position::After(ref sibling) => {
+            let matches = sibling_matcher.unwrap();
+            let mut sibling_node = match node.iter_mut().find(|child| matches(child.data())) {
+                Some(sibling_node) => sibling_node,
+                None => return Err(Error::from(format!("{} is not a child of {}", display_value(sibling), display_value(&node_data))).into()),
+            };
+            if let Position::Before(_) = position {
+                sub_trees.into_iter().for_each(|sub_tree| sibling_node.insert_prev_sib(sub_tree))
+            } else {
+                sub_trees.into_iter().rev().for_each(|sub_tree| sibling_node.insert_next_sib(sub_tree))
+            }
+        },
+    }
+    for sub_tree in added {
+        value.index_subtree(sub_tree);
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    REDIS_OK
+}
+
+
+fn append_leaves(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let path = args.next_bytes()?;
+    let if_version = leading_if_version(&mut args)?;
+    let mut labels = args.map(|label| label.0).collect::<Vec<_>>();
+
+    let dedup = labels.len() > 1 && labels.last().unwrap().eq_ignore_ascii_case(b"DEDUP");
+    if dedup {
+        labels.pop();
+    }
+    if labels.is_empty() {
+        return Err(RedisError::WrongArity)
+    }
+    for label in &labels {
+        check_node_data(label)?;
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let case_insensitive = value.case_insensitive;
+    let parent = value.node_at_path(&path)?;
+
+    // values as they are compared, duplicates of the children and of earlier labels are dropped
+    let comparable = |v: &[u8]| if case_insensitive { lowercase(v) } else { v.to_vec() };
+    let mut seen = HashSet::new();
+    if dedup {
+        seen.extend(parent.iter().map(|child| comparable(&child.data().value)));
+    }
+    let mut leaves = Forest::new();
+    for label in labels {
+        if !dedup || seen.insert(comparable(&label)) {
+            leaves.push_back(Tree::new(NodeData::new(label)));
+        }
+    }
+
+    value.check_unique(leaves.iter().map(|leaf| leaf.data().value.as_slice()))?;
+    let node_count = value.node_count() + leaves.iter().count();
+    limits().check(node_count, child_depth(parent), leaves.iter().map(|leaf| leaf.data().value.as_slice()))?;
+    let added = leaves.iter().map(NonNull::from).collect::<Vec<_>>();
+    value.node_at_path_mut(&path)?.append(leaves);
+    for leaf in &added {
+        value.index_subtree(*leaf);
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(added.len()))
+}
+
+
+// walks the path down from the root, making the nodes missing at its end as one branch
+fn add_path(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let path = args.next_bytes()?;
+    let if_version = trailing_if_version(&mut args)?;
+    let segments = split_path(&path);
+    if segments.iter().any(|node_data| check_node_data(node_data).is_err()) {
+        return Err(Error::from(format!("invalid path {}", display_value(&path))).into())
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    // on a forest the path may start at a new root
+    let mut node = value.data.root();
+    let mut existing = 0;
+    if !value.forest() {
+        if !value.matcher(&segments[0])(node.data()) {
+            return Err(Error::from(format!("path {} does not start at the root {}", display_value(&path), node.data())).into())
+        }
+        existing = 1;
+    }
+    for node_data in &segments[existing..] {
+        let matches = value.matcher(node_data);
+        match node.iter().find(|child| matches(child.data())) {
+            Some(child) => node = child,
+            None => break,
+        }
+        existing += 1;
+    }
+    let missing = &segments[existing..];
+    value.check_unique(missing.iter().map(Vec::as_slice))?;
+    if !missing.is_empty() {
+        limits().check(value.node_count() + missing.len(), child_depth(node) + missing.len() - 1, missing.iter().map(Vec::as_slice))?;
+    }
+
+    if let Some((first, rest)) = missing.split_first() {
+        let branch = Tree::new(NodeData::new(first.clone()));
+        let mut tail = NonNull::from(branch.root());
+        for node_data in rest {
+            let sub_tree = Tree::new(NodeData::new(node_data.clone()));
+            let next = NonNull::from(sub_tree.root());
+            unsafe { &mut *tail.as_ptr() }.push_back(sub_tree);
+            tail = next;
+        }
+        let parent = NonNull::from(node);
+        let added = NonNull::from(branch.root());
+        unsafe { &mut *parent.as_ptr() }.push_back(branch);
+        value.index_subtree(added);
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(missing.len()))
+}
+
+
+// moves the whole tree at src_key under a node of the tree at dst_key, where it follows the rules
+// of its new tree. Replies how many nodes were grafted, nil when either key is missing
+fn graft(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let dst_name = args.next_key()?;
+    let node_data = args.next_bytes()?;
+    let src_name = args.next_key()?;
+
+    let mut keep_src = false;
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "KEEPSRC" => keep_src = true,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    if dst_name == src_name {
+        return Err(Error::from("a tree can not be grafted onto itself").into())
+    }
+
+    let dst_key = ctx.open_key_writable(&dst_name);
+    check_if_version(ctx, dst_key.get_value(&TREE_TYPE), if_version)?;
+    let src_key = ctx.open_key_writable(&src_name);
+    let (dst, src) = match (dst_key.get_value::<RedisTreeType>(&TREE_TYPE)?, src_key.get_value::<RedisTreeType>(&TREE_TYPE)?) {
+        (Some(dst), Some(src)) => (dst, src),
+        _ => return reply::no_key(),
+    };
+    dst.check_unique(src.data.root().locate_all_by(|data| !data.is_hidden_root()).map(|node| node.data().value.as_slice()))?;
+    limits().check_subtree(dst.node_count(), dst.node(&node_data)?, src.data.root())?;
+
+    // the source tree moves over as it is, deadlines too, its key goes away right after holding a bare leaf.
+    // A forest moves over as its roots
+    let (mut grafted, expires) = if keep_src {
+        (src.data.root().deep_clone(), Vec::new())
+    } else {
+        let moved = std::mem::replace(src, RedisTreeType::new(Tree::new(NodeData::new(Vec::new())), false));
+        let expires = moved.expires.deadlines.iter().map(|(&node, &at)| (node, at)).collect::<Vec<_>>();
+        (moved.data, expires)
+    };
+    let roots = match grafted.root().data().is_hidden_root() {
+        true => std::iter::from_fn(|| grafted.pop_front()).collect(),
+        false => vec![grafted],
+    };
+    let mut node_count = 0;
+    for root in roots {
+        let added = NonNull::from(root.root());
+        node_count += root.root().node_count();
+        dst.node_mut(&node_data)?.push_back(root);
+        dst.index_subtree(added);
+    }
+    for (node, at) in expires {
+        dst.expires.set(node, at);
+    }
+    if !keep_src {
+        src_key.delete()?;
+    }
+    dst.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(node_count))
+}
+
+
+// deep clones the subtree under a node and adds it as the last child of dst_parent, or without
+// one makes it the tree of a new dst_key with the flags of the source. Both keys may be the same.
+// Replies how many nodes were copied, nil when a key is missing
+fn copy_subtree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let src_name = args.next_key()?;
+    let node_data = args.next_bytes()?;
+    let dst_name = args.next_key()?;
+    let dst_parent = args.next_if(|arg| !arg.eq_ignore_ascii_case("IFVERSION"));
+    let if_version = trailing_if_version(&mut args)?;
+
+    let (copy, case_insensitive, unique) = {
+        let src_key = ctx.open_key(&src_name);
+        let src = match src_key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+            Some(src) => src,
+            None => return reply::no_key(),
+        };
+        (src.node(&node_data)?.deep_clone(), src.case_insensitive, src.unique)
+    };
+    let node_count = copy.root().node_count();
+
+    let dst_key = ctx.open_key_writable(&dst_name);
+    check_if_version(ctx, dst_key.get_value(&TREE_TYPE), if_version)?;
+    match dst_parent {
+        Some(dst_parent) => {
+            let dst = match dst_key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+                Some(dst) => dst,
+                None => return reply::no_key(),
+            };
+            dst.check_unique(copy.root().locate_all_by(|_| true).map(|node| node.data().value.as_slice()))?;
+            limits().check_subtree(dst.node_count(), dst.node(&dst_parent)?, copy.root())?;
+            let added = NonNull::from(copy.root());
+            dst.node_mut(&dst_parent)?.push_back(copy);
+            dst.index_subtree(added);
+            dst.version += 1;
+        },
+        None => {
+            if !dst_key.is_empty() {
+                return Err(Error::from(format!("{} already exists", dst_name)).into())
+            }
+            limits().check_tree(&copy)?;
+            let mut value = RedisTreeType::new(copy, case_insensitive);
+            value.unique = unique;
+            dst_key.set_value(&TREE_TYPE, value)?;
+        },
+    }
+    ctx.replicate_verbatim();
+    Ok(reply::integer(node_count))
+}
+
+
+fn dumpnode(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(dump_node(value.node(&node_data)?).into()),
+        None => reply::no_key(),
+    }
+}
+
+
+// attaches a subtree from tree.dumpnode as the last child of parent, attributes and all
+fn restorenode(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let parent_data = args.next_bytes()?;
+    let sub_tree = restore_node(&args.next_string()?)?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    value.check_unique(sub_tree.root().locate_all_by(|_| true).map(|node| node.data().value.as_slice()))?;
+    limits().check_subtree(value.node_count(), value.node(&parent_data)?, sub_tree.root())?;
+    let node_count = sub_tree.root().node_count();
+    let added = NonNull::from(sub_tree.root());
+    value.node_mut(&parent_data)?.push_back(sub_tree);
+    value.index_subtree(added);
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(node_count))
+}
+
+
+// puts a new node where the node is and the node under it, wrapping the root makes a new root
+fn wrap_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let new_data = args.next_bytes()?;
+    check_node_data(&new_data)?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = NonNull::from(value.node(&node_data)?);
+    value.check_unique(std::iter::once(new_data.as_slice()))?;
+    // the wrapped subtree goes a level down
+    let wrapped = unsafe { node.as_ref() };
+    let depth = value.height().max(child_depth(wrapped) + wrapped.height());
+    limits().check(value.node_count() + 1, depth, std::iter::once(new_data.as_slice()))?;
+    let wrapper = Tree::new(NodeData::new(new_data));
+    // indexed while alone, the nodes it is about to wrap keep their places in the index
+    let added = NonNull::from(wrapper.root());
+    value.index_subtree(added);
+    let mut node = unsafe { Pin::new_unchecked(&mut *node.as_ptr()) };
+    if node.parent().is_none() {
+        let root = std::mem::replace(&mut value.data, wrapper);
+        value.data.push_back(root);
+    } else {
+        node.insert_prev_sib(wrapper);
+        let sub_tree = node.detach();
+        unsafe { &mut *added.as_ptr() }.push_back(sub_tree);
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    REDIS_OK
+}
+
+
+// removes the node alone, its children take its place among its siblings in their order
+fn collapse_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    if value.node(&node_data)?.parent().is_none() {
+        return Err(Error::from("the root can not be collapsed").into())
+    }
+    let mut node = value.node_mut(&node_data)?;
+    let promoted = node.degree();
+    while let Some(child) = node.pop_front() {
+        node.insert_prev_sib(child);
+    }
+    let removed = node.detach();
+    value.unindex_node(removed.root());
+    // collapsing the last root of a forest when it is a leaf leaves no node
+    if value.forest() && value.data.root().has_no_child() {
+        key.delete()?;
+        ctx.replicate_verbatim();
+        return Ok(reply::integer(promoted))
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(promoted))
+}
+
+
+fn sort_children(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let (mut desc, mut numeric, mut recursive) = (false, false, false);
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "DESC" => desc = true,
+            "NUMERIC" => numeric = true,
+            "RECURSIVE" => recursive = true,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let number = |value: &[u8]| std::str::from_utf8(value).ok()?.parse::<f64>().ok();
+    if numeric {
+        // every value to be compared is checked before anything moves
+        let node = value.node(&node_data)?;
+        let sorted = if recursive { node.locate_all_by(|_| true).skip(1).collect() } else { node.iter().collect::<Vec<_>>() };
+        if let Some(node) = sorted.iter().find(|node| number(&node.data().value).is_none()) {
+            return Err(Error::from(format!("{} is not a number", node.data())).into())
+        }
+    }
+    let case_insensitive = value.case_insensitive;
+    let order = move |a: &NodeData, b: &NodeData| {
+        let ordering = if numeric {
+            number(&a.value).unwrap().total_cmp(&number(&b.value).unwrap())
+        } else if case_insensitive {
+            lowercase(&a.value).cmp(&lowercase(&b.value))
+        } else {
+            a.value.cmp(&b.value)
+        };
+        if desc { ordering.reverse() } else { ordering }
+    };
+    // children already in order leave the tree as it was
+    if value.node_mut(&node_data)?.sort_children_by(recursive, order) {
+        value.version += 1;
+        ctx.replicate_verbatim();
+    }
+    REDIS_OK
+}
+
+
+// repositions a child among its siblings, the child and its subtree stay the same nodes
+fn move_child(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let parent_data = args.next_bytes()?;
+    let child_data = args.next_bytes()?;
+
+    enum Position { Index(usize), Before(Vec<u8>), After(Vec<u8>) }
+    let position = match args.next_string()?.to_uppercase().as_str() {
+        "INDEX" => Position::Index(args.next_u64()? as usize),
+        "BEFORE" => Position::Before(args.next_bytes()?),
+        "AFTER" => Position::After(args.next_bytes()?),
+        arg => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+    };
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let parent = value.node(&parent_data)?;
+    let child_index = |node_data: &[u8]| {
+        let matches = value.matcher(node_data);
+        parent.iter().position(|child| matches(child.data()))
+            .ok_or_else(|| Error::from(format!("{} is not a child of {}", display_value(node_data), display_value(&parent_data))))
+    };
+    let from = child_index(&child_data)?;
+    // where the child goes among the siblings left once it is taken out
+    let to = match position {
+        Position::Index(index) if index < parent.degree() => index,
+        Position::Index(_) => return Err(Error::from("index out of range").into()),
+        Position::Before(ref sibling) | Position::After(ref sibling) => {
+            let at = child_index(sibling)?;
+            let after = matches!(position, Position::After(_)) as usize;
+            match at.cmp(&from) {
+                std::cmp::Ordering::Equal => from,
+                std::cmp::Ordering::Greater => at - 1 + after,
+                std::cmp::Ordering::Less => at + after,
+            }
+        },
+    };
+
+    let mut parent = value.node_mut(&parent_data)?;
+    let child = parent.iter_mut().nth(from).unwrap().detach();
+    match to {
+        0 => parent.push_front(child),
+        to => parent.iter_mut().nth(to - 1).unwrap().insert_next_sib(child),
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    REDIS_OK
+}
+
+
+fn rename_node(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let old_data = args.next_bytes()?;
+    let new_data = args.next_bytes()?;
+    check_node_data(&new_data)?;
+
+    let mut all = false;
+    let mut if_version = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "ALL" => all = true,
+            "IFVERSION" => if_version = Some(next_if_version(&mut args)?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    // ALL renames whatever matches, possibly nothing, which changes nothing and is not replicated.
+    // A single rename needs the node to be there
+    if all {
+        let renamed = value.indexed(&old_data).to_vec();
+        if renamed.is_empty() {
+            return Ok(reply::integer(0))
+        }
+        value.check_unique_rename(&old_data, &new_data)?;
+        limits().check(0, 0, std::iter::once(new_data.as_slice()))?;
+        for node in &renamed {
+            value.relabel(*node, new_data.clone());
+        }
+        value.version += 1;
+        ctx.replicate_verbatim();
+        return Ok(reply::integer(renamed.len()))
+    }
+    let node = value.locate_ptr(&old_data).ok_or_else(|| reply::no_node(&old_data))?;
+    value.check_unique_rename(&old_data, &new_data)?;
+    limits().check(0, 0, std::iter::once(new_data.as_slice()))?;
+    value.relabel(node, new_data);
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(1))
+}
+
+
+// replies how many of the fields are new, like HSET
+fn set_attr(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let if_version = leading_if_version(&mut args)?;
+    let pairs = args.map(Arg::into_string).collect::<Result<Vec<_>, _>>()?;
+    if pairs.is_empty() || pairs.len() % 2 == 1 {
+        return Err(RedisError::WrongArity)
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let mut node = value.node_mut(&node_data)?;
+    let mut pairs = pairs.into_iter();
+    let mut added = 0;
+    while let (Some(field), Some(attribute)) = (pairs.next(), pairs.next()) {
+        added += node.data_mut().set_attribute(field, attribute) as usize;
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(added))
+}
+
+
+// one field, or every field and value of the node as a map like HGETALL
+fn get_attr(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let field = args.next().map(Arg::into_string).transpose()?;
+    args.done()?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let data = value.node(&node_data)?.data();
+    match field {
+        Some(field) => Ok(data.attribute(&field).into()),
+        None => Reply::Map(data.attributes().map(|(field, value)| (field.clone(), RedisValue::from(value).into())).collect()).send(ctx),
+    }
+}
+
+
+// replies how many of the fields were there
+fn del_attr(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let if_version = leading_if_version(&mut args)?;
+    let fields = args.map(Arg::into_string).collect::<Result<Vec<_>, _>>()?;
+    if fields.is_empty() {
+        return Err(RedisError::WrongArity)
+    }
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let mut node = value.node_mut(&node_data)?;
+    let removed = fields.iter().filter(|field| node.data_mut().remove_attribute(field)).count();
+    if removed > 0 {
+        value.version += 1;
+        ctx.replicate_verbatim();
+    }
+    Ok(reply::integer(removed))
+}
+
+
+// [path, value] of the field for the node and every node below it which has the field, in preorder,
+// the paths running from the root as WITHPATH prints them
+fn node_hgetall_subtree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let field = args.next_string()?;
+    args.done()?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let pairs = node_paths(value.node(&node_data)?, PATH_SEPARATOR).filter_map(|(path, node)| {
+        let attribute = node.data().attribute(&field)?;
+        Some(Reply::Array(vec![path.into(), RedisValue::from(attribute).into()]))
+    });
+    Reply::array(pairs).send(ctx)
+}
+
+
+// {"path": {"field": "value" or null, ..}, ..} patching the attributes of many nodes at once, null
+// removing the field. Every path is checked before any node changes, replies how many were patched
+fn node_hmset_bulk(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let patches = json_patches(&args.next_string()?)?;
+    let if_version = trailing_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let nodes = patches.iter()
+        .map(|(path, _)| value.node_at_path(path.as_bytes()).map(NonNull::from))
+        .collect::<Result<Vec<_>, _>>()?;
+    if nodes.is_empty() {
+        return Ok(reply::integer(0))
+    }
+    for (node, (_, fields)) in nodes.iter().zip(patches) {
+        let data = unsafe { &mut *node.as_ptr() }.data_mut();
+        for (field, attribute) in fields {
+            match attribute {
+                Some(attribute) => data.set_attribute(field, attribute),
+                None => data.remove_attribute(&field),
+            };
+        }
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(nodes.len()))
+}
+
+
+// the child at the end of the edge from the parent, the first of its value among the children
+fn edge_child<'a>(value: &'a RedisTreeType, parent_data: &[u8], child_data: &[u8]) -> Result<&'a Node<NodeData>, RedisError> {
+    let matches = value.matcher(child_data);
+    value.node(parent_data)?.iter().find(|child| matches(child.data()))
+        .ok_or_else(|| Error::from(format!("{} is not a child of {}", display_value(child_data), display_value(parent_data))).into())
+}
+
+// replies 1 when the edge had no weight yet, 0 when it replaced one
+fn set_weight(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let parent_data = args.next_bytes()?;
+    let child_data = args.next_bytes()?;
+    let weight = args.next_string()?;
+    let if_version = trailing_if_version(&mut args)?;
+    let weight = weight.parse::<f64>().ok().filter(|weight| weight.is_finite())
+        .ok_or_else(|| Error::from(format!("invalid weight {}", weight)))?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let child = NonNull::from(edge_child(value, &parent_data, &child_data)?);
+    let previous = unsafe { &mut *child.as_ptr() }.data_mut().weight.replace(weight);
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(previous.is_none() as usize))
+}
+
+
+fn get_weight(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let parent_data = args.next_bytes()?;
+    let child_data = args.next_bytes()?;
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(edge_child(value, &parent_data, &child_data)?.data().weight.map_or(RedisValue::Null, number_reply)),
+        None => reply::no_key(),
+    }
+}
+
+
+// puts a subtree taken out of the tree back among the children of `parent` at `index`
+fn insert_child(parent: NonNull<Node<NodeData>>, index: usize, tree: Tree<NodeData>) {
+    let parent = unsafe { &mut *parent.as_ptr() };
+    match index.checked_sub(1).and_then(|before| parent.iter_mut().nth(before)) {
+        Some(before) => unsafe { Pin::get_unchecked_mut(before) }.insert_next_sib(tree),
+        None => parent.push_front(tree),
+    }
+}
+
+// what takes an applied edit back, for a script failing on a later one
+enum Undo {
+    Insert(NonNull<Node<NodeData>>),
+    Delete { parent: NonNull<Node<NodeData>>, index: usize, removed: Tree<NodeData>, deadlines: Vec<(NonNull<Node<NodeData>>, i64)> },
+    Relabel { node: NonNull<Node<NodeData>>, old_data: Vec<u8> },
+    Move { node: NonNull<Node<NodeData>>, parent: NonNull<Node<NodeData>>, index: usize },
+}
+
+impl Undo {
+    fn undo(self, value: &mut RedisTreeType) {
+        match self {
+            Undo::Insert(added) => {
+                let added = unsafe { &mut *added.as_ptr() }.detach();
+                value.unindex_subtree(added.root());
+            },
+            Undo::Delete { parent, index, removed, deadlines } => {
+                let restored = NonNull::from(removed.root());
+                insert_child(parent, index, removed);
+                value.index_subtree(restored);
+                for (node, at) in deadlines {
+                    value.expires.set(node, at);
+                }
+            },
+            Undo::Relabel { node, old_data } => value.relabel(node, old_data),
+            Undo::Move { node, parent, index } => {
+                let moved = unsafe { &mut *node.as_ptr() }.detach();
+                insert_child(parent, index, moved);
+            },
+        }
+    }
+}
+
+// edit script operations, nodes addressed by root-to-node paths as WITHPATH prints them.
+// An edit that fails leaves the tree as it was, one that succeeds says how to take it back
+fn apply_edit(value: &mut RedisTreeType, op: &Arg, args: &mut impl Iterator<Item = Arg>) -> Result<Undo, RedisError> {
+    match op.to_uppercase().as_str() {
+        "INSERT" => {
+            let parent_path = args.next_bytes()?;
+            let sub_tree = Format::String.parse_tree(&args.next_bytes()?)?;
+            value.check_unique(sub_tree.root().locate_all_by(|_| true).map(|node| node.data().value.as_slice()))?;
+            limits().check_subtree(value.node_count(), value.node_at_path(&parent_path)?, sub_tree.root())?;
+            let added = NonNull::from(sub_tree.root());
+            value.node_at_path_mut(&parent_path)?.push_back(sub_tree);
+            value.index_subtree(added);
+            Ok(Undo::Insert(added))
+        },
+        "DELETE" => {
+            let path = args.next_bytes()?;
+            let node = value.node_at_path(&path)?;
+            let parent = NonNull::from(node_parent(node).ok_or_else(|| Error::from("the root can not be deleted"))?);
+            let index = node.child_index().unwrap();
+            let deadlines = node.locate_all_by(|_| true)
+                .filter_map(|node| Some((NonNull::from(node), value.expires.get(NonNull::from(node))?)))
+                .collect();
+            let removed = value.locate_by_path_mut(&path).unwrap().detach();
+            value.unindex_subtree(removed.root());
+            Ok(Undo::Delete { parent, index, removed, deadlines })
+        },
+        "RELABEL" => {
+            let path = args.next_bytes()?;
+            let new_data = args.next_bytes()?;
+            check_node_data(&new_data)?;
+            let node = value.node_at_path(&path)?;
+            value.check_unique_rename(&node.data().value, &new_data)?;
+            limits().check(0, 0, std::iter::once(new_data.as_slice()))?;
+            let old_data = node.data().value.clone();
+            let node = NonNull::from(node);
+            value.relabel(node, new_data);
+            Ok(Undo::Relabel { node, old_data })
+        },
+        "MOVE" => {
+            let path = args.next_bytes()?;
+            let parent_path = args.next_bytes()?;
+            let node = value.node_at_path(&path)?;
+            let parent = NonNull::from(node_parent(node).ok_or_else(|| Error::from("the root can not be moved"))?);
+            let index = node.child_index().unwrap();
+            // a parent inside the moved subtree fails below, once it is out of the way
+            if let Ok(new_parent) = value.node_at_path(&parent_path) {
+                limits().check(0, child_depth(new_parent) + node.height(), None)?;
+            }
+            let node = NonNull::from(node);
+            // the same nodes end up elsewhere, the index has nothing to change
+            let sub_tree = value.locate_by_path_mut(&path).unwrap().detach();
+            // a parent inside the moved subtree is gone by now, the subtree then goes back
+            match value.node_at_path_mut(&parent_path) {
+                Ok(mut new_parent) => new_parent.push_back(sub_tree),
+                Err(e) => {
+                    insert_child(parent, index, sub_tree);
+                    return Err(e)
+                },
+            }
+            Ok(Undo::Move { node, parent, index })
+        },
+        _ => Err(Error::from(format!("unknown edit operation {}", op)).into()),
+    }
+}
+
+
+// applies the script in place, taking back the edits applied so far when one fails, so the tree
+// only changes when every step succeeded. An empty script changes nothing
+fn apply_diff(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1).peekable();
+    let key = ctx.open_key_writable(&args.next_key()?);
+    let if_version = leading_if_version(&mut args)?;
+    check_if_version(ctx, key.get_value(&TREE_TYPE), if_version)?;
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let mut applied = Vec::new();
+    while let Some(op) = args.next() {
+        match apply_edit(value, &op, &mut args) {
+            Ok(undo) => applied.push(undo),
+            Err(e) => {
+                for undo in applied.into_iter().rev() {
+                    undo.undo(value);
+                }
+                return Err(e)
+            },
+        }
+    }
+    if applied.is_empty() {
+        return Ok(reply::integer(0))
+    }
+    value.version += 1;
+    ctx.replicate_verbatim();
+    Ok(reply::integer(applied.len()))
+}
+
+
+// what changed from the tree `old` to the tree `new`, nodes named by their root-to-node paths:
+// [REMOVED, old_path] in the preorder of old, then [ADDED, new_path] and [MOVED, old_path, new_path]
+// in the preorder of new. A value found once in each tree matches across them wherever it is and is
+// MOVED when its parent does not match, the other nodes match by path. Values compare like in old
+fn tree_diff(old: &RedisTreeType, new: &RedisTreeType) -> Vec<Reply> {
+    let old_nodes = node_paths(old.data.root(), PATH_SEPARATOR).collect::<Vec<_>>();
+    let new_nodes = node_paths(new.data.root(), PATH_SEPARATOR).collect::<Vec<_>>();
+    let position = |nodes: &[(Vec<u8>, &Node<NodeData>)]| {
+        nodes.iter().enumerate().map(|(i, (_, node))| (*node as *const Node<NodeData>, i)).collect::<HashMap<_, _>>()
+    };
+    let (old_position, new_position) = (position(&old_nodes), position(&new_nodes));
+    let groups = |nodes: &[(Vec<u8>, &Node<NodeData>)]| {
+        let mut groups = HashMap::<Vec<u8>, Vec<usize>>::new();
+        for (i, (_, node)) in nodes.iter().enumerate() {
+            groups.entry(old.index_key(&node.data().value)).or_default().push(i);
+        }
+        groups
+    };
+    let new_groups = groups(&new_nodes);
 
-#[derive(Debug)]
-struct RedisTreeType {
-    data: Tree<String>,
-    // map:  HashMap<String, String>
+    let mut old_match = vec![None; old_nodes.len()];
+    let mut new_match = vec![None; new_nodes.len()];
+    let mut by_value = vec![false; new_nodes.len()];
+    for (node_data, olds) in groups(&old_nodes) {
+        if let ([i], Some([j])) = (olds.as_slice(), new_groups.get(&node_data).map(Vec::as_slice)) {
+            old_match[*i] = Some(*j);
+            new_match[*j] = Some(*i);
+            by_value[*j] = true;
+        }
+    }
+    let mut by_path = HashMap::<Vec<u8>, VecDeque<usize>>::new();
+    for (j, (path, _)) in new_nodes.iter().enumerate().filter(|(j, _)| new_match[*j].is_none()) {
+        by_path.entry(old.index_key(path)).or_default().push_back(j);
+    }
+    for (i, (path, _)) in old_nodes.iter().enumerate() {
+        if old_match[i].is_some() {
+            continue
+        }
+        if let Some(j) = by_path.get_mut(&old.index_key(path)).and_then(VecDeque::pop_front) {
+            old_match[i] = Some(j);
+            new_match[j] = Some(i);
+        }
+    }
+
+    let mut diff = Vec::new();
+    for ((path, _), _) in old_nodes.iter().zip(&old_match).filter(|(_, j)| j.is_none()) {
+        diff.push(Reply::Array(vec![RedisValue::from("REMOVED").into(), path.clone().into()]));
+    }
+    for (j, (path, node)) in new_nodes.iter().enumerate() {
+        let i = match new_match[j] {
+            Some(i) => i,
+            None => {
+                diff.push(Reply::Array(vec![RedisValue::from("ADDED").into(), path.clone().into()]));
+                continue
+            },
+        };
+        let old_parent = node_parent(old_nodes[i].1).map(|parent| old_position[&(parent as *const _)]);
+        let new_parent = node_parent(node).map(|parent| new_position[&(parent as *const _)]);
+        // among same valued siblings a node can change parent and keep its path, which shows nothing
+        let moved = old_parent.and_then(|parent| old_match[parent]) != new_parent && old_nodes[i].0 != *path;
+        if by_value[j] && moved {
+            diff.push(Reply::Array(vec![RedisValue::from("MOVED").into(), old_nodes[i].0.clone().into(), path.clone().into()]));
+        }
+    }
+    diff
 }
 
-impl RedisTreeType {
-    fn to_string(&self) -> String {
-        self.data.to_string()
+fn diff(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let old_key = ctx.open_key(&args.next_key()?);
+    let new_key = ctx.open_key(&args.next_key()?);
+    args.done()?;
+
+    match (old_key.get_value::<RedisTreeType>(&TREE_TYPE)?, new_key.get_value::<RedisTreeType>(&TREE_TYPE)?) {
+        (Some(old), Some(new)) => Reply::array(tree_diff(old, new)).send(ctx),
+        _ => reply::no_key(),
     }
 }
 
 
-#[allow(non_snake_case, unused)]
-pub extern "C" fn init(_: *mut raw::RedisModuleCtx) -> c_int {
-    raw::Status::Ok as c_int
+// with more than one node an array per node, nil for those not in the tree
+fn get_ancestors(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let mut limit = usize::MAX;
+    let mut root_first = false;
+    let mut with_self = false;
+    // NODES n names n more nodes, counted so that a node may be called like an option
+    let mut more = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "LIMIT" => limit = args.next_u64()? as usize,
+            "ROOTFIRST" => root_first = true,
+            "WITHSELF" => with_self = true,
+            "NODES" => {
+                let count = args.next_u64()? as usize;
+                let nodes = args.by_ref().take(count).collect::<Vec<_>>();
+                if nodes.len() < count {
+                    return Err(Error::from(format!("NODES expects {} nodes, got {}", count, nodes.len())).into())
+                }
+                more = Some(nodes);
+            },
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let ancestors = |node: &Node<NodeData>| {
+        // the nearest ones are kept, the node itself being the nearest of all
+        let mut ancestors = with_self.then_some(node.data()).into_iter()
+            .chain(node_ancestors(node).into_iter().map(|node| node.data()))
+            .take(limit)
+            .collect::<Vec<_>>();
+        if root_first {
+            ancestors.reverse();
+        }
+        Reply::array(ancestors)
+    };
+    let more = match more {
+        Some(more) => more,
+        None => return ancestors(value.node(&node_data)?).send(ctx),
+    };
+    let nodes = std::iter::once(node_data.as_slice()).chain(more.iter().map(|node_data| &node_data[..]));
+    Reply::array(nodes.map(|node_data| value.locate(node_data).map(ancestors))).send(ctx)
 }
 
-#[allow(non_snake_case, unused)]
-pub unsafe extern "C" fn rdb_load(rdb: *mut raw::RedisModuleIO, encver: c_int) -> *mut c_void {
-    if let Ok(tree) = Tree::try_from(raw::load_string(rdb)) {
-        Box::into_raw(Box::new(tree)) as *mut c_void
-    } else {
-        Box::into_raw(Box::new(Tree::new("rdb_load_fail"))) as *mut c_void
+
+// the nearest ancestor with a value matching the pattern, the node itself first with WITHSELF, or nil
+fn first_ancestor_match(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    let pattern = glob_units(&args.next_bytes()?).collect::<Vec<_>>();
+
+    let mut with_self = false;
+    for arg in args {
+        match arg.to_uppercase().as_str() {
+            "WITHSELF" => with_self = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
     }
 
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = value.node(&node_data)?;
+    let mut chars = Vec::new();
+    let found = with_self.then_some(node.data()).into_iter().chain(node_ancestors(node).into_iter().map(|node| node.data())).find(|data| {
+        chars.clear();
+        chars.extend(glob_units(&data.value));
+        glob_match(&pattern, &chars, value.case_insensitive)
+    });
+    Reply::from(found).send(ctx)
 }
 
-#[allow(non_snake_case, unused)]
-pub unsafe extern "C" fn rdb_save(rdb: *mut raw::RedisModuleIO, value: *mut c_void) {
-    let tree = (&*(value as *mut Tree<String>)).to_string();
-    raw::save_string(rdb, tree.as_str());
 
+fn lowest_common_ancestor(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_a = args.next_bytes()?;
+    let node_b = args.next_bytes()?;
 
-    // let tree = &*(value as *mut Tree<String>);
-    // let tree_string = tree.to_string();
-    // let c_str = CString::new(tree_string.as_str()).unwrap();
-    // raw::RedisModule_SaveStringBuffer.unwrap()(rdb, c_str.as_ptr() as *const c_char, tree_string.len());
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            // nodes under different roots of a forest have none
+            let lca = value.node(&node_a)?.lowest_common_ancestor(value.node(&node_b)?);
+            Reply::from(lca.map(|lca| lca.data()).filter(|lca| !lca.is_hidden_root())).send(ctx)
+        },
+        None => reply::no_key(),
+    }
 }
 
 
-#[allow(non_snake_case, unused)]
-pub unsafe extern "C" fn aof_rewrite(aof: *mut raw::RedisModuleIO, key: *mut raw::RedisModuleString, value: *mut c_void) {
-    // do nothing
+fn is_ancestor(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let ancestor = args.next_bytes()?;
+    let descendant = args.next_bytes()?;
+
+    let mut direct = false;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "DIRECT" => direct = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        if let Some(node) = value.locate(&descendant) {
+            let matches = value.matcher(&ancestor);
+            let found = if direct {
+                node.parent().map_or(false, |parent| matches(parent.data()))
+            } else {
+                node.ancestor_nodes().into_iter().any(|v| matches(v.data()))
+            };
+            return Ok(RedisValue::Integer(found as i64))
+        }
+    }
+    Ok(RedisValue::Integer(0))
 }
 
 
+// the same values in the same shape, walking both at once as the PartialEq of Node recurses once per level
+fn same_tree(a: &Node<NodeData>, b: &Node<NodeData>) -> bool {
+    a.node_count() == b.node_count() && NodeWalk::from(a).zip(NodeWalk::from(b)).all(|visits| match visits {
+        (walk::Visit::Begin(a), walk::Visit::Begin(b)) | (walk::Visit::Leaf(a), walk::Visit::Leaf(b)) => a.data() == b.data(),
+        (walk::Visit::End(_), walk::Visit::End(_)) => true,
+        _ => false,
+    })
+}
 
-#[allow(non_snake_case, unused)]
-pub unsafe extern "C" fn free(value: *mut c_void) {
-    Box::from_raw(value as *mut RedisTreeType);
+fn equals(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key_a = ctx.open_key(&args.next_key()?);
+    let key_b = ctx.open_key(&args.next_key()?);
+    let nodes = match args.next() {
+        Some(node_a) => Some((node_a, args.next_bytes()?)),
+        None => None,
+    };
+    args.done()?;
+
+    if let (Some(a), Some(b)) = (key_a.get_value::<RedisTreeType>(&TREE_TYPE)?, key_b.get_value::<RedisTreeType>(&TREE_TYPE)?) {
+        let (node_a, node_b) = match &nodes {
+            Some((node_a, node_b)) => (a.locate(node_a), b.locate(node_b)),
+            None => (Some(a.data.root()), Some(b.data.root())),
+        };
+        if let (Some(node_a), Some(node_b)) = (node_a, node_b) {
+            return Ok(RedisValue::Integer(same_tree(node_a, node_b) as i64))
+        }
+    }
+    Ok(RedisValue::Integer(0))
 }
 
 
-#[allow(non_snake_case, unused)]
-pub unsafe extern "C" fn aux_load(rdb: *mut raw::RedisModuleIO, encver: i32, when: i32) -> i32 {
-    raw::Status::Ok as i32
+fn get_path(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let from = args.next_bytes()?;
+    let to = args.next_bytes()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            // nodes under different roots of a forest have none
+            let path = value.node(&from)?.path_to(value.node(&to)?).filter(|path| !path.iter().any(|data| data.is_hidden_root()));
+            Reply::from(path.map(Reply::array)).send(ctx)
+        },
+        None => reply::no_key(),
+    }
 }
 
-#[allow(non_snake_case, unused)]
-pub unsafe extern "C" fn aux_save(rdb: *mut raw::RedisModuleIO, when: i32) {
+
+// FILTER TAG t keeps the nodes having attribute t, FILTER field=value the ones where it has that value,
+// WITHANCESTORS the nodes connecting them to the node asked for as well
+fn get_descendants(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let mut depth = None;
+    let mut filter: Option<(String, Option<String>)> = None;
+    let mut with_ancestors = false;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "DEPTH" => depth = Some(args.next_u64()? as usize),
+            "FILTER" => {
+                let condition = args.next_string()?;
+                filter = Some(if condition.eq_ignore_ascii_case("TAG") {
+                    (args.next_string()?, None)
+                } else {
+                    let mut parts = condition.splitn(2, '=');
+                    match (parts.next(), parts.next()) {
+                        (Some(field), Some(wanted)) if !field.is_empty() => (field.to_string(), Some(wanted.to_string())),
+                        _ => return Err(Error::from("FILTER takes TAG t or field=value").into()),
+                    }
+                });
+            },
+            "WITHANCESTORS" => with_ancestors = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    if with_ancestors && filter.is_none() {
+        return Err(Error::from("WITHANCESTORS needs FILTER").into())
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = value.node(&node_data)?;
+    let descendants = match depth {
+        Some(depth) => node.descendants_to_depth(depth),
+        None => node.descendants(),
+    };
+    let (field, wanted) = match filter {
+        Some(filter) => filter,
+        None => return Reply::array(descendants).send(ctx),
+    };
+
+    let top = node.depth();
+    let mut kept = HashSet::new();
+    let matched = node.locate_all_by(|data| match (data.attribute(&field), &wanted) {
+        (Some(found), Some(wanted)) => found == wanted,
+        (found, None) => found.is_some(),
+        _ => false,
+    });
+    for found in matched.filter(|found| depth.is_none_or(|depth| found.depth() - top <= depth)) {
+        kept.insert(found.data() as *const NodeData);
+        if with_ancestors {
+            let mut current = found;
+            while !ptr::eq(current, node) {
+                current = current.parent().unwrap();
+                kept.insert(current.data() as *const NodeData);
+            }
+        }
+    }
+    // the bfs order and the depth limit come from the unfiltered list
+    Reply::array(descendants.into_iter().filter(|data| kept.contains(&(*data as *const NodeData)))).send(ctx)
 }
 
 
-static TREE_TYPE: RedisType = RedisType::new(
-    "ReTreeYou",
-    0,
-    raw::RedisModuleTypeMethods {
-        version: raw::REDISMODULE_TYPE_METHOD_VERSION as u64,
-        rdb_load: Some(rdb_load),
-        rdb_save: Some(rdb_save),
-        aof_rewrite: None,
-        free: Some(free),
-        mem_usage: None,
-        digest: None,
-        aux_load: None,
-        aux_save: None,
-        aux_save_triggers: 0,
-    },
-)execution failed error during connect: Get http://%2Fvar%2Frun%2Fdocker.sock/v1.40/containers/he6j1i859tsdcm6s9ukcg8muicl3dxib/json: context canceled        {"stage-d": 9095, "rep;
+fn get_depth(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    args.done()?;
 
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(reply::integer(node_depth(value.node(&node_data)?))),
+        None => reply::no_key(),
+    }
+}
 
 
-fn init_tree(ctx: &Context, args: Vec<String>) -> RedisResult {
+fn get_height(ctx: &Context, args: Vec<Arg>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key_writable(&args.next_string()?);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
 
-    key.set_value(&TREE_TYPE, Tree::try_from(args.next_string()?)?)?;
-    REDIS_OK
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = value.node_or_root(node_data.as_deref())?;
+            Ok(reply::integer(node.height() - node.data().is_hidden_root() as usize))
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// nodes exactly `depth` levels below the node, left to right
+fn get_level(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let depth = args.next_u64()? as usize;
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = value.node_or_root(node_data.as_deref())?;
+            // the roots of a forest are its level 0
+            let depth = depth + node.data().is_hidden_root() as usize;
+            let level = node.bfs_levels()
+                .skip_while(|(level, _)| *level < depth)
+                .take_while(|(level, _)| *level == depth)
+                .map(|(_, visit)| visit.data);
+            Reply::array(level).send(ctx)
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// the most nodes any one level below the node holds, its own included
+fn get_width(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let widths = level_widths(value.node_or_root(node_data.as_deref())?);
+            Ok(reply::integer(widths.into_iter().max().unwrap_or(0)))
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+// node counts of the levels from the node down, the node's own level first
+fn level_stats(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(reply::array(level_widths(value.node_or_root(node_data.as_deref())?))),
+        None => reply::no_key(),
+    }
 }
 
-fn get_tree(ctx: &Context, args: Vec<String>) -> RedisResult {
+
+// [group, count] pairs over the node, or the root, and the nodes below it. By DEPTH every level
+// from the node's own down, by PARENT the children of each parent value in preorder, parents of
+// the same value as the tree compares them counted together
+fn aggregate(ctx: &Context, args: Vec<Arg>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key(&args.next_string()?);
+    let key = ctx.open_key(&args.next_key()?);
+
+    let mut under = None;
+    let mut group_by = None;
+    let mut count = false;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "UNDER" => under = Some(args.next_bytes()?),
+            "GROUPBY" => group_by = Some(args.next_string()?.to_uppercase()),
+            "COUNT" => count = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+    match group_by.as_deref() {
+        Some("DEPTH") | Some("PARENT") if count => (),
+        Some("DEPTH") | Some("PARENT") | None => return Err(Error::from("aggregate takes GROUPBY DEPTH|PARENT COUNT").into()),
+        Some(group) => return Err(Error::from(format!("unknown group {}", group)).into()),
+    }
 
     let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        Some(value) => value.to_string().into(),
-        None => RedisValue::Null,
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = value.node_or_root(under.as_deref())?;
+    let groups = if group_by.as_deref() == Some("DEPTH") {
+        level_widths(node).into_iter().enumerate().map(|(depth, count)| Reply::array([reply::integer(depth), reply::integer(count)])).collect()
+    } else {
+        let mut positions = HashMap::new();
+        let mut groups: Vec<(&NodeData, usize)> = Vec::new();
+        for parent in node.locate_all_by(|data| !data.is_hidden_root()).filter(|parent| !parent.has_no_child()) {
+            let position = *positions.entry(value.index_key(&parent.data().value)).or_insert_with(|| {
+                groups.push((parent.data(), 0));
+                groups.len() - 1
+            });
+            groups[position].1 += parent.degree();
+        }
+        groups.into_iter().map(|(parent, count)| Reply::Array(vec![parent.into(), reply::integer(count).into()])).collect()
     };
+    Reply::Array(groups).send(ctx)
+}
 
-    Ok(value)
+
+enum Rollup {
+    Sum,
+    Min,
+    Max,
+    Avg,
 }
 
-fn get_subtree(ctx: &Context, args: Vec<String>) -> RedisResult {
+// the numbers of the subtree under the node rolled up: the node values, or the attribute FIELD
+// names. Values which are no number and nodes without the attribute are left out, which leaves
+// the sum 0 and the others nil when none is
+fn rollup(ctx: &Context, args: Vec<Arg>, rollup: Rollup) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key(&args.next_string()?);
-    let node_data = args.next_string()?;
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
 
-    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let  Some(node) = value.data.root().locate_first_by_data(&node_data) {
-            return Ok(node.to_string().into())
+    let mut field = None;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "FIELD" => field = Some(args.next_string()?),
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
+        }
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let numbers = value.node(&node_data)?.locate_all_by(|_| true)
+        .filter_map(|node| match &field {
+            Some(field) => node.data().attribute(field).map(String::as_bytes),
+            None => Some(node.data().value.as_slice()),
+        })
+        .filter_map(|number| std::str::from_utf8(number).ok()?.parse::<f64>().ok().filter(|number| number.is_finite()));
+    let rolled_up = match rollup {
+        Rollup::Sum => Some(numbers.sum()),
+        Rollup::Min => numbers.reduce(f64::min),
+        Rollup::Max => numbers.reduce(f64::max),
+        Rollup::Avg => {
+            let (sum, count) = numbers.fold((0.0, 0), |(sum, count), number| (sum + number, count + 1));
+            (count > 0).then(|| sum / count as f64)
+        },
+    };
+    Ok(rolled_up.map_or(RedisValue::Null, number_reply))
+}
+
+fn sum(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    rollup(ctx, args, Rollup::Sum)
+}
+
+fn min(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    rollup(ctx, args, Rollup::Min)
+}
+
+fn max(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    rollup(ctx, args, Rollup::Max)
+}
+
+fn avg(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    rollup(ctx, args, Rollup::Avg)
+}
+
+
+// field value pairs describing the tree at key as a whole, gathered in one bfs pass
+fn tree_info(ctx: &Context, key_name: &str) -> RedisResult {
+    let key = ctx.open_key(key_name);
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let (mut height, mut max_degree, mut leaves) = (0, 0, 0);
+    // the hidden root of a forest comes first and is a level of its own
+    let hidden = value.forest() as usize;
+    for (level, visit) in value.data.root().bfs_levels().skip(hidden) {
+        height = level - hidden;
+        max_degree = max_degree.max(visit.size.degree);
+        if visit.size.degree == 0 {
+            leaves += 1;
         }
     }
-    Ok(RedisValue::Null)
+    let flag = |on: bool| RedisValue::Integer(on as i64);
+    Reply::fields(vec![
+        ("nodes", reply::integer(value.node_count())),
+        ("height", reply::integer(height)),
+        ("max_degree", reply::integer(max_degree)),
+        ("leaves", reply::integer(leaves)),
+        ("memory_bytes", reply::integer(value.memory_usage())),
+        ("encoding_version", RedisValue::Integer(ENCODING_VERSION as i64)),
+        ("case_insensitive", flag(value.case_insensitive)),
+        ("unique", flag(value.unique)),
+        ("forest", flag(value.forest())),
+    ]).send(ctx)
 }
 
 
-fn del_tree(ctx: &Context, args: Vec<String>) -> RedisResult {
+// [degree, descendants] of the node, straight from the sizes the tree keeps up to date
+fn get_size(ctx: &Context, args: Vec<Arg>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key_writable(&args.next_string()?);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
 
     match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        Some(_) => {
-            key.delete()?;
-            REDIS_OK
-        }
-        None => Ok(RedisValue::Null),
+        Some(value) => {
+            let node = value.node_or_root(node_data.as_deref())?;
+            Ok(reply::array(vec![node.degree(), node.node_count() - 1]))
+        },
+        None => reply::no_key(),
     }
 }
 
-fn del_subtree(ctx: &Context, args: Vec<String>) -> RedisResult {
+
+fn get_degree(ctx: &Context, args: Vec<Arg>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let mut key = ctx.open_key_writable(&args.next_string()?);
-    let node_data = args.next_string()?;
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next();
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(reply::integer(value.node_or_root(node_data.as_deref())?.degree())),
+        None => reply::no_key(),
+    }
+}
 
 
-    if let Some(mut value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let  Some(mut node) = value.data.root_mut().locate_first_mut_by_data(&node_data) {
-            return Ok(node.detach().to_string().into())
-        }
+fn is_leaf(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    args.done()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Ok(RedisValue::Integer(value.node(&node_data)?.has_no_child() as i64)),
+        None => reply::no_key(),
     }
-    Ok(RedisValue::Null)
 }
 
-fn set_tail_child(ctx: &Context, args: Vec<String>) -> RedisResult {
+
+fn get_father(ctx: &Context, args: Vec<Arg>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let mut key = ctx.open_key_writable(&args.next_string()?);
-    let node_data = args.next_string()?;
-    // let path = args.next_string()?.split(".").map(|v| v.to_string()).collect::<Vec<String>>();
-    let sub_tree = Tree::try_from(args.next_string()?)?;
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Reply::from(node_parent(value.node(&node_data)?).map(|parent| parent.data())).send(ctx),
+        None => reply::no_key(),
+    }
+}
+
+
+fn get_root(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    args.done()?;
+
+    // the roots of a forest, in an array
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) if value.forest() => Reply::array(value.data.root().iter().map(|root| root.data())).send(ctx),
+        Some(value) => Reply::from(value.data.root().data()).send(ctx),
+        None => reply::no_key(),
+    }
+}
 
 
-    if let Some(mut value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let Some(mut node) = value.data.root_mut().locate_first_mut_by_data(&node_data) {
-            node.push_back(sub_tree);
-            return REDIS_OK;
+fn get_siblings(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let mut with_self = false;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "WITHSELF" => with_self = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
         }
     }
 
-    Ok(RedisValue::Null)
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => Reply::array(value.node(&node_data)?.siblings(with_self).map(|v| v.data())).send(ctx),
+        None => reply::no_key(),
+    }
 }
 
 
-fn get_ancestors(ctx: &Context, args: Vec<String>) -> RedisResult {
+fn child_index(ctx: &Context, args: Vec<Arg>) -> RedisResult {
     let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key(&args.next_string()?);
-    let node_data = args.next_string()?;
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+    args.done()?;
 
-    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let Some(node) = value.data.root().locate_first_by_data(&node_data) {
-            let ancestors = node.ancestors();
-            if ancestors.len() > 0 {
-                return Ok(RedisValue::Array(ancestors.into_iter().map(|v|{
-                    v.clone().into()
-                }).collect::<Vec<_>>()))
-            }
+    match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => {
+            let node = value.node(&node_data)?;
+            let position = node.child_index().zip(node.parent()).map(|(index, parent)| {
+                reply::array(vec![index, parent.degree()])
+            });
+            Ok(position.into())
+        },
+        None => reply::no_key(),
+    }
+}
+
+
+fn get_children(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let key = ctx.open_key(&args.next_key()?);
+    let node_data = args.next_bytes()?;
+
+    let mut limit = None;
+    let mut count_only = false;
+    while let Some(arg) = args.next() {
+        match arg.to_uppercase().as_str() {
+            "LIMIT" => limit = Some((args.next_u64()? as usize, args.next_u64()? as usize)),
+            "COUNT" => count_only = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
         }
     }
+    if limit.is_some() && count_only {
+        return Err(Error::from("LIMIT and COUNT can not be combined").into())
+    }
+
+    let value = match key.get_value::<RedisTreeType>(&TREE_TYPE)? {
+        Some(value) => value,
+        None => return reply::no_key(),
+    };
+    let node = value.node(&node_data)?;
+    if count_only {
+        return Ok(reply::integer(node.degree()))
+    }
+    let (offset, count) = limit.unwrap_or((0, usize::MAX));
+    Reply::array(node.iter().skip(offset).take(count).map(|child| child.data())).send(ctx)
+}
+
 
-    Ok(RedisValue::Null)
+// tree.info reports on the module without a key and on the tree with one
+fn info(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+    match args.as_slice() {
+        [_] => module_info(ctx),
+        [_, name] => tree_info(ctx, &key_name(name.clone())?),
+        _ => Err(RedisError::WrongArity),
+    }
 }
 
+fn module_info(ctx: &Context) -> RedisResult {
+    let capabilities = capabilities();
+    let limits = limits();
+    let flag = |on: bool| RedisValue::Integer(on as i64);
+    #[allow(unused_mut)]
+    let mut fields = vec![
+        ("redis_version", capabilities.server_version.map(format_version).unwrap_or_else(|| "unknown".to_string()).into()),
+        ("resp3", flag(capabilities.resp3)),
+        ("keyspec", flag(capabilities.keyspec)),
+        ("config", flag(capabilities.config)),
+        ("acl", flag(capabilities.acl)),
+        ("acl_category", flag(capabilities.acl_category)),
+        ("scan", flag(capabilities.scan)),
+        ("max_nodes", RedisValue::Integer(limits.max_nodes)),
+        ("max_depth", RedisValue::Integer(limits.max_depth)),
+        ("max_value_length", RedisValue::Integer(limits.max_value_length)),
+    ];
+    // counted by the trees crate on the main thread, where every command runs
+    #[cfg(feature = "stats")]
+    {
+        let stats = trees::stats::snapshot();
+        fields.push(("node_allocations", RedisValue::Integer(stats.allocations as i64)));
+        fields.push(("node_drops", RedisValue::Integer(stats.drops as i64)));
+        fields.push(("traversal_steps", RedisValue::Integer(stats.traversal_steps as i64)));
+    }
+    Reply::fields(fields).send(ctx)
+}
 
-fn get_descendants(ctx: &Context, args: Vec<String>) -> RedisResult {
-    let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key(&args.next_string()?);
-    let node_data = args.next_string()?;
 
-    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let Some(node) = value.data.root().locate_first_by_data(&node_data) {
-            let descendants = node.descendants();
-            if descendants.len() > 0 {
-                return Ok(RedisValue::Array(descendants.into_iter().map(|v|{
-                    v.clone().into()
-                }).collect::<Vec<_>>()))
+// =================================================================================================
+// SELFTEST
+// =================================================================================================
+// trees every check runs on, built in memory so no key is ever touched
+fn selftest_trees() -> Vec<RedisTreeType> {
+    let mut samples = vec![
+        "0".to_string(),
+        "0( 1( 2 3( 7 ) ) 4( 5 6 ) 8 )".to_string(),
+        "USA( Legislature( House( Pelosi ) Senate( Harris ) ) Executive( Biden ) )".to_string(),
+        "a( a( a ) a )".to_string(),
+        "\"New York\"( \"(1)\" \"say \\\"hi\\\"\" C:\\\\ a\"b )".to_string(),
+        r#"bytes( "nul\x00" "\xff\xfe" a/b "c\\" )"#.to_string(),
+    ];
+    // deep and wide enough to catch anything recursing or quadratic
+    let deep = (0..2000).map(|i| i.to_string()).collect::<Vec<_>>();
+    samples.push(format!("{}{}", deep.join("( "), " )".repeat(deep.len() - 1)));
+    samples.push(format!("root( {} )", deep.join(" ")));
+
+    let mut trees = samples.iter().map(|s| RedisTreeType::new(node_tree(Tree::try_from(s.as_bytes()).unwrap()), false)).collect::<Vec<_>>();
+    let mut case_insensitive = RedisTreeType::new(node_tree(Tree::try_from("Root( Food( Fruit ) Toys )".as_bytes()).unwrap()), true);
+    for (node_data, field, value) in [("Root", "owner", "shop"), ("Fruit", "aisle", "3"), ("Fruit", "unit", "kg ( \"loose\" )")] {
+        case_insensitive.node_mut(node_data.as_bytes()).unwrap().data_mut().set_attribute(field.to_string(), value.to_string());
+    }
+    for (node_data, weight) in [("Food", 0.25), ("Toys", -1e300)] {
+        case_insensitive.node_mut(node_data.as_bytes()).unwrap().data_mut().weight = Some(weight);
+    }
+    trees.push(case_insensitive);
+    trees
+}
+
+// parsing what a tree prints gives the same tree back, in both formats, attributes included in JSON
+fn check_parser(trees: &[RedisTreeType]) -> Result<String, String> {
+    for value in trees {
+        let printed = value.to_string();
+        let reparsed = Format::String.parse_tree(printed.as_bytes()).map_err(|e| format!("{} does not parse: {}", printed, e.msg))?;
+        if tree_string(reparsed.root()) != printed {
+            return Err(format!("{} parses as {}", printed, tree_string(reparsed.root())))
+        }
+        let json = value.data.root().to_json();
+        let from_json = Tree::<NodeData>::from_json(&json).map_err(|e| format!("{} does not parse: {}", json, e))?;
+        if from_json.root().to_json() != json {
+            return Err(format!("{} parses as {}", json, from_json.root().to_json()))
+        }
+    }
+    Ok(format!("{} trees round tripped", trees.len()))
+}
+
+// the sizes kept on every node agree with a walk, also after edits
+fn check_sizes(trees: &[RedisTreeType]) -> Result<String, String> {
+    fn check(node: &Node<NodeData>) -> Result<usize, String> {
+        let mut checked = 0;
+        for visit in NodeWalk::from(node) {
+            let node = match visit {
+                walk::Visit::Begin(node) | walk::Visit::Leaf(node) => node,
+                walk::Visit::End(_) => continue,
+            };
+            let walked = NodeWalk::from(node).filter(|v| !matches!(v, walk::Visit::End(_))).count();
+            if node.node_count() != walked || node.degree() != node.iter().count() {
+                return Err(format!("sizes of {} are off", node.data()))
             }
+            checked += 1;
         }
+        Ok(checked)
     }
 
-    Ok(RedisValue::Null)
+    let mut checked = 0;
+    for value in trees {
+        let mut edited = value.deep_clone();
+        checked += check(edited.data.root())?;
+        let mut root = edited.data.root_mut();
+        root.push_back(node_tree(Tree::try_from("x( y z )".as_bytes()).unwrap()));
+        root.push_front(Tree::new(NodeData::new(b"w".to_vec())));
+        checked += check(edited.data.root())?;
+        let mut root = edited.data.root_mut();
+        root.pop_front();
+        if let Some(mut first) = root.iter_mut().next() {
+            first.detach();
+        }
+        checked += check(edited.data.root())?;
+    }
+    Ok(format!("{} node sizes checked", checked))
 }
 
+// every node is found again by its path and by its value
+fn check_locate(trees: &[RedisTreeType]) -> Result<String, String> {
+    let mut checked = 0;
+    for value in trees {
+        // locate_all per node adds up on the big samples, the first few hundred make the point
+        for (path, node) in node_paths(value.data.root(), PATH_SEPARATOR).take(200) {
+            let located = value.locate_by_path(&path).ok_or_else(|| format!("no node at path {}", display_value(&path)))?;
+            if join_path(located, PATH_SEPARATOR) != path {
+                return Err(format!("path {} locates {}", display_value(&path), display_value(&join_path(located, PATH_SEPARATOR))))
+            }
+            let matches = value.matcher(&node.data().value);
+            let expected = node_paths(value.data.root(), PATH_SEPARATOR).filter(|(_, n)| matches(n.data())).count();
+            if value.data.root().locate_all_by(&matches).count() != expected {
+                return Err(format!("{} is not located", node.data()))
+            }
+            // the index finds the same node a walk in preorder finds first
+            let first = value.data.root().locate_first_by(&matches);
+            if value.locate(&node.data().value).map(|n| n as *const _) != first.map(|n| n as *const _) {
+                return Err(format!("{} is not located first in preorder", node.data()))
+            }
+            checked += 1;
+        }
+    }
+    Ok(format!("{} nodes located", checked))
+}
 
-fn get_father(ctx: &Context, args: Vec<String>) -> RedisResult {
-    let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key(&args.next_string()?);
-    let node_data = args.next_string()?;
+// the index holds every node of the tree under its value and nothing else, also after edits
+fn check_index(trees: &[RedisTreeType]) -> Result<String, String> {
+    fn check(value: &RedisTreeType) -> Result<usize, String> {
+        let indexed = value.index.values().map(Vec::len).sum::<usize>();
+        if indexed != value.data.root().node_count() {
+            return Err(format!("{} nodes indexed in a tree of {}", indexed, value.data.root().node_count()))
+        }
+        for node in value.data.root().locate_all_by(|_| true) {
+            if !value.indexed(&node.data().value).iter().any(|indexed| ptr::eq(indexed.as_ptr(), node)) {
+                return Err(format!("{} is not indexed", node.data()))
+            }
+        }
+        Ok(indexed)
+    }
 
-    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let Some(node) = value.data.root().locate_first_by_data(&node_data) {
-            if let Some(father) = node.father() {
-                return Ok(father.into());
-            } 
+    let mut checked = 0;
+    for value in trees {
+        let mut edited = value.deep_clone();
+        checked += check(&edited)?;
+        let sub_tree = node_tree(Tree::try_from("x( y z )".as_bytes()).unwrap());
+        let added = NonNull::from(sub_tree.root());
+        edited.data.root_mut().push_back(sub_tree);
+        edited.index_subtree(added);
+        if let Some(removed) = edited.data.root_mut().pop_front() {
+            edited.unindex_subtree(removed.root());
         }
+        let root = NonNull::from(edited.data.root());
+        edited.relabel(root, b"W".to_vec());
+        checked += check(&edited)?;
     }
+    Ok(format!("{} index entries checked", checked))
+}
 
-    Ok(RedisValue::Null)
+// what rdb_save encodes decodes to the same tree, the sizes and attributes included, and damage is caught
+fn check_rdb(trees: &[RedisTreeType]) -> Result<String, String> {
+    for value in trees {
+        let encoded = encode_tree(value.data.root());
+        let decoded = decode_tree(&encoded).map_err(|e| format!("{} does not decode: {}", value.to_string(), e.msg))?;
+        if decoded.root().to_json() != value.data.root().to_json() || decoded.root().subtree_hash() != value.data.root().subtree_hash() {
+            return Err(format!("{} decodes as {}", value.to_string(), tree_string(decoded.root())))
+        }
+        check_sizes(&[RedisTreeType::new(decoded, false)])?;
+        if decode_tree(&encoded[..encoded.len() - 1]).is_ok() {
+            return Err(format!("truncated encoding of {} decodes", value.to_string()))
+        }
+    }
+    Ok(format!("{} trees encoded and decoded", trees.len()))
 }
 
+// active defrag leaves an equal tree behind in fresh allocations and indexed anew, in one go or a
+// node at a time, starts over after a write and leaves a compacted tree alone
+fn check_defrag(trees: &[RedisTreeType]) -> Result<String, String> {
+    let addresses = |value: &RedisTreeType| value.data.root().locate_all_by(|_| true).map(|node| node as *const _ as usize).collect::<HashSet<_>>();
+    for value in trees {
+        for chunk in [DEFRAG_CHUNK_NODES, 1] {
+            let mut defragged = Box::into_raw(Box::new(value.deep_clone())) as *mut c_void;
+            let before = addresses(unsafe { &*(defragged as *const RedisTreeType) });
+            let mut passes = 1;
+            while unsafe { defrag_value(&mut defragged, chunk, || true) } {
+                passes += 1;
+            }
+            if passes != value.data.root().node_count().div_ceil(chunk) {
+                return Err(format!("{} defrags in {} passes of {} nodes", value.to_string(), passes, chunk))
+            }
+            let compacted = defragged;
+            if unsafe { defrag_value(&mut defragged, chunk, || true) } || defragged != compacted {
+                return Err(format!("{} is defragged again", value.to_string()))
+            }
+            let defragged = unsafe { Box::from_raw(defragged as *mut RedisTreeType) };
+            if defragged.data.root().to_json() != value.data.root().to_json()
+                || (defragged.case_insensitive, defragged.unique) != (value.case_insensitive, value.unique) {
+                return Err(format!("{} defrags to {}", value.to_string(), defragged.to_string()))
+            }
+            if !addresses(&defragged).is_disjoint(&before) {
+                return Err(format!("nodes of {} stay put in defrag", value.to_string()))
+            }
+            check_index(std::slice::from_ref(&defragged))?;
+        }
 
-fn get_children(ctx: &Context, args: Vec<String>) -> RedisResult {
-    let mut args = args.into_iter().skip(1);
-    let key = ctx.open_key(&args.next_string()?);
-    let node_data = args.next_string()?;
+        // the root is copied on the first pass, a copy going on after the rename would keep the old value
+        let mut defragged = Box::into_raw(Box::new(value.deep_clone())) as *mut c_void;
+        if unsafe { defrag_value(&mut defragged, 1, || true) } {
+            let tree = unsafe { &mut *(defragged as *mut RedisTreeType) };
+            let root = NonNull::from(tree.data.root());
+            tree.relabel(root, b"renamed".to_vec());
+            tree.version += 1;
+            while unsafe { defrag_value(&mut defragged, 1, || true) } {}
+        }
+        let defragged = unsafe { Box::from_raw(defragged as *mut RedisTreeType) };
+        if value.data.root().has_no_child() != (defragged.data.root().data().value != b"renamed") {
+            return Err(format!("{} defrags on from before a write", value.to_string()))
+        }
+        check_index(std::slice::from_ref(&defragged))?;
+    }
+    Ok(format!("{} trees defragged", trees.len()))
+}
 
-    if let Some(value) = key.get_value::<RedisTreeType>(&TREE_TYPE)? {
-        if let Some(node) = value.data.locate_first_by_data(&node_data) {
-            let children = node.children();
-            if children.len() > 0 {
-                return Ok(RedisValue::Array(children.into_iter().map(|v|{
-                    v.clone().into()
-                }).collect::<Vec<_>>()))
-            }
+fn selftest(_: &Context, args: Vec<Arg>) -> RedisResult {
+    let mut args = args.into_iter().skip(1);
+    let mut verbose = false;
+    for arg in args.by_ref() {
+        match arg.to_uppercase().as_str() {
+            "VERBOSE" => verbose = true,
+            _ => return Err(Error::from(format!("unknown argument {}", arg)).into()),
         }
     }
 
-    Ok(RedisValue::Null)
+    let trees = selftest_trees();
+    let checks: [(&str, fn(&[RedisTreeType]) -> Result<String, String>); 6] = [
+        ("parser", check_parser),
+        ("sizes", check_sizes),
+        ("index", check_index),
+        ("locate", check_locate),
+        ("rdb", check_rdb),
+        ("defrag", check_defrag),
+    ];
+    // [check, pass|fail] each, VERBOSE adds what was checked or what went wrong
+    Ok(reply::array(checks.iter().map(|(name, check)| {
+        let outcome = check(&trees);
+        let mut result = vec![RedisValue::from(*name), if outcome.is_ok() { "pass" } else { "fail" }.into()];
+        if verbose {
+            result.push(outcome.unwrap_or_else(|e| e).into());
+        }
+        RedisValue::Array(result)
+    })))
 }
 
 
+// files every command under @read or @write as its flags say, and under @tree, where the server has them.
+// Every command first takes out what is past its deadline in the trees at its keys, writes then
+// notify the trees they changed. This is redis_command! with the Context made by
+// with_context, so raw_context can find the raw one behind it
+macro_rules! register_commands {
+    ( $ctx:expr, $([$name:expr, $command:expr, $flags:expr, $firstkey:expr, $lastkey:expr, $keystep:expr]),* $(,)* ) => {{
+        $(
+            {
+                fn run(ctx: &Context, args: Vec<Arg>) -> RedisResult {
+                    expire_keys(ctx, $name, &args, $firstkey, $lastkey, $keystep);
+                    let notification = Notification::before(ctx, $name, $flags, &args, $firstkey, $lastkey, $keystep);
+                    let reply = $command(ctx, args);
+                    if let Some(notification) = notification {
+                        notification.after(ctx);
+                    }
+                    reply
+                }
+
+                extern "C" fn do_command(ctx: *mut raw::RedisModuleCtx, argv: *mut *mut raw::RedisModuleString, argc: c_int) -> c_int {
+                    with_context(ctx, |ctx| {
+                        let args = unsafe { slice::from_raw_parts(argv, argc as usize) }.iter().map(|&arg| {
+                            let mut len = 0;
+                            let bytes = unsafe { raw::RedisModule_StringPtrLen.unwrap()(arg, &mut len) };
+                            Arg(unsafe { slice::from_raw_parts(bytes as *const u8, len) }.to_vec())
+                        }).collect();
+                        match run(ctx, args) {
+                            Ok(value) => {
+                                let _ = Reply::from(value).send(ctx);
+                                raw::Status::Ok as c_int
+                            },
+                            // redis-module sends errors as C strings
+                            Err(RedisError::String(message)) => ctx.reply(Err(RedisError::String(message.replace('\0', "\\x00")))) as c_int,
+                            Err(e) => ctx.reply(Err(e)) as c_int,
+                        }
+                    })
+                }
+
+                let name = CString::new($name).unwrap();
+                let flags = CString::new($flags).unwrap();
+                let created = unsafe {
+                    raw::RedisModule_CreateCommand.unwrap()($ctx, name.as_ptr(), Some(do_command), flags.as_ptr(), $firstkey, $lastkey, $keystep)
+                };
+                if created == raw::Status::Err as c_int {
+                    return raw::Status::Err as c_int
+                }
+            }
+            if set_acl_categories($ctx, $name, $flags) == raw::Status::Err as c_int {
+                return raw::Status::Err as c_int
+            }
+        )*
+        raw::Status::Ok as c_int
+    }};
+}
 
-redis_module! {
-    name: "ReTree",
-    version: 1,
-    data_types: [
-        TREE_TYPE,
-    ],
-    init: init,
-    commands: [
+// registered from init rather than listed in redis_module!, which only runs init before the commands
+// exist, while ACL categories can only be set on commands that do and only while the module loads
+fn create_commands(ctx: *mut raw::RedisModuleCtx) -> c_int {
+    use std::slice;
+    register_commands! { ctx,
         ["tree.init", init_tree, "write", 1, 1, 1],
         ["tree.get", get_tree, "readonly", 1, 1, 1],
+        ["tree.fromedges", from_edges, "write", 1, 1, 1],
+        ["tree.toedges", to_edges, "readonly", 1, 1, 1],
+        ["tree.mget", get_multi, "readonly", 1, -1, 1],
+        ["tree.getset", get_set, "write", 1, 1, 1],
+        ["tree.hash", get_hash, "readonly", 1, 1, 1],
+        ["tree.version", get_version, "readonly", 1, 1, 1],
+        ["tree.notify", notify, "write", 1, 1, 1],
+        ["tree.shape", get_shape, "readonly", 1, 1, 1],
+        ["tree.shape_hash", get_shape_hash, "readonly", 1, 1, 1],
+        ["tree.export_commands", export_commands, "readonly", 1, 1, 1],
+        ["tree.todot", to_dot, "readonly", 1, 1, 1],
         ["tree.del", del_tree, "write", 1, 1, 1],
 
         ["tree.get_subtree", get_subtree, "readonly", 1, 1, 1],
+        ["tree.locate_all", locate_all, "readonly", 1, 1, 1],
+        ["tree.search", search, "readonly", 1, 1, 1],
+        ["tree.count_match", count_match, "readonly", 1, 1, 1],
+        ["tree.filter", filter, "readonly", 1, 1, 1],
+        ["tree.randnode", randnode, "readonly random", 1, 1, 1],
+        ["tree.exists_node", exists_node, "readonly", 1, 1, 1],
+        ["tree.contains_path", contains_path, "readonly", 1, 1, 1],
         ["tree.del_subtree", del_subtree, "write", 1, 1, 1],
+        ["tree.del_children", del_children, "write", 1, 1, 1],
+        ["tree.prune", prune, "write", 1, 1, 1],
+        ["tree.expire_node", expire_node, "write", 1, 1, 1],
+        ["tree.ttl_node", ttl_node, "readonly", 1, 1, 1],
+        ["tree.persist_node", persist_node, "write", 1, 1, 1],
+        ["tree.purge_expired", purge_expired, "write", 1, 1, 1],
         ["tree.set_subtree", set_tail_child, "write", 1, 1, 1],
+        ["tree.append_leaves", append_leaves, "write", 1, 1, 1],
+        ["tree.graft", graft, "write", 1, 3, 2],
+        ["tree.copy_subtree", copy_subtree, "write", 1, 3, 2],
+        ["tree.add_path", add_path, "write", 1, 1, 1],
+        ["tree.dumpnode", dumpnode, "readonly", 1, 1, 1],
+        ["tree.restorenode", restorenode, "write", 1, 1, 1],
+        ["tree.wrap_node", wrap_node, "write", 1, 1, 1],
+        ["tree.collapse_node", collapse_node, "write", 1, 1, 1],
+        ["tree.sort_children", sort_children, "write", 1, 1, 1],
+        ["tree.move_child", move_child, "write", 1, 1, 1],
+        ["tree.rename_node", rename_node, "write", 1, 1, 1],
+        ["tree.setattr", set_attr, "write", 1, 1, 1],
+        ["tree.getattr", get_attr, "readonly", 1, 1, 1],
+        ["tree.delattr", del_attr, "write", 1, 1, 1],
+        ["tree.node_hgetall_subtree", node_hgetall_subtree, "readonly", 1, 1, 1],
+        ["tree.node_hmset_bulk", node_hmset_bulk, "write", 1, 1, 1],
+        ["tree.set_weight", set_weight, "write", 1, 1, 1],
+        ["tree.get_weight", get_weight, "readonly", 1, 1, 1],
+        ["tree.apply_diff", apply_diff, "write", 1, 1, 1],
+        ["tree.diff", diff, "readonly", 1, 2, 1],
         ["tree.get_ancestors", get_ancestors, "readonly", 1, 1, 1],
+        ["tree.first_ancestor_match", first_ancestor_match, "readonly", 1, 1, 1],
         ["tree.get_descendants", get_descendants, "readonly", 1, 1, 1],
+        ["tree.lca", lowest_common_ancestor, "readonly", 1, 1, 1],
+        ["tree.is_ancestor", is_ancestor, "readonly", 1, 1, 1],
+        ["tree.equals", equals, "readonly", 1, 2, 1],
+        ["tree.path", get_path, "readonly", 1, 1, 1],
+        ["tree.leaf_paths", leaf_paths, "readonly", 1, 1, 1],
+        ["tree.leaves", get_leaves, "readonly", 1, 1, 1],
+        ["tree.scan", scan, "readonly", 1, 1, 1],
+        ["tree.walk_start", walk_start, "readonly", 1, 1, 1],
+        // key-less, they name a cursor of the calling client and check its key themselves
+        ["tree.walk_next", walk_next, "readonly", 0, 0, 0],
+        ["tree.walk_to_child", walk_to_child, "readonly", 0, 0, 0],
+        ["tree.walk_to_parent", walk_to_parent, "readonly", 0, 0, 0],
+        ["tree.flatten", flatten, "readonly", 1, 1, 1],
+        ["tree.depth", get_depth, "readonly", 1, 1, 1],
+        ["tree.height", get_height, "readonly", 1, 1, 1],
+        ["tree.level", get_level, "readonly", 1, 1, 1],
+        ["tree.width", get_width, "readonly", 1, 1, 1],
+        ["tree.levelstats", level_stats, "readonly", 1, 1, 1],
+        ["tree.aggregate", aggregate, "readonly", 1, 1, 1],
+        ["tree.sum", sum, "readonly", 1, 1, 1],
+        ["tree.min", min, "readonly", 1, 1, 1],
+        ["tree.max", max, "readonly", 1, 1, 1],
+        ["tree.avg", avg, "readonly", 1, 1, 1],
+        ["tree.size", get_size, "readonly", 1, 1, 1],
+        ["tree.degree", get_degree, "readonly", 1, 1, 1],
+        ["tree.is_leaf", is_leaf, "readonly", 1, 1, 1],
         ["tree.get_father", get_father, "readonly", 1, 1, 1],
+        ["tree.root", get_root, "readonly", 1, 1, 1],
         ["tree.get_children", get_children, "readonly", 1, 1, 1],
-    ],
+        ["tree.siblings", get_siblings, "readonly", 1, 1, 1],
+        ["tree.child_index", child_index, "readonly", 1, 1, 1],
+        ["tree.info", info, "readonly", 1, 1, 1],
+        ["tree.selftest", selftest, "readonly", 0, 0, 0],
+    }
+}
+
+// what redis_module! makes, with the load arguments passed on to init
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn RedisModule_OnLoad(ctx: *mut raw::RedisModuleCtx, argv: *mut *mut raw::RedisModuleString, argc: c_int) -> c_int {
+    // nothing is allocated before RedisModule_Init, the allocator is the server's
+    let name = b"ReTree\0";
+    if raw::Export_RedisModule_Init(ctx, name.as_ptr() as *const c_char, 1, raw::REDISMODULE_APIVER_1 as c_int) == raw::Status::Err as c_int {
+        return raw::Status::Err as c_int
+    }
+    let args = std::slice::from_raw_parts(argv, argc as usize).iter()
+        .map(|&arg| RedisString::from_ptr(arg).map(|arg| arg.to_owned()))
+        .collect::<Result<Vec<_>, _>>();
+    match args {
+        Ok(args) => init(ctx, &args),
+        Err(_) => {
+            log("the load arguments are not UTF-8");
+            raw::Status::Err as c_int
+        },
+    }
 }
\ No newline at end of file
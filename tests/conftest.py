@@ -10,6 +10,14 @@ def redis_client():
     return client
 
 
+@pytest.fixture(scope="session")
+def binary_client():
+    # DUMP payloads are not UTF-8, the decoding client can not hand them back
+    client = redis.Redis(host="127.0.0.1", port=6379)
+    client.ping()
+    return client
+
+
 @pytest.fixture(scope="function", autouse=True)
 def clear_db(redis_client):
     redis_client.flushdb()
\ No newline at end of file
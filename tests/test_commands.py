@@ -1,4 +1,11 @@
+import contextlib
+import json
+import re
+import socket
+import time
+
 import pytest
+import redis
 from redis.exceptions import ResponseError
 
 
@@ -10,15 +17,391 @@ def test_init(redis_client):
     with pytest.raises(ResponseError, match="no root in tree string"):
         redis_client.execute_command("tree.init", "hello", "(2)")
 
+    with pytest.raises(ResponseError, match="multiple roots in tree string"):
+        redis_client.execute_command("tree.init", "hello", "0 (1) 2")
+
+    with pytest.raises(ResponseError, match="unexpected \\( after the tree"):
+        redis_client.execute_command("tree.init", "hello", "0 (1) (2)")
+
+    with pytest.raises(ResponseError, match="empty \\(\\) in tree string"):
+        redis_client.execute_command("tree.init", "hello", "0 (1 ())")
+
+    # the offending token, its byte offset and what is around it
+    with pytest.raises(ResponseError, match=re.escape('multiple roots in tree string: e at byte 17 near "... b (c) d) e"')):
+        redis_client.execute_command("tree.init", "hello", "root (a b (c) d) e")
+    with pytest.raises(ResponseError, match=re.escape('unclosed quote in tree string: " at byte 5 near "a (b "c d)"')):
+        redis_client.execute_command("tree.init", "hello", 'a (b "c d)')
+
     assert redis_client.execute_command("tree.get", "hello") is None
     redis_client.execute_command("tree.init", "hello", "0 (1 2)")
 
 
+def test_init_case_insensitive(redis_client):
+    redis_client.execute_command("tree.init", "hello", "Root (Food (Fruit Meat) Toys)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "food") == "Food( Fruit Meat )"
+    assert redis_client.execute_command("tree.get_father", "hello", "FRUIT") == "Food"
+
+    redis_client.execute_command("tree.init", "hello", "Root (Food (Fruit Meat) Toys)")
+    with pytest.raises(ResponseError, match="no node food"):
+        redis_client.execute_command("tree.get_subtree", "hello", "food")
+
+    with pytest.raises(ResponseError, match="unknown argument"):
+        redis_client.execute_command("tree.init", "hello", "0 (1 2)", "NOPE")
+
+
+def test_init_nx_xx(redis_client):
+    assert redis_client.execute_command("tree.init", "hello", "a (b)", "XX") is None
+    assert redis_client.execute_command("exists", "hello") == 0
+    assert redis_client.execute_command("tree.init", "hello", "a (b)", "NX") == "OK"
+    assert redis_client.execute_command("tree.init", "hello", "x", "NX") is None
+    assert redis_client.execute_command("tree.get", "hello") == "a( b )"
+    assert redis_client.execute_command("tree.init", "hello", "x", "XX") == "OK"
+    assert redis_client.execute_command("tree.get", "hello") == "x"
+
+    with pytest.raises(ResponseError, match="NX and XX can not be combined"):
+        redis_client.execute_command("tree.init", "hello", "y", "NX", "XX")
+    with pytest.raises(ResponseError, match="is not closed"):
+        redis_client.execute_command("tree.init", "hello", "y (", "NX")
+    assert redis_client.execute_command("tree.get", "hello") == "x"
+
+
+def test_init_unique(redis_client, binary_client):
+    with pytest.raises(ResponseError, match="duplicate node value a"):
+        redis_client.execute_command("tree.init", "hello", "a (b a)", "UNIQUE")
+    with pytest.raises(ResponseError, match="duplicate node value"):
+        redis_client.execute_command("tree.init", "hello", "Root (Food food)", "UNIQUE", "CASEINSENSITIVE")
+    assert redis_client.execute_command("exists", "hello") == 0
+
+    redis_client.execute_command("tree.init", "hello", "a (b (c) d)", "UNIQUE")
+    with pytest.raises(ResponseError, match="duplicate node value c"):
+        redis_client.execute_command("tree.set_subtree", "hello", "d", "e (c)")
+    with pytest.raises(ResponseError, match="duplicate node value e"):
+        redis_client.execute_command("tree.set_subtree", "hello", "d", "e (e)")
+    with pytest.raises(ResponseError, match="duplicate node value b"):
+        redis_client.execute_command("tree.append_leaves", "hello", "a/d", "x", "b")
+    with pytest.raises(ResponseError, match="duplicate node value x"):
+        redis_client.execute_command("tree.append_leaves", "hello", "a/d", "x", "x")
+    with pytest.raises(ResponseError, match="duplicate node value d"):
+        redis_client.execute_command("tree.rename_node", "hello", "c", "d")
+    with pytest.raises(ResponseError, match="duplicate node value d"):
+        redis_client.execute_command("tree.rename_node", "hello", "c", "d", "ALL")
+    with pytest.raises(ResponseError, match="duplicate node value b"):
+        redis_client.execute_command("tree.apply_diff", "hello", "INSERT", "a/d", "e", "RELABEL", "a/d/e", "b")
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( c ) d )"
+
+    # DEDUP drops the labels already among the children before the check
+    assert redis_client.execute_command("tree.append_leaves", "hello", "a", "d", "x", "DEDUP") == 1
+    assert redis_client.execute_command("tree.rename_node", "hello", "c", "c") == 1
+    assert redis_client.execute_command("tree.rename_node", "hello", "c", "y") == 1
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( y ) d x )"
+
+    # the flag stays with the tree
+    assert redis_client.execute_command("tree.export_commands", "hello") == [["tree.init", "hello", "a( b( y ) d x )", "UNIQUE"]]
+    redis_client.execute_command("copy", "hello", "copy")
+    with pytest.raises(ResponseError, match="duplicate node value x"):
+        redis_client.execute_command("tree.set_subtree", "copy", "d", "x")
+    redis_client.restore("restored", 0, binary_client.dump("hello"))
+    with pytest.raises(ResponseError, match="duplicate node value x"):
+        redis_client.execute_command("tree.set_subtree", "restored", "d", "x")
+
+
 def test_get(redis_client):
     redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)) e f (g h))")
     assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( a b( d ) ) e f( g h ) )"
 
 
+def test_get_chunked(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.get", "hello", "CHUNKED", "4") == ["4", "0( 1 2( a "]
+    assert redis_client.execute_command("tree.get", "hello", "CHUNKED", "4", "CURSOR", "4") == ["8", "b( d ) ) e f( "]
+    assert redis_client.execute_command("tree.get", "hello", "CHUNKED", "4", "CURSOR", "8") == ["0", "g h ) )"]
+    assert redis_client.execute_command("tree.get", "hello", "CHUNKED", "4", "CURSOR", "100") == ["0", ""]
+
+    for count in range(1, 12):
+        chunks, cursor = [], "0"
+        while True:
+            cursor, chunk = redis_client.execute_command("tree.get", "hello", "CHUNKED", count, "CURSOR", cursor)
+            chunks.append(chunk)
+            if cursor == "0":
+                break
+        assert "".join(chunks) == "0( 1 2( a b( d ) ) e f( g h ) )"
+
+    with pytest.raises(ResponseError, match="CHUNKED takes a positive count"):
+        redis_client.execute_command("tree.get", "hello", "CHUNKED", "0")
+    with pytest.raises(ResponseError, match="CHUNKED can not be combined"):
+        redis_client.execute_command("tree.get", "hello", "CHUNKED", "2", "FORMAT", "JSON")
+    with pytest.raises(ResponseError, match="CURSOR needs CHUNKED"):
+        redis_client.execute_command("tree.get", "hello", "CURSOR", "2")
+    assert redis_client.execute_command("tree.get", "nope", "CHUNKED", "2") is None
+
+
+def test_init_frompaths(redis_client):
+    assert redis_client.execute_command("tree.init", "fs", "FROMPATHS", "a/b/c", "a/b/d", "a/e") == "OK"
+    assert redis_client.execute_command("tree.get", "fs") == "a( b( c d ) e )"
+    assert redis_client.execute_command("tree.init", "fs", "frompaths", "a", "a/x/b", "a/x/b", "a/b") == "OK"
+    assert redis_client.execute_command("tree.get", "fs") == "a( x( b ) b )"
+    assert redis_client.execute_command("tree.locate_all", "fs", "b") == ["a/x/b", "a/b"]
+
+    # options go ahead of FROMPATHS, case-insensitive trees merge paths differing in case
+    assert redis_client.execute_command("tree.init", "ci", "CASEINSENSITIVE", "FROMPATHS", "Root/Food", "root/FOOD/fruit") == "OK"
+    assert redis_client.execute_command("tree.get", "ci") == "Root( Food( fruit ) )"
+    assert redis_client.execute_command("tree.init", "ci", "NX", "FROMPATHS", "r/x") is None
+    assert redis_client.execute_command("tree.init", "ci", "IFVERSION", 1, "XX", "FROMPATHS", "r/x") == "OK"
+    assert redis_client.execute_command("tree.get", "ci") == "r( x )"
+    with pytest.raises(ResponseError, match="duplicate node value b"):
+        redis_client.execute_command("tree.init", "u", "UNIQUE", "FROMPATHS", "a/b", "a/x/b")
+
+    # past FROMPATHS everything is a path, whatever the nodes are called
+    assert redis_client.execute_command("tree.init", "opts", "FROMPATHS", "UNIQUE", "UNIQUE/NX", "UNIQUE") == "OK"
+    assert redis_client.execute_command("tree.get", "opts") == "UNIQUE( NX )"
+    # and a tree value reading like an option is still one
+    assert redis_client.execute_command("tree.init", "opts", "UNIQUE") == "OK"
+    assert redis_client.execute_command("tree.get", "opts") == "UNIQUE"
+
+    with pytest.raises(ResponseError, match="path b/c does not start at the root a"):
+        redis_client.execute_command("tree.init", "bad", "FROMPATHS", "a/b", "b/c")
+    with pytest.raises(ResponseError, match="invalid path a//b"):
+        redis_client.execute_command("tree.init", "bad", "FROMPATHS", "a//b")
+    with pytest.raises(ResponseError, match="FROMPATHS needs at least one path"):
+        redis_client.execute_command("tree.init", "bad", "UNIQUE", "FROMPATHS")
+    with pytest.raises(ResponseError, match="unknown argument STRING"):
+        redis_client.execute_command("tree.init", "bad", "FORMAT", "STRING", "FROMPATHS", "a/b")
+    assert redis_client.exists("bad", "u") == 0
+
+
+def test_init_forest(redis_client):
+    assert redis_client.execute_command("tree.init", "f", "a (b c) d (e)", "FOREST") == "OK"
+    assert redis_client.execute_command("tree.get", "f") == "a( b c ) d( e )"
+    assert json.loads(redis_client.execute_command("tree.get", "f", "FORMAT", "JSON")) == [
+        {"value": "a", "children": [{"value": "b", "children": []}, {"value": "c", "children": []}]},
+        {"value": "d", "children": [{"value": "e", "children": []}]},
+    ]
+    assert redis_client.execute_command("tree.get", "f", "DEPTH", 0) == "a d"
+    assert redis_client.execute_command("tree.get", "f", "CHUNKED", 4) == ["4", "a( b c ) d( "]
+    assert redis_client.execute_command("tree.get", "f", "CHUNKED", 4, "CURSOR", 4) == ["0", "e )"]
+    assert redis_client.execute_command("tree.get", "f", "CHUNKED", 4, "CURSOR", 2 ** 64 - 1) == ["0", ""]
+    assert redis_client.execute_command("tree.root", "f") == ["a", "d"]
+    info = redis_client.execute_command("tree.info", "f")
+    fields = dict(zip(info[::2], info[1::2]))
+    assert (fields["nodes"], fields["height"], fields["leaves"], fields["forest"]) == (5, 1, 3, 1)
+
+    # paths and depths start at the root a node is under, which has no father
+    assert redis_client.execute_command("tree.locate_all", "f", "e") == ["d/e"]
+    assert redis_client.execute_command("tree.depth", "f", "d") == 0
+    assert redis_client.execute_command("tree.depth", "f", "e") == 1
+    assert redis_client.execute_command("tree.get_father", "f", "d") is None
+    assert redis_client.execute_command("tree.lca", "f", "b", "e") is None
+    assert redis_client.execute_command("tree.contains_path", "f", "d/e") == 1
+    assert redis_client.execute_command("tree.flatten", "f") == ["a", "b", "c", "d", "e"]
+    assert redis_client.execute_command("tree.count_match", "f", "*") == 5
+
+    # a path no root matches starts a new one
+    assert redis_client.execute_command("tree.add_path", "f", "x/y") == 2
+    assert redis_client.execute_command("tree.get", "f") == "a( b c ) d( e ) x( y )"
+
+    # JSON takes an array of trees, FROMPATHS paths from any root
+    assert redis_client.execute_command("tree.init", "j", '[{"value": "a"}, {"value": "b"}]', "FOREST", "FORMAT", "JSON") == "OK"
+    assert redis_client.execute_command("tree.get", "j") == "a b"
+    assert redis_client.execute_command("tree.init", "p", "FOREST", "FROMPATHS", "a/b", "c", "a/d") == "OK"
+    assert redis_client.execute_command("tree.get", "p") == "a( b d ) c"
+
+    # removing the last root removes the key
+    assert redis_client.execute_command("tree.del_subtree", "j", "a") == "a"
+    assert redis_client.execute_command("tree.get", "j") == "b"
+    assert redis_client.execute_command("tree.del_subtree", "j", "b") == "b"
+    assert redis_client.exists("j") == 0
+
+    # several roots need FOREST
+    with pytest.raises(ResponseError, match="multiple roots in tree string"):
+        redis_client.execute_command("tree.init", "bad", "a b")
+    with pytest.raises(ResponseError, match="a forest needs at least one root"):
+        redis_client.execute_command("tree.init", "bad", "[]", "FOREST", "FORMAT", "JSON")
+    assert redis_client.exists("bad") == 0
+
+
+def test_fromedges_toedges(redis_client):
+    assert redis_client.execute_command("tree.fromedges", "org", "ceo", "cto", "dev", "ceo", "cto", "ceo", "cfo", "cto", "ops") == "OK"
+    assert redis_client.execute_command("tree.get", "org") == "ceo( cto( dev ops ) cfo )"
+    edges = redis_client.execute_command("tree.toedges", "org")
+    assert edges == ["ceo", "cto", "ceo", "cfo", "cto", "dev", "cto", "ops"]
+    assert redis_client.execute_command("tree.toedges", "org", "cto") == ["cto", "dev", "cto", "ops"]
+    assert redis_client.execute_command("tree.toedges", "org", "dev") == []
+
+    # the export builds the same tree again
+    redis_client.execute_command("tree.fromedges", "copy", "ceo", *edges)
+    assert redis_client.execute_command("tree.equals", "org", "copy") == 1
+    assert redis_client.execute_command("tree.fromedges", "single", "ceo") == "OK"
+    assert redis_client.execute_command("tree.get", "single") == "ceo"
+
+    with pytest.raises(ResponseError, match="dev has more than one parent"):
+        redis_client.execute_command("tree.fromedges", "bad", "ceo", "ceo", "dev", "cto", "dev")
+    with pytest.raises(ResponseError, match="cycle through ceo"):
+        redis_client.execute_command("tree.fromedges", "bad", "ceo", "ceo", "cto", "cto", "ceo")
+    with pytest.raises(ResponseError, match="cycle through a"):
+        redis_client.execute_command("tree.fromedges", "bad", "ceo", "ceo", "cto", "a", "b", "b", "a")
+    with pytest.raises(ResponseError, match="x is not connected to the root ceo"):
+        redis_client.execute_command("tree.fromedges", "bad", "ceo", "ceo", "cto", "x", "y")
+    with pytest.raises(ResponseError, match="edges come as parent child pairs"):
+        redis_client.execute_command("tree.fromedges", "bad", "ceo", "ceo")
+    assert redis_client.exists("bad") == 0
+    assert redis_client.execute_command("tree.toedges", "nope") is None
+
+
+def test_getset(redis_client):
+    assert redis_client.execute_command("tree.getset", "hello", "a (b)") is None
+    assert redis_client.execute_command("tree.getset", "hello", "x (y z)") == "a( b )"
+    assert redis_client.execute_command("tree.get", "hello") == "x( y z )"
+
+    # the flags stay with the key
+    redis_client.execute_command("tree.init", "shop", "Root (Food)", "CASEINSENSITIVE", "UNIQUE")
+    with pytest.raises(ResponseError, match="duplicate node value"):
+        redis_client.execute_command("tree.getset", "shop", "Root (food FOOD)")
+    previous = redis_client.execute_command("tree.getset", "shop", json.dumps({"value": "Root", "children": [{"value": "Toys"}]}), "FORMAT", "JSON")
+    assert json.loads(previous) == {"value": "Root", "children": [{"value": "Food", "children": []}]}
+    assert redis_client.execute_command("tree.get_subtree", "shop", "TOYS") == "Toys"
+
+    with pytest.raises(ResponseError, match="is not closed"):
+        redis_client.execute_command("tree.getset", "hello", "a (")
+    assert redis_client.execute_command("tree.get", "hello") == "x( y z )"
+    redis_client.set("s", "not a tree")
+    with pytest.raises(ResponseError, match="WRONGTYPE"):
+        redis_client.execute_command("tree.getset", "s", "a")
+
+
+def test_mget(redis_client):
+    redis_client.execute_command("tree.init", "a", "0 (1 2)")
+    redis_client.execute_command("tree.init", "b", "x (y)")
+    redis_client.set("s", "not a tree")
+    assert redis_client.execute_command("tree.mget", "a", "nope", "b", "s") == ["0( 1 2 )", None, "x( y )", None]
+    assert redis_client.execute_command("command", "getkeys", "tree.mget", "a", "b", "c") == ["a", "b", "c"]
+    with pytest.raises(ResponseError, match="wrong number of arguments"):
+        redis_client.execute_command("tree.mget")
+
+
+def test_get_format_json(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a))")
+    assert json.loads(redis_client.execute_command("tree.get", "hello", "FORMAT", "JSON")) == {
+        "value": "0", "children": [
+            {"value": "1", "children": []},
+            {"value": "2", "children": [{"value": "a", "children": []}]},
+        ],
+    }
+    assert redis_client.execute_command("tree.get", "hello", "FORMAT", "string") == "0( 1 2( a ) )"
+
+    path, subtree = redis_client.execute_command("tree.get_subtree", "hello", "2", "WITHPATH", "FORMAT", "JSON")
+    assert path == "0/2"
+    assert json.loads(subtree) == {"value": "2", "children": [{"value": "a", "children": []}]}
+
+    with pytest.raises(ResponseError, match="unknown format XML"):
+        redis_client.execute_command("tree.get", "hello", "FORMAT", "XML")
+
+
+def test_get_format_nested(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)))")
+    assert redis_client.execute_command("tree.get", "hello", "FORMAT", "NESTED") == \
+        ["0", ["1"], ["2", ["a"], ["b", ["d"]]]]
+    assert redis_client.execute_command("tree.get_subtree", "hello", "d", "FORMAT", "NESTED") == ["d"]
+    assert redis_client.execute_command("tree.get_subtree", "hello", "b", "WITHPATH", "FORMAT", "NESTED") == \
+        ["0/2/b", ["b", ["d"]]]
+
+    with pytest.raises(ResponseError, match="only for replies"):
+        redis_client.execute_command("tree.init", "hello", "0", "FORMAT", "NESTED")
+
+
+def test_init_format_json(redis_client):
+    redis_client.execute_command("tree.init", "hello", json.dumps({
+        "value": "0", "children": [{"value": "1"}, {"value": "2", "children": [{"value": "a"}]}],
+    }), "FORMAT", "JSON")
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( a ) )"
+
+    exported = redis_client.execute_command("tree.get", "hello", "FORMAT", "JSON")
+    redis_client.execute_command("tree.init", "world", exported, "FORMAT", "JSON", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.get", "world") == "0( 1 2( a ) )"
+
+    with pytest.raises(ResponseError, match="node without value in JSON tree"):
+        redis_client.execute_command("tree.init", "hello", '{"children": []}', "FORMAT", "JSON")
+    with pytest.raises(ResponseError, match="unexpected end of JSON tree"):
+        redis_client.execute_command("tree.init", "hello", '{"value": "0", "children": [', "FORMAT", "JSON")
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.init", "hello", '{"value": ""}', "FORMAT", "JSON")
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( a ) )"
+
+
+def test_hash(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)) e f (g h))")
+    tree, tree_hash = redis_client.execute_command("tree.get", "hello", "WITHHASH")
+    assert tree == "0( 1 2( a b( d ) ) e f( g h ) )"
+    assert tree_hash == redis_client.execute_command("tree.hash", "hello")
+    assert len(tree_hash) == 16
+
+    subtree, subtree_hash = redis_client.execute_command("tree.get_subtree", "hello", "2", "WITHHASH")
+    assert subtree == "2( a b( d ) )"
+    assert subtree_hash == redis_client.execute_command("tree.hash", "hello", "2")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "2", "WITHPATH", "WITHHASH") == \
+        ["0/2", "2( a b( d ) )", subtree_hash]
+
+    redis_client.execute_command("tree.set_subtree", "hello", "d", "x")
+    assert redis_client.execute_command("tree.hash", "hello", "2") != subtree_hash
+    assert redis_client.execute_command("tree.hash", "hello") != tree_hash
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.hash", "hello", "nope")
+
+
+def test_shape(redis_client):
+    redis_client.execute_command("tree.init", "hello", "r (x y (secret z))")
+    redis_client.execute_command("tree.init", "world", "a (b c (d e))")
+    assert redis_client.execute_command("tree.shape", "hello") == "0( 0 1( 0 1 ) )"
+    assert redis_client.execute_command("tree.shape", "hello", "y") == "0( 0 1 )"
+    assert redis_client.execute_command("tree.shape_hash", "hello") == redis_client.execute_command("tree.shape_hash", "world")
+    assert redis_client.execute_command("tree.shape_hash", "hello") != redis_client.execute_command("tree.shape_hash", "hello", "y")
+    assert redis_client.execute_command("tree.shape", "nope") is None
+
+
+def test_export_commands(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)))", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.export_commands", "hello") == \
+        [["tree.init", "hello", "0( 1 2( a b( d ) ) )", "CASEINSENSITIVE"]]
+    commands = redis_client.execute_command("tree.export_commands", "hello", "B")
+    assert commands == [["tree.init", "hello", "b( d )", "CASEINSENSITIVE"]]
+
+    redis_client.execute_command("tree.del", "hello")
+    for command in commands:
+        redis_client.execute_command(*command)
+    assert redis_client.execute_command("tree.get", "hello") == "b( d )"
+    assert redis_client.execute_command("tree.export_commands", "nope") is None
+
+
+def test_todot(redis_client):
+    redis_client.execute_command("tree.init", "hello", "a (b (a) c)")
+    assert redis_client.execute_command("tree.todot", "hello") == "\n".join([
+        "digraph tree {",
+        '  n0 [label="a"];',
+        '  n1 [label="b"];',
+        "  n0 -> n1;",
+        '  n2 [label="a"];',
+        "  n1 -> n2;",
+        '  n3 [label="c"];',
+        "  n0 -> n3;",
+        "}",
+    ])
+    assert redis_client.execute_command("tree.todot", "hello", "c") == 'digraph tree {\n  n0 [label="c"];\n}'
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.todot", "hello", "nope")
+    assert redis_client.execute_command("tree.todot", "nope") is None
+
+    # the formats of tree.get and tree.get_subtree, and DOT for them in turn
+    assert redis_client.execute_command("tree.todot", "hello", "FORMAT", "STRING") == "a( b( a ) c )"
+    assert redis_client.execute_command("tree.todot", "hello", "c", "FORMAT", "NESTED") == ["c"]
+    assert redis_client.execute_command("tree.get_subtree", "hello", "c", "FORMAT", "DOT") == \
+        'digraph tree {\n  n0 [label="c"];\n}'
+    assert redis_client.execute_command("tree.get", "hello", "DEPTH", "0", "FORMAT", "dot") == \
+        'digraph tree {\n  n0 [label="a"];\n}'
+    with pytest.raises(ResponseError, match="FORMAT DOT is only for replies"):
+        redis_client.execute_command("tree.init", "other", "a", "FORMAT", "DOT")
+    with pytest.raises(ResponseError, match="unknown format PNG"):
+        redis_client.execute_command("tree.todot", "hello", "FORMAT", "PNG")
+
+
 def test_del(redis_client):
     redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)) e f (g h))")
     redis_client.execute_command("tree.del", "hello")
@@ -30,20 +413,1231 @@ def test_get_subtree(redis_client):
     assert redis_client.execute_command("tree.get_subtree", "hello", "f") == "f( g h )"
 
 
+def test_get_subtree_with_path(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "b", "WITHPATH") == ["0/2/b", "b( d )"]
+    assert redis_client.execute_command("tree.get_subtree", "hello", "0", "withpath") == ["0", "0( 1 2( a b( d ) ) e f( g h ) )"]
+    with pytest.raises(ResponseError, match="no node x"):
+        redis_client.execute_command("tree.get_subtree", "hello", "x", "WITHPATH")
+
+
+def test_locate_all(redis_client):
+    redis_client.execute_command("tree.init", "hello", "a (b (x c (x)) x d)")
+    assert redis_client.execute_command("tree.locate_all", "hello", "x") == ["a/b/x", "a/b/c/x", "a/x"]
+    assert redis_client.execute_command("tree.locate_all", "hello", "y") == []
+    assert redis_client.execute_command("tree.locate_all", "nope", "y") is None
+
+
+def test_search(redis_client):
+    redis_client.execute_command("tree.init", "orders", "orders (2023 (order-2023-1) 2024 (order-2024-1 order-2024-2 [x]))")
+    assert redis_client.execute_command("tree.search", "orders", "order-2024-*") == \
+        ["orders/2024/order-2024-1", "orders/2024/order-2024-2"]
+    assert redis_client.execute_command("tree.search", "orders", "order-202?-1") == \
+        ["orders/2023/order-2023-1", "orders/2024/order-2024-1"]
+    assert redis_client.execute_command("tree.search", "orders", "*-[^1]") == ["orders/2024/order-2024-2"]
+    assert redis_client.execute_command("tree.search", "orders", "\\[x]") == ["orders/2024/[x]"]
+    assert redis_client.execute_command("tree.search", "orders", "order-*", "COUNT", "1") == ["orders/2023/order-2023-1"]
+    assert redis_client.execute_command("tree.search", "orders", "ORDER-*") == []
+    assert redis_client.execute_command("tree.search", "nope", "*") is None
+
+    redis_client.execute_command("tree.init", "shop", "Root (Food Toys)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.search", "shop", "f*") == ["Root/Food"]
+    with pytest.raises(ResponseError, match="COUNT must be positive"):
+        redis_client.execute_command("tree.search", "shop", "*", "COUNT", "0")
+
+
+def test_count_match(redis_client):
+    redis_client.execute_command("tree.init", "orders", "orders (2023 (order-2023-1) 2024 (order-2024-1 order-2024-2))")
+    assert redis_client.execute_command("tree.count_match", "orders", "order-*") == 3
+    assert redis_client.execute_command("tree.count_match", "orders", "order-*", "UNDER", "2024") == 2
+    assert redis_client.execute_command("tree.count_match", "orders", "20*", "UNDER", "2024") == 1
+    assert redis_client.execute_command("tree.count_match", "orders", "*") == 6
+    assert redis_client.execute_command("tree.count_match", "orders", "ORDER-*") == 0
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.count_match", "orders", "*", "UNDER", "nope")
+    assert redis_client.execute_command("tree.count_match", "nope", "*") is None
+
+    redis_client.execute_command("tree.init", "shop", "Root (Food Toys)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.count_match", "shop", "f*") == 1
+
+
+def test_filter(redis_client):
+    redis_client.execute_command("tree.init", "orders", "orders (2023 (order-2023-1) 2024 (order-2024-1 order-2024-2) 2025)")
+    assert redis_client.execute_command("tree.filter", "orders", "*-2") == "orders( 2024( order-2024-2 ) )"
+    assert redis_client.execute_command("tree.filter", "orders", "order-*-1") == \
+        "orders( 2023( order-2023-1 ) 2024( order-2024-1 ) )"
+    # a match keeps its ancestors but not its children
+    assert redis_client.execute_command("tree.filter", "orders", "202?") == "orders( 2023 2024 2025 )"
+    assert redis_client.execute_command("tree.filter", "orders", "*") == redis_client.execute_command("tree.get", "orders")
+    assert redis_client.execute_command("tree.filter", "orders", "ORDER-*") is None
+    assert json.loads(redis_client.execute_command("tree.filter", "orders", "2025", "FORMAT", "JSON")) == \
+        {"value": "orders", "children": [{"value": "2025", "children": []}]}
+    with pytest.raises(ResponseError, match="unknown argument NOPE"):
+        redis_client.execute_command("tree.filter", "orders", "*", "NOPE")
+    assert redis_client.execute_command("tree.filter", "nope", "*") is None
+
+    redis_client.execute_command("tree.init", "shop", "Root (Food (Fruit) Toys)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.filter", "shop", "f*") == "Root( Food( Fruit ) )"
+
+
+def test_randnode(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    values = {"0", "1", "2", "a", "k", "j", "bb", "b", "d", "e", "f", "g", "h"}
+    assert redis_client.execute_command("tree.randnode", "hello") in values
+    picked = redis_client.execute_command("tree.randnode", "hello", "5")
+    assert len(picked) == 5 and len(set(picked)) == 5 and set(picked) <= values
+    assert sorted(redis_client.execute_command("tree.randnode", "hello", "100")) == sorted(values)
+    assert redis_client.execute_command("tree.randnode", "hello", "0") == []
+    picked = redis_client.execute_command("tree.randnode", "hello", "-30")
+    assert len(picked) == 30 and set(picked) <= values
+
+    # every node turns up sooner or later
+    seen = set()
+    for _ in range(50):
+        seen.update(redis_client.execute_command("tree.randnode", "hello", "-20"))
+    assert seen == values
+
+    redis_client.execute_command("tree.init", "single", "0")
+    assert redis_client.execute_command("tree.randnode", "single", "-3") == ["0", "0", "0"]
+    assert len(redis_client.execute_command("tree.randnode", "single", "-100000")) == 100000
+    with pytest.raises(ResponseError, match="count is out of range"):
+        redis_client.execute_command("tree.randnode", "single", "-100001")
+    with pytest.raises(ResponseError, match="count is out of range"):
+        redis_client.execute_command("tree.randnode", "single", str(-2**63))
+    assert redis_client.execute_command("tree.randnode", "nope") is None
+
+
+def test_exists_node(redis_client):
+    redis_client.execute_command("tree.init", "hello", "a (b (x c (y)) z d)")
+    assert redis_client.execute_command("tree.exists_node", "hello", "y") == 1
+    assert redis_client.execute_command("tree.exists_node", "hello", "nope") == 0
+    assert redis_client.execute_command("tree.exists_node", "hello", "y", "BYPATH", "a/b") == 1
+    assert redis_client.execute_command("tree.exists_node", "hello", "y", "BYPATH", "a/z") == 0
+    assert redis_client.execute_command("tree.exists_node", "hello", "y", "BYPATH", "a/nope") == 0
+    assert redis_client.execute_command("tree.exists_node", "nope", "y") == 0
+
+
+def test_contains_path(redis_client):
+    redis_client.execute_command("tree.init", "hello", "a (b (x) b (y) z)")
+    assert redis_client.execute_command("tree.contains_path", "hello", "a/b/x") == 1
+    # the second b is tried when the first one does not lead on
+    assert redis_client.execute_command("tree.contains_path", "hello", "a/b/y") == 1
+    assert redis_client.execute_command("tree.contains_path", "hello", "a") == 1
+    assert redis_client.execute_command("tree.contains_path", "hello", "a/z/y") == 0
+    assert redis_client.execute_command("tree.contains_path", "hello", "b/x") == 0
+    assert redis_client.execute_command("tree.contains_path", "nope", "a") == 0
+
+    redis_client.execute_command("tree.init", "mixed", "A (B)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.contains_path", "mixed", "a/b") == 1
+
+
+def test_leaf_paths(redis_client):
+    redis_client.execute_command("tree.init", "hello", "a (b (x c (y)) z d)")
+    assert redis_client.execute_command("tree.leaf_paths", "hello") == ["a/b/x", "a/b/c/y", "a/z", "a/d"]
+    assert redis_client.execute_command("tree.leaf_paths", "hello", "b", "SEPARATOR", ".") == ["a.b.x", "a.b.c.y"]
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.leaf_paths", "hello", "nope")
+
+    redis_client.execute_command("tree.init", "wide", "r (" + " ".join(str(i) for i in range(15)) + ")")
+    cursor, page = redis_client.execute_command("tree.leaf_paths", "wide", "CURSOR", "0")
+    assert cursor == "10" and page == ["r/%d" % i for i in range(10)]
+    cursor, page = redis_client.execute_command("tree.leaf_paths", "wide", "CURSOR", cursor)
+    assert cursor == "0" and page == ["r/%d" % i for i in range(10, 15)]
+
+
+def test_leaves(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.leaves", "hello") == ["1", "j", "bb", "d", "e", "g", "h"]
+    assert redis_client.execute_command("tree.leaves", "hello", "a") == ["j", "bb"]
+    assert redis_client.execute_command("tree.leaves", "hello", "h") == ["h"]
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.leaves", "hello", "nope")
+
+
+def test_scan(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    cursor, page = redis_client.execute_command("tree.scan", "hello", "0", "COUNT", "5")
+    assert cursor == "5" and page == ["0", "1", "2", "e", "f"]
+    cursor, page = redis_client.execute_command("tree.scan", "hello", cursor, "COUNT", "5")
+    assert cursor == "10" and page == ["a", "b", "g", "h", "k"]
+    cursor, page = redis_client.execute_command("tree.scan", "hello", cursor, "COUNT", "5")
+    assert cursor == "0" and page == ["bb", "d", "j"]
+    assert redis_client.execute_command("tree.scan", "hello", "0")[0] == "10"
+    assert redis_client.execute_command("tree.scan", "nope", "0") is None
+    with pytest.raises(ResponseError, match="COUNT must be positive"):
+        redis_client.execute_command("tree.scan", "hello", "0", "COUNT", "0")
+
+
+def test_flatten(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    pre = ["0", "1", "2", "a", "k", "j", "bb", "b", "d", "e", "f", "g", "h"]
+    assert redis_client.execute_command("tree.flatten", "hello") == pre
+    assert redis_client.execute_command("tree.flatten", "hello", "pre") == pre
+    assert redis_client.execute_command("tree.flatten", "hello", "POST") == ["1", "j", "k", "bb", "a", "d", "b", "2", "e", "g", "h", "f", "0"]
+    assert redis_client.execute_command("tree.flatten", "hello", "BFS") == ["0", "1", "2", "e", "f", "a", "b", "g", "h", "k", "bb", "d", "j"]
+    redis_client.execute_command("tree.init", "single", "0")
+    assert redis_client.execute_command("tree.flatten", "single", "POST") == ["0"]
+    with pytest.raises(ResponseError, match="unknown order IN"):
+        redis_client.execute_command("tree.flatten", "hello", "IN")
+    assert redis_client.execute_command("tree.flatten", "nope") is None
+
+
+def test_del_children(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.del_children", "hello", "2") == 6
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1 2 e f( g h ) )"
+    # a leaf leaves the version alone
+    assert redis_client.execute_command("tree.version", "hello") == 2
+    assert redis_client.execute_command("tree.del_children", "hello", "2") == 0
+    assert redis_client.execute_command("tree.version", "hello") == 2
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.del_children", "hello", "nope")
+
+
+def test_prune(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (x-1 (x-2 (j) b) 2 (x-3 b (x-4)) e)")
+    assert redis_client.execute_command("tree.prune", "hello", "x-*", "LEAVESONLY") == 2
+    assert redis_client.execute_command("tree.get", "hello") == "0( x-1( x-2( j ) b ) 2( b ) e )"
+    assert redis_client.execute_command("tree.prune", "hello", "x-*") == 4
+    assert redis_client.execute_command("tree.get", "hello") == "0( 2( b ) e )"
+    assert redis_client.execute_command("tree.exists_node", "hello", "j") == 0
+    assert redis_client.execute_command("tree.prune", "hello", "x-*") == 0
+    # the first b is gone with x-1, the one left is found by value
+    assert redis_client.execute_command("tree.get_father", "hello", "b") == "2"
+    with pytest.raises(ResponseError, match="unknown argument NOPE"):
+        redis_client.execute_command("tree.prune", "hello", "*", "NOPE")
+
+    # a matching root takes the key along
+    assert redis_client.execute_command("tree.prune", "hello", "?") == 4
+    assert redis_client.execute_command("exists", "hello") == 0
+    assert redis_client.execute_command("tree.prune", "nope", "*") is None
+
+    redis_client.execute_command("tree.init", "shop", "Root (Food (Fruit) Toys)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.prune", "shop", "f*") == 2
+    assert redis_client.execute_command("tree.get", "shop") == "Root( Toys )"
+
+
+def test_walk(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 (2 3) 4)")
+    cursor, visit = redis_client.execute_command("tree.walk_start", "hello")
+    assert visit == ["event", "begin", "value", "0", "depth", 0, "children", 2]
+
+    def walk(command, *args):
+        visit = redis_client.execute_command(command, cursor, *args)
+        return visit and (visit[1], visit[3], visit[5])
+
+    assert [walk("tree.walk_next") for _ in range(8)] == [
+        ("begin", "1", 1), ("leaf", "2", 2), ("leaf", "3", 2), ("end", "1", 1), ("leaf", "4", 1), ("end", "0", 0), None, None]
+
+    cursor, _ = redis_client.execute_command("tree.walk_start", "hello", "1")
+    assert walk("tree.walk_to_child", 2) is None
+    assert walk("tree.walk_to_child", 1) == ("leaf", "3", 1)
+    assert walk("tree.walk_to_child", 0) is None
+    assert walk("tree.walk_to_parent") == ("end", "1", 0)
+    # the walk stays below where it started
+    assert walk("tree.walk_to_parent") is None
+    assert walk("tree.walk_next") is None
+    with pytest.raises(ResponseError, match="unknown walk cursor"):
+        walk("tree.walk_next")
+
+    # any change to the tree ends the walk
+    cursor, _ = redis_client.execute_command("tree.walk_start", "hello")
+    redis_client.execute_command("tree.set_subtree", "hello", "4", "5")
+    with pytest.raises(ResponseError, match="is stale, its tree changed"):
+        walk("tree.walk_next")
+    with pytest.raises(ResponseError, match="unknown walk cursor"):
+        walk("tree.walk_next")
+
+    # and only the connection that started it walks it
+    cursor, _ = redis_client.execute_command("tree.walk_start", "hello")
+    other = redis.Redis(**redis_client.connection_pool.connection_kwargs)
+    with pytest.raises(ResponseError, match="unknown walk cursor"):
+        other.execute_command("tree.walk_next", cursor)
+    other.close()
+    assert walk("tree.walk_next") == ("begin", "1", 1)
+
+    assert redis_client.execute_command("tree.walk_start", "nope") is None
+    with pytest.raises(ResponseError, match="no node 9"):
+        redis_client.execute_command("tree.walk_start", "hello", "9")
+
+
+def test_walk_acl(redis_client):
+    info = redis_client.execute_command("tree.info")
+    if not dict(zip(info[::2], info[1::2]))["keyspec"]:
+        pytest.skip("checking key permissions from a module needs redis 7")
+    redis_client.execute_command("tree.init", "hello", "0 (1)")
+    redis_client.execute_command("acl", "setuser", "walker", "on", "nopass", "~other", "+@all")
+    try:
+        # the moves name no key, the tree is checked against the user the connection runs as now
+        cursor, _ = redis_client.execute_command("tree.walk_start", "hello")
+        redis_client.execute_command("auth", "walker", "x")
+        with pytest.raises(ResponseError, match="no permission to read the tree walk cursor"):
+            redis_client.execute_command("tree.walk_next", cursor)
+    finally:
+        redis_client.execute_command("auth", "default", "x")
+        redis_client.execute_command("acl", "deluser", "walker")
+    assert redis_client.execute_command("tree.walk_next", cursor)[3] == "1"
+
+
+def test_version(redis_client):
+    assert redis_client.execute_command("tree.version", "hello") is None
+    # IFVERSION 0 only creates the tree
+    assert redis_client.execute_command("tree.init", "hello", "0 (1 2)", "IFVERSION", 0) == "OK"
+    assert redis_client.execute_command("tree.version", "hello") == 1
+    with pytest.raises(ResponseError, match="version mismatch, the tree is at version 1"):
+        redis_client.execute_command("tree.init", "hello", "0 (1 2)", "IFVERSION", 0)
+
+    assert redis_client.execute_command("tree.set_subtree", "hello", "1", "3", "IFVERSION", 1) == "OK"
+    assert redis_client.execute_command("tree.version", "hello") == 2
+    with pytest.raises(ResponseError, match="version mismatch, the tree is at version 2"):
+        redis_client.execute_command("tree.del_subtree", "hello", "3", "IFVERSION", 1)
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1( 3 ) 2 )"
+    with pytest.raises(ResponseError, match="IFVERSION takes a version number"):
+        redis_client.execute_command("tree.del_subtree", "hello", "3", "IFVERSION", "latest")
+
+    # writes that change nothing leave the version alone, reads never touch it
+    assert redis_client.execute_command("tree.prune", "hello", "x*") == 0
+    redis_client.execute_command("tree.get_subtree", "hello", "1")
+    assert redis_client.execute_command("tree.version", "hello") == 2
+    redis_client.execute_command("tree.setattr", "hello", "2", "color", "red")
+    assert redis_client.execute_command("tree.version", "hello") == 3
+
+    # replacing the tree counts on, and the version survives a reload
+    redis_client.execute_command("tree.getset", "hello", "a (b)")
+    assert redis_client.execute_command("tree.version", "hello") == 4
+    redis_client.execute_command("debug", "reload")
+    assert redis_client.execute_command("tree.version", "hello") == 4
+
+    # graft checks the destination
+    redis_client.execute_command("tree.init", "src", "x")
+    with pytest.raises(ResponseError, match="version mismatch"):
+        redis_client.execute_command("tree.graft", "hello", "b", "src", "IFVERSION", 3)
+    assert redis_client.execute_command("tree.graft", "hello", "b", "src", "IFVERSION", 4) == 1
+
+    # ahead of the list of a write, past it IFVERSION is just another item
+    assert redis_client.execute_command("tree.setattr", "hello", "b", "IFVERSION", 5, "IFVERSION", 7) == 1
+    assert redis_client.execute_command("tree.getattr", "hello", "b", "IFVERSION") == "7"
+    assert redis_client.execute_command("tree.append_leaves", "hello", "a", "IFVERSION", 6, "c", "IFVERSION", 9) == 3
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( x ) c IFVERSION 9 )"
+    with pytest.raises(ResponseError, match="version mismatch, the tree is at version 7"):
+        redis_client.execute_command("tree.apply_diff", "hello", "IFVERSION", 6, "DELETE", "a/c")
+    assert redis_client.execute_command("tree.apply_diff", "hello", "IFVERSION", 7, "DELETE", "a/9") == 1
+    with pytest.raises(ResponseError, match="edges come as parent child pairs"):
+        redis_client.execute_command("tree.fromedges", "hello", "a", "IFVERSION", 8, "a")
+    assert redis_client.execute_command("tree.fromedges", "hello", "a", "IFVERSION", 8, "a", "IFVERSION") == "OK"
+    assert redis_client.execute_command("tree.get", "hello") == "a( IFVERSION )"
+
+    # the version an AOF rewrite carries is not for clients to set
+    with pytest.raises(ResponseError, match="unknown argument VERSION"):
+        redis_client.execute_command("tree.init", "hello", "a", "VERSION", 1)
+
+
+def test_notify(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    assert redis_client.execute_command("tree.notify", "hello") == []
+    assert redis_client.execute_command("tree.notify", "hello", "STREAM", "hello:changes") == "OK"
+    assert redis_client.execute_command("tree.notify", "hello") == ["stream", "hello:changes"]
+
+    redis_client.execute_command("tree.set_subtree", "hello", "1", "a (b)", "FRONT")
+    # nothing changed, nothing published
+    redis_client.execute_command("tree.prune", "hello", "x*")
+    redis_client.execute_command("tree.setattr", "hello", "2", "color", "red")
+    redis_client.execute_command("tree.del_subtree", "hello", "0")
+    events = [json.loads(fields["event"]) for _, fields in redis_client.xrange("hello:changes")]
+    assert events == [
+        {"op": "set_subtree", "key": "hello", "version": 2, "parent": "1", "subtree": "a (b)", "args": ["FRONT"]},
+        {"op": "setattr", "key": "hello", "version": 3, "node": "2", "args": ["color", "red"]},
+        {"op": "del_subtree", "key": "hello", "version": 0, "node": "0"},
+    ]
+
+    # to a channel, kept when the tree is replaced
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    redis_client.execute_command("tree.notify", "hello", "CHANNEL", "hello:changes:live")
+    pubsub = redis_client.pubsub()
+    pubsub.subscribe("hello:changes:live")
+    assert pubsub.get_message(timeout=1)["type"] == "subscribe"
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 3)", "IFVERSION", 1)
+    assert redis_client.execute_command("tree.notify", "hello") == ["channel", "hello:changes:live"]
+    redis_client.execute_command("tree.rename_node", "hello", "3", "three")
+    messages = [pubsub.get_message(timeout=1) for _ in range(2)]
+    assert [json.loads(message["data"]) for message in messages] == [
+        {"op": "init", "key": "hello", "version": 2, "tree": "0 (1 2 3)", "args": ["IFVERSION", "1"]},
+        {"op": "rename_node", "key": "hello", "version": 3, "node": "3", "new_value": "three"},
+    ]
+
+    assert redis_client.execute_command("tree.notify", "hello", "OFF") == "OK"
+    redis_client.execute_command("tree.del_subtree", "hello", "three")
+    assert pubsub.get_message(timeout=0.1) is None
+    pubsub.close()
+
+    with pytest.raises(ResponseError, match="unknown argument NOPE"):
+        redis_client.execute_command("tree.notify", "hello", "NOPE")
+    assert redis_client.execute_command("tree.notify", "nope", "CHANNEL", "c") is None
+
+
+def test_expire_node(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 (2 3) 4 (5))")
+    assert redis_client.execute_command("tree.ttl_node", "hello", "1") == -1
+    assert redis_client.execute_command("tree.expire_node", "hello", "1", "100") == 1
+    assert 99 <= redis_client.execute_command("tree.ttl_node", "hello", "1") <= 100
+    assert redis_client.execute_command("tree.persist_node", "hello", "1") == 1
+    assert redis_client.execute_command("tree.persist_node", "hello", "1") == 0
+    assert redis_client.execute_command("tree.ttl_node", "hello", "1") == -1
+
+    # a renamed node keeps its deadline, by tree.rename_node or a RELABEL
+    redis_client.execute_command("tree.expire_node", "hello", "4", "100")
+    assert redis_client.execute_command("tree.rename_node", "hello", "4", "four") == 1
+    assert 99 <= redis_client.execute_command("tree.ttl_node", "hello", "four") <= 100
+    assert redis_client.execute_command("tree.apply_diff", "hello", "RELABEL", "0/four", "4") == 1
+    assert 99 <= redis_client.execute_command("tree.ttl_node", "hello", "4") <= 100
+    redis_client.execute_command("tree.persist_node", "hello", "4")
+
+    # gone before the next command reads the tree
+    assert redis_client.execute_command("tree.expire_node", "hello", "0/1", "PXAT", int(time.time() * 1000) + 50, "PATH") == 1
+    time.sleep(0.1)
+    assert redis_client.execute_command("tree.get", "hello") == "0( 4( 5 ) )"
+    assert redis_client.execute_command("tree.exists_node", "hello", "2") == 0
+
+    # a deadline already past takes the subtree out right away
+    assert redis_client.execute_command("tree.expire_node", "hello", "5", "0") == 1
+    assert redis_client.execute_command("tree.get", "hello") == "0( 4 )"
+
+    # or the timer does, with no module command touching the key
+    redis_client.execute_command("tree.expire_node", "hello", "0", "PXAT", int(time.time() * 1000) + 50)
+    for _ in range(50):
+        if redis_client.execute_command("exists", "hello") == 0:
+            break
+        time.sleep(0.02)
+    assert redis_client.execute_command("exists", "hello") == 0
+
+    # tree.purge_expired at a given time, as masters send it to replicas
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    redis_client.execute_command("tree.expire_node", "hello", "1", "PXAT", 4102444800000)
+    assert redis_client.execute_command("tree.purge_expired", "hello") == 0
+    assert redis_client.execute_command("tree.purge_expired", "hello", 4102444800000) == 1
+    assert redis_client.execute_command("tree.get", "hello") == "0( 2 )"
+
+    # a root past its deadline takes the key along
+    assert redis_client.execute_command("tree.expire_node", "hello", "0", "0") == 1
+    assert redis_client.execute_command("exists", "hello") == 0
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    assert redis_client.execute_command("tree.expire_node", "hello", "0", "PXAT", int(time.time() * 1000) - 1000) == 1
+    assert redis_client.execute_command("exists", "hello") == 0
+
+    redis_client.execute_command("tree.init", "hello", "0 (1)")
+    with pytest.raises(ResponseError, match="invalid expire time soon"):
+        redis_client.execute_command("tree.expire_node", "hello", "1", "soon")
+    with pytest.raises(ResponseError, match="unknown argument NOPE"):
+        redis_client.execute_command("tree.expire_node", "hello", "1", "10", "NOPE")
+    with pytest.raises(ResponseError, match="no node 9"):
+        redis_client.execute_command("tree.expire_node", "hello", "9", "10")
+    assert redis_client.execute_command("tree.expire_node", "nope", "1", "10") is None
+    assert redis_client.execute_command("tree.ttl_node", "nope", "1") is None
+
+
+def test_expire_node_persistence(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 (2) 1 (3))")
+    redis_client.execute_command("tree.expire_node", "hello", "0/1", "PXAT", 4102444800000, "PATH")
+    redis_client.execute_command("tree.expire_node", "hello", "3", "PXAT", 4102444800000)
+    digest = redis_client.execute_command("debug", "digest-value", "hello")
+    redis_client.execute_command("debug", "reload")
+    assert redis_client.execute_command("tree.ttl_node", "hello", "1") > 0
+    assert redis_client.execute_command("tree.ttl_node", "hello", "3") > 0
+    assert redis_client.execute_command("debug", "digest-value", "hello") == digest
+
+    # deadlines count in the digest
+    redis_client.execute_command("tree.persist_node", "hello", "3")
+    assert redis_client.execute_command("debug", "digest-value", "hello") != digest
+
+    # and move along with a grafted tree
+    redis_client.execute_command("tree.init", "dst", "a (b)")
+    redis_client.execute_command("tree.graft", "dst", "b", "hello")
+    assert redis_client.execute_command("tree.ttl_node", "dst", "1") > 0
+
+
 def test_set_subtree(redis_client):
     redis_client.execute_command("tree.init", "hello", "0 (1 2)")
     redis_client.execute_command("tree.set_subtree", "hello", "2", "3 ( 4 5)")
     assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( 3( 4 5 ) ) )"
 
 
+def test_set_subtree_position(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    redis_client.execute_command("tree.set_subtree", "hello", "0", "a", "FRONT")
+    assert redis_client.execute_command("tree.get", "hello") == "0( a 1 2 )"
+    redis_client.execute_command("tree.set_subtree", "hello", "0", "b (c)", "BEFORE", "2")
+    assert redis_client.execute_command("tree.get", "hello") == "0( a 1 b( c ) 2 )"
+    redis_client.execute_command("tree.set_subtree", "hello", "0", "d", "AFTER", "a")
+    assert redis_client.execute_command("tree.get", "hello") == "0( a d 1 b( c ) 2 )"
+
+    with pytest.raises(ResponseError, match="c is not a child of 0"):
+        redis_client.execute_command("tree.set_subtree", "hello", "0", "e", "AFTER", "c")
+
+
+def test_set_subtree_many(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    redis_client.execute_command("tree.set_subtree", "hello", "2", "a (b)", "c", "d")
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( a( b ) c d ) )"
+    redis_client.execute_command("tree.set_subtree", "hello", "0", "e", "f", "FRONT")
+    assert redis_client.execute_command("tree.get", "hello") == "0( e f 1 2( a( b ) c d ) )"
+    redis_client.execute_command("tree.set_subtree", "hello", "2", "g", "h", "BEFORE", "c")
+    redis_client.execute_command("tree.set_subtree", "hello", "2", "i", "j", "AFTER", "c")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "2") == "2( a( b ) g h c i j d )"
+
+    # one subtree that does not parse and none is added
+    with pytest.raises(ResponseError, match="is not closed"):
+        redis_client.execute_command("tree.set_subtree", "hello", "1", "x", "y (")
+    with pytest.raises(ResponseError, match="wrong number of arguments"):
+        redis_client.execute_command("tree.set_subtree", "hello", "1", "x", "FRONT", "y")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "1") == "1"
+
+
+def test_append_leaves(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a))")
+    assert redis_client.execute_command("tree.append_leaves", "hello", "0/2", "b", "c", "b") == 3
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( a b c b ) )"
+    assert redis_client.execute_command("tree.append_leaves", "hello", "0/2", "a", "d", "d", "e", "dedup") == 2
+    assert redis_client.execute_command("tree.get", "hello") == "0( 1 2( a b c b d e ) )"
+    assert redis_client.execute_command("tree.append_leaves", "hello", "0/2", "a", "DEDUP") == 0
+
+    with pytest.raises(ResponseError, match="no node at path 0/nope"):
+        redis_client.execute_command("tree.append_leaves", "hello", "0/nope", "x")
+    assert redis_client.execute_command("tree.append_leaves", "nope", "0", "x") is None
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.append_leaves", "hello", "0", "x", "")
+    with pytest.raises(ResponseError, match="wrong number of arguments"):
+        redis_client.execute_command("tree.append_leaves", "hello", "0")
+
+
+def test_graft(redis_client):
+    redis_client.execute_command("tree.init", "dst", "a (b c)")
+    redis_client.execute_command("tree.init", "src", "x (y (z))", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.setattr", "src", "y", "k", "v")
+    assert redis_client.execute_command("tree.graft", "dst", "b", "src", "KEEPSRC") == 3
+    assert redis_client.execute_command("tree.get", "src") == "x( y( z ) )"
+    assert redis_client.execute_command("tree.graft", "dst", "c", "src") == 3
+    assert redis_client.execute_command("exists", "src") == 0
+    assert redis_client.execute_command("tree.get", "dst") == "a( b( x( y( z ) ) ) c( x( y( z ) ) ) )"
+    # the grafted nodes follow the rules of their new tree and keep their attributes
+    assert redis_client.execute_command("tree.locate_all", "dst", "Y") == []
+    assert redis_client.execute_command("tree.locate_all", "dst", "z") == ["a/b/x/y/z", "a/c/x/y/z"]
+    assert redis_client.execute_command("tree.getattr", "dst", "y", "k") == "v"
+
+    redis_client.execute_command("tree.init", "ids", "1 (2)", "UNIQUE")
+    redis_client.execute_command("tree.init", "src", "3 (2)")
+    with pytest.raises(ResponseError, match="duplicate node value 2"):
+        redis_client.execute_command("tree.graft", "ids", "1", "src")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.graft", "dst", "nope", "src")
+    with pytest.raises(ResponseError, match="grafted onto itself"):
+        redis_client.execute_command("tree.graft", "dst", "a", "dst")
+    assert redis_client.execute_command("tree.get", "src") == "3( 2 )"
+    assert redis_client.execute_command("tree.graft", "dst", "a", "nope") is None
+    assert redis_client.execute_command("tree.graft", "nope", "a", "src") is None
+    assert redis_client.execute_command("command", "getkeys", "tree.graft", "dst", "a", "src") == ["dst", "src"]
+
+
+def test_copy_subtree(redis_client):
+    redis_client.execute_command("tree.init", "fs", "root (template (src docs) home (alice))")
+    redis_client.execute_command("tree.setattr", "fs", "docs", "mode", "755")
+    assert redis_client.execute_command("tree.copy_subtree", "fs", "template", "fs", "alice") == 3
+    assert redis_client.execute_command("tree.get", "fs") == "root( template( src docs ) home( alice( template( src docs ) ) ) )"
+    assert redis_client.execute_command("tree.locate_all", "fs", "docs") == ["root/template/docs", "root/home/alice/template/docs"]
+
+    # the copy shares nothing with the original
+    redis_client.execute_command("tree.apply_diff", "fs", "DELETE", "root/home/alice/template/src")
+    assert redis_client.execute_command("tree.get_subtree", "fs", "template") == "template( src docs )"
+
+    assert redis_client.execute_command("tree.copy_subtree", "fs", "template", "layout") == 3
+    assert redis_client.execute_command("tree.get", "layout") == "template( src docs )"
+    assert redis_client.execute_command("tree.getattr", "layout", "docs", "mode") == "755"
+    with pytest.raises(ResponseError, match="layout already exists"):
+        redis_client.execute_command("tree.copy_subtree", "fs", "template", "layout")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.copy_subtree", "fs", "nope", "layout", "docs")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.copy_subtree", "fs", "docs", "layout", "nope")
+
+    redis_client.execute_command("tree.init", "ids", "1 (2)", "UNIQUE")
+    with pytest.raises(ResponseError, match="duplicate node value 2"):
+        redis_client.execute_command("tree.copy_subtree", "ids", "2", "ids", "1")
+    assert redis_client.execute_command("tree.copy_subtree", "nope", "a", "layout", "docs") is None
+    assert redis_client.execute_command("tree.copy_subtree", "fs", "docs", "nope", "a") is None
+    assert redis_client.execute_command("command", "getkeys", "tree.copy_subtree", "fs", "a", "layout") == ["fs", "layout"]
+
+
+def test_add_path(redis_client):
+    redis_client.execute_command("tree.init", "fs", "root (home (alice) tmp)")
+    assert redis_client.execute_command("tree.add_path", "fs", "root/home/bob/docs/cv") == 3
+    assert redis_client.execute_command("tree.get", "fs") == "root( home( alice bob( docs( cv ) ) ) tmp )"
+    assert redis_client.execute_command("tree.add_path", "fs", "root/home/bob/docs") == 0
+    assert redis_client.execute_command("tree.add_path", "fs", "root") == 0
+    assert redis_client.execute_command("tree.add_path", "fs", "root/tmp/x") == 1
+    assert redis_client.execute_command("tree.locate_all", "fs", "cv") == ["root/home/bob/docs/cv"]
+
+    with pytest.raises(ResponseError, match="path home/x does not start at the root root"):
+        redis_client.execute_command("tree.add_path", "fs", "home/x")
+    with pytest.raises(ResponseError, match="invalid path root//x"):
+        redis_client.execute_command("tree.add_path", "fs", "root//x")
+
+    redis_client.execute_command("tree.init", "ci", "Root (Home)", "CASEINSENSITIVE", "UNIQUE")
+    assert redis_client.execute_command("tree.add_path", "ci", "root/HOME/bob") == 1
+    assert redis_client.execute_command("tree.get", "ci") == "Root( Home( bob ) )"
+    with pytest.raises(ResponseError, match="duplicate node value BOB"):
+        redis_client.execute_command("tree.add_path", "ci", "root/x/BOB")
+    assert redis_client.execute_command("tree.get", "ci") == "Root( Home( bob ) )"
+    assert redis_client.execute_command("tree.add_path", "nope", "a/b") is None
+
+
+def test_dumpnode_restorenode(redis_client):
+    redis_client.execute_command("tree.init", "fs", "root (home (alice (notes) bob) tmp)")
+    redis_client.execute_command("tree.setattr", "fs", "notes", "mode", "600")
+    dump = redis_client.execute_command("tree.dumpnode", "fs", "alice")
+    assert dump == redis_client.execute_command("tree.dumpnode", "fs", "alice")
+
+    redis_client.execute_command("tree.init", "backup", "backup")
+    assert redis_client.execute_command("tree.restorenode", "backup", "backup", dump) == 2
+    assert redis_client.execute_command("tree.get", "backup") == "backup( alice( notes ) )"
+    assert redis_client.execute_command("tree.getattr", "backup", "notes", "mode") == "600"
+    assert redis_client.execute_command("tree.restorenode", "fs", "tmp", dump) == 2
+    assert redis_client.execute_command("tree.locate_all", "fs", "notes") == ["root/home/alice/notes", "root/tmp/alice/notes"]
+
+    with pytest.raises(ResponseError, match="invalid node dump"):
+        redis_client.execute_command("tree.restorenode", "backup", "backup", "not hex")
+    with pytest.raises(ResponseError, match="truncated tree encoding"):
+        redis_client.execute_command("tree.restorenode", "backup", "backup", dump[:-2])
+    with pytest.raises(ResponseError, match="node dump of unknown encoding version 2"):
+        redis_client.execute_command("tree.restorenode", "backup", "backup", "02" + dump[2:])
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.restorenode", "backup", "nope", dump)
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.dumpnode", "fs", "nope")
+
+    redis_client.execute_command("tree.init", "ids", "alice", "UNIQUE")
+    with pytest.raises(ResponseError, match="duplicate node value alice"):
+        redis_client.execute_command("tree.restorenode", "ids", "alice", dump)
+    assert redis_client.execute_command("tree.dumpnode", "nope", "a") is None
+    assert redis_client.execute_command("tree.restorenode", "nope", "a", dump) is None
+
+
+def test_wrap_node(redis_client):
+    redis_client.execute_command("tree.init", "shop", "all (shoes hats (caps) socks)")
+    assert redis_client.execute_command("tree.wrap_node", "shop", "hats", "wear") == "OK"
+    assert redis_client.execute_command("tree.get", "shop") == "all( shoes wear( hats( caps ) ) socks )"
+    assert redis_client.execute_command("tree.get_father", "shop", "caps") == "hats"
+    assert redis_client.execute_command("tree.locate_all", "shop", "caps") == ["all/wear/hats/caps"]
+
+    # wrapping the root gives the tree a new root
+    assert redis_client.execute_command("tree.wrap_node", "shop", "all", "store") == "OK"
+    assert redis_client.execute_command("tree.get", "shop") == "store( all( shoes wear( hats( caps ) ) socks ) )"
+    assert redis_client.execute_command("tree.root", "shop") == "store"
+
+    # the new parent may carry the same value as the node it wraps
+    assert redis_client.execute_command("tree.wrap_node", "shop", "socks", "socks") == "OK"
+    assert redis_client.execute_command("tree.get_subtree", "shop", "socks") == "socks( socks )"
+
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.wrap_node", "shop", "nope", "x")
+    redis_client.execute_command("tree.init", "ids", "1 (2)", "UNIQUE")
+    with pytest.raises(ResponseError, match="duplicate node value 1"):
+        redis_client.execute_command("tree.wrap_node", "ids", "2", "1")
+    assert redis_client.execute_command("tree.get", "ids") == "1( 2 )"
+    assert redis_client.execute_command("tree.wrap_node", "nope", "a", "b") is None
+
+
+def test_collapse_node(redis_client):
+    redis_client.execute_command("tree.init", "fs", "root (docs (a b (c)) tmp (x) etc)")
+    assert redis_client.execute_command("tree.collapse_node", "fs", "docs") == 2
+    assert redis_client.execute_command("tree.get", "fs") == "root( a b( c ) tmp( x ) etc )"
+    assert redis_client.execute_command("tree.get_father", "fs", "c") == "b"
+    assert redis_client.execute_command("tree.exists_node", "fs", "docs") == 0
+    assert redis_client.execute_command("tree.size", "fs") == [5, 6]
+
+    # a leaf simply goes away
+    assert redis_client.execute_command("tree.collapse_node", "fs", "etc") == 0
+    assert redis_client.execute_command("tree.get", "fs") == "root( a b( c ) tmp( x ) )"
+
+    with pytest.raises(ResponseError, match="the root can not be collapsed"):
+        redis_client.execute_command("tree.collapse_node", "fs", "root")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.collapse_node", "fs", "nope")
+    assert redis_client.execute_command("tree.collapse_node", "nope", "a") is None
+
+
+def test_sort_children(redis_client):
+    redis_client.execute_command("tree.init", "fs", "root (src (main lib build) docs 10 9 (b a))")
+    assert redis_client.execute_command("tree.sort_children", "fs", "root") == "OK"
+    assert redis_client.execute_command("tree.get", "fs") == "root( 10 9( b a ) docs src( main lib build ) )"
+    assert redis_client.execute_command("tree.sort_children", "fs", "root", "DESC", "RECURSIVE") == "OK"
+    assert redis_client.execute_command("tree.get", "fs") == "root( src( main lib build ) docs 9( b a ) 10 )"
+    assert redis_client.execute_command("tree.sort_children", "fs", "src", "RECURSIVE") == "OK"
+    assert redis_client.execute_command("tree.get_children", "fs", "src") == ["build", "lib", "main"]
+    # sorting what is in order already, or a leaf, leaves the version alone
+    version = redis_client.execute_command("tree.version", "fs")
+    assert redis_client.execute_command("tree.sort_children", "fs", "src", "RECURSIVE") == "OK"
+    assert redis_client.execute_command("tree.sort_children", "fs", "main") == "OK"
+    assert redis_client.execute_command("tree.version", "fs") == version
+
+    with pytest.raises(ResponseError, match="src is not a number"):
+        redis_client.execute_command("tree.sort_children", "fs", "root", "NUMERIC")
+    assert redis_client.execute_command("tree.get_children", "fs", "root") == ["src", "docs", "9", "10"]
+
+    redis_client.execute_command("tree.init", "scores", "all (10 9 -1.5 100)")
+    assert redis_client.execute_command("tree.sort_children", "scores", "all", "NUMERIC") == "OK"
+    assert redis_client.execute_command("tree.get", "scores") == "all( -1.5 9 10 100 )"
+    assert redis_client.execute_command("tree.sort_children", "scores", "all", "NUMERIC", "DESC") == "OK"
+    assert redis_client.execute_command("tree.get", "scores") == "all( 100 10 9 -1.5 )"
+
+    redis_client.execute_command("tree.init", "names", "all (bob Alice carol)", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.sort_children", "names", "all")
+    assert redis_client.execute_command("tree.get", "names") == "all( Alice bob carol )"
+
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.sort_children", "fs", "nope")
+    assert redis_client.execute_command("tree.sort_children", "nope", "a") is None
+
+
+def test_move_child(redis_client):
+    redis_client.execute_command("tree.init", "menu", "menu (home (news) about blog contact)")
+    assert redis_client.execute_command("tree.move_child", "menu", "menu", "home", "INDEX", "3") == "OK"
+    assert redis_client.execute_command("tree.get", "menu") == "menu( about blog contact home( news ) )"
+    assert redis_client.execute_command("tree.move_child", "menu", "menu", "home", "INDEX", "0") == "OK"
+    assert redis_client.execute_command("tree.get", "menu") == "menu( home( news ) about blog contact )"
+    assert redis_client.execute_command("tree.move_child", "menu", "menu", "home", "AFTER", "blog") == "OK"
+    assert redis_client.execute_command("tree.get_children", "menu", "menu") == ["about", "blog", "home", "contact"]
+    assert redis_client.execute_command("tree.move_child", "menu", "menu", "contact", "BEFORE", "about") == "OK"
+    assert redis_client.execute_command("tree.get_children", "menu", "menu") == ["contact", "about", "blog", "home"]
+    assert redis_client.execute_command("tree.move_child", "menu", "menu", "blog", "BEFORE", "blog") == "OK"
+    assert redis_client.execute_command("tree.get_children", "menu", "menu") == ["contact", "about", "blog", "home"]
+    assert redis_client.execute_command("tree.get_subtree", "menu", "home") == "home( news )"
+
+    with pytest.raises(ResponseError, match="index out of range"):
+        redis_client.execute_command("tree.move_child", "menu", "menu", "home", "INDEX", "4")
+    with pytest.raises(ResponseError, match="news is not a child of menu"):
+        redis_client.execute_command("tree.move_child", "menu", "menu", "news", "INDEX", "0")
+    with pytest.raises(ResponseError, match="news is not a child of menu"):
+        redis_client.execute_command("tree.move_child", "menu", "menu", "home", "AFTER", "news")
+    with pytest.raises(ResponseError, match="unknown argument LAST"):
+        redis_client.execute_command("tree.move_child", "menu", "menu", "home", "LAST")
+    assert redis_client.execute_command("tree.move_child", "nope", "a", "b", "INDEX", "0") is None
+
+
+def test_rename_node(redis_client):
+    redis_client.execute_command("tree.init", "hello", "a (b (x c (x)) x d)")
+    assert redis_client.execute_command("tree.rename_node", "hello", "x", "y") == 1
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( y c( x ) ) x d )"
+    assert redis_client.execute_command("tree.rename_node", "hello", "x", "z", "ALL") == 2
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( y c( z ) ) z d )"
+    version = redis_client.execute_command("tree.version", "hello")
+    assert redis_client.execute_command("tree.rename_node", "hello", "nope", "z", "ALL") == 0
+    assert redis_client.execute_command("tree.version", "hello") == version
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.rename_node", "hello", "nope", "z")
+    assert redis_client.execute_command("tree.rename_node", "nope", "x", "z") is None
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.rename_node", "hello", "y", "")
+
+
+def test_equals(redis_client):
+    redis_client.execute_command("tree.init", "a", "r (x (y z) w)")
+    redis_client.execute_command("tree.init", "b", "r (x (y z) w)", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.setattr", "b", "y", "k", "v")
+    assert redis_client.execute_command("tree.equals", "a", "b") == 1
+    assert redis_client.execute_command("tree.equals", "a", "a", "x", "x") == 1
+    redis_client.execute_command("tree.init", "c", "q (y z)")
+    assert redis_client.execute_command("tree.equals", "a", "c") == 0
+    assert redis_client.execute_command("tree.equals", "a", "c", "y", "y") == 1
+    assert redis_client.execute_command("tree.equals", "a", "c", "x", "q") == 0
+
+    # the same values in another shape
+    redis_client.execute_command("tree.init", "d", "r (x (y (z)) w)")
+    assert redis_client.execute_command("tree.equals", "a", "d") == 0
+    redis_client.execute_command("tree.init", "e", "r (x (y) z w)")
+    assert redis_client.execute_command("tree.equals", "a", "e") == 0
+
+    assert redis_client.execute_command("tree.equals", "a", "nope") == 0
+    assert redis_client.execute_command("tree.equals", "a", "b", "nope", "x") == 0
+    with pytest.raises(ResponseError, match="wrong number of arguments"):
+        redis_client.execute_command("tree.equals", "a", "b", "x")
+
+
+def test_diff(redis_client):
+    redis_client.execute_command("tree.init", "v1", "conf (net (dns proxy) disk (quota))")
+    redis_client.execute_command("tree.init", "v2", "conf (net (dns) disk (quota proxy) log)")
+    assert redis_client.execute_command("tree.diff", "v1", "v2") == [
+        ["MOVED", "conf/net/proxy", "conf/disk/proxy"],
+        ["ADDED", "conf/log"],
+    ]
+    assert redis_client.execute_command("tree.diff", "v2", "v1") == [
+        ["REMOVED", "conf/log"],
+        ["MOVED", "conf/disk/proxy", "conf/net/proxy"],
+    ]
+    assert redis_client.execute_command("tree.diff", "v1", "v1") == []
+
+    # repeated values only match where the path is the same
+    redis_client.execute_command("tree.init", "a", "r (x (y) x)")
+    redis_client.execute_command("tree.init", "b", "r (z (x) x)")
+    assert redis_client.execute_command("tree.diff", "a", "b") == [
+        ["REMOVED", "r/x/y"],
+        ["REMOVED", "r/x"],
+        ["ADDED", "r/z"],
+        ["ADDED", "r/z/x"],
+    ]
+
+    assert redis_client.execute_command("tree.diff", "v1", "nope") is None
+    assert redis_client.execute_command("command", "getkeys", "tree.diff", "v1", "v2") == ["v1", "v2"]
+
+
+def test_locate_after_writes(redis_client):
+    # whatever the writes did, a node named by value is the first match in preorder
+    redis_client.execute_command("tree.init", "hello", "a (b (x c (x)) x d)")
+    redis_client.execute_command("tree.set_subtree", "hello", "a", "x (w)", "FRONT")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "x") == "x( w )"
+    redis_client.execute_command("tree.del_subtree", "hello", "x")
+    assert redis_client.execute_command("tree.get_father", "hello", "x") == "b"
+    assert redis_client.execute_command("tree.exists_node", "hello", "w") == 0
+
+    assert redis_client.execute_command("tree.rename_node", "hello", "x", "y", "ALL") == 3
+    assert redis_client.execute_command("tree.exists_node", "hello", "x") == 0
+    redis_client.execute_command("tree.apply_diff", "hello", "RELABEL", "a/d", "x", "INSERT", "a/x", "q")
+    assert redis_client.execute_command("tree.get_father", "hello", "q") == "x"
+    redis_client.execute_command("tree.append_leaves", "hello", "a/b", "q")
+    assert redis_client.execute_command("tree.get_father", "hello", "q") == "b"
+    redis_client.execute_command("tree.del_children", "hello", "b")
+    assert redis_client.execute_command("tree.get_father", "hello", "q") == "x"
+    assert redis_client.execute_command("tree.get_father", "hello", "y") == "a"
+    assert redis_client.execute_command("tree.get", "hello") == "a( b y x( q ) )"
+
+    redis_client.execute_command("tree.init", "shop", "Root (toys Toys)", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.rename_node", "shop", "TOYS", "Games")
+    assert redis_client.execute_command("tree.locate_all", "shop", "toys") == ["Root/Toys"]
+    assert redis_client.execute_command("tree.locate_all", "shop", "GAMES") == ["Root/Games"]
+
+
+def test_attributes(redis_client):
+    redis_client.execute_command("tree.init", "org", "CEO (CTO (Dev) CFO)")
+    assert redis_client.execute_command("tree.setattr", "org", "CTO", "name", "Ada", "floor", "3") == 2
+    assert redis_client.execute_command("tree.setattr", "org", "CTO", "floor", "4") == 0
+    assert redis_client.execute_command("tree.getattr", "org", "CTO", "floor") == "4"
+    assert redis_client.execute_command("tree.getattr", "org", "CTO", "nope") is None
+    assert redis_client.execute_command("tree.getattr", "org", "CFO", "name") is None
+
+    # attributes stay with their node through renames and moves, and go with it
+    redis_client.execute_command("tree.rename_node", "org", "CTO", "VPE")
+    redis_client.execute_command("tree.apply_diff", "org", "MOVE", "CEO/VPE", "CEO/CFO")
+    assert redis_client.execute_command("tree.getattr", "org", "VPE", "name") == "Ada"
+    redis_client.execute_command("tree.del_subtree", "org", "VPE")
+    redis_client.execute_command("tree.set_subtree", "org", "CEO", "VPE")
+    assert redis_client.execute_command("tree.getattr", "org", "VPE", "name") is None
+
+    # JSON carries them both ways
+    redis_client.execute_command("tree.setattr", "org", "CEO", "name", "Grace")
+    tree = json.loads(redis_client.execute_command("tree.get", "org", "FORMAT", "JSON"))
+    assert tree["attributes"] == {"name": "Grace"}
+    assert "attributes" not in tree["children"][0]
+    redis_client.execute_command("tree.init", "copy", json.dumps(tree), "FORMAT", "JSON")
+    assert redis_client.execute_command("tree.getattr", "copy", "CEO", "name") == "Grace"
+    commands = redis_client.execute_command("tree.export_commands", "org")
+    assert commands == [["tree.init", "org", redis_client.execute_command("tree.get", "org", "FORMAT", "JSON"), "FORMAT", "JSON"]]
+
+    assert redis_client.execute_command("tree.delattr", "org", "CEO", "name", "nope") == 1
+    assert redis_client.execute_command("tree.getattr", "org", "CEO", "name") is None
+    assert redis_client.execute_command("tree.export_commands", "org") == [["tree.init", "org", "CEO( CFO VPE )"]]
+    # removing nothing leaves the version alone
+    version = redis_client.execute_command("tree.version", "org")
+    assert redis_client.execute_command("tree.delattr", "org", "CEO", "name") == 0
+    assert redis_client.execute_command("tree.version", "org") == version
+
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.setattr", "org", "nope", "a", "b")
+    with pytest.raises(ResponseError, match="wrong number of arguments"):
+        redis_client.execute_command("tree.setattr", "org", "CEO", "a")
+    assert redis_client.execute_command("tree.getattr", "nope", "CEO", "a") is None
+    assert redis_client.execute_command("tree.setattr", "nope", "CEO", "a", "b") is None
+    assert redis_client.execute_command("exists", "nope") == 0
+
+
+def test_node_hgetall_subtree(redis_client):
+    redis_client.execute_command("tree.init", "org", "CEO (CTO (Dev Ops) CFO)")
+    redis_client.execute_command("tree.setattr", "org", "CTO", "name", "Ada")
+    redis_client.execute_command("tree.setattr", "org", "Ops", "name", "Lin")
+    redis_client.execute_command("tree.setattr", "org", "CFO", "floor", "2")
+    assert redis_client.execute_command("tree.node_hgetall_subtree", "org", "CEO", "name") == \
+        [["CEO/CTO", "Ada"], ["CEO/CTO/Ops", "Lin"]]
+    assert redis_client.execute_command("tree.node_hgetall_subtree", "org", "Ops", "name") == [["CEO/CTO/Ops", "Lin"]]
+    assert redis_client.execute_command("tree.node_hgetall_subtree", "org", "CEO", "nope") == []
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.node_hgetall_subtree", "org", "nope", "name")
+    assert redis_client.execute_command("tree.node_hgetall_subtree", "nope", "CEO", "name") is None
+
+
+def test_node_hmset_bulk(redis_client):
+    redis_client.execute_command("tree.init", "org", "CEO (CTO (Dev) CFO)")
+    redis_client.execute_command("tree.setattr", "org", "CEO", "name", "Grace")
+    patches = {"CEO/CTO": {"name": "Ada", "floor": "3"}, "CEO/CTO/Dev": {"stack": "rust"}, "CEO": {"name": None}}
+    assert redis_client.execute_command("tree.node_hmset_bulk", "org", json.dumps(patches)) == 3
+    assert redis_client.execute_command("tree.getattr", "org", "CTO") == ["floor", "3", "name", "Ada"]
+    assert redis_client.execute_command("tree.getattr", "org", "Dev", "stack") == "rust"
+    assert redis_client.execute_command("tree.getattr", "org", "CEO") == []
+    assert redis_client.execute_command("tree.node_hmset_bulk", "org", "{}") == 0
+
+    # a path that is not there leaves every node as it was
+    with pytest.raises(ResponseError, match="no node at path CEO/nope"):
+        redis_client.execute_command("tree.node_hmset_bulk", "org", json.dumps({"CEO/CFO": {"a": "b"}, "CEO/nope": {}}))
+    assert redis_client.execute_command("tree.getattr", "org", "CFO") == []
+    with pytest.raises(ResponseError, match="unexpected 1 in JSON"):
+        redis_client.execute_command("tree.node_hmset_bulk", "org", '{"CEO": {"a": 1}}')
+    assert redis_client.execute_command("tree.node_hmset_bulk", "nope", "{}") is None
+
+
+def test_attributes_persist(redis_client, binary_client):
+    redis_client.execute_command("tree.init", "org", "CEO (CTO (Dev) CFO)", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.setattr", "org", "dev", "stack", "rust ( \"and\" c )")
+    redis_client.restore("copy", 0, binary_client.dump("org"))
+    assert redis_client.execute_command("tree.getattr", "copy", "DEV", "stack") == "rust ( \"and\" c )"
+
+    with appendonly(redis_client):
+        redis_client.execute_command("bgrewriteaof")
+        wait_for_aof_rewrite(redis_client)
+        load_aof(redis_client)
+    assert redis_client.execute_command("tree.getattr", "org", "DEV", "stack") == "rust ( \"and\" c )"
+
+
+def test_edge_weights(redis_client):
+    redis_client.execute_command("tree.init", "route", "gateway (eu (paris berlin) us)")
+    assert redis_client.execute_command("tree.set_weight", "route", "gateway", "eu", "0.7") == 1
+    assert redis_client.execute_command("tree.set_weight", "route", "gateway", "us", "0.3") == 1
+    assert redis_client.execute_command("tree.set_weight", "route", "eu", "paris", "2") == 1
+    assert redis_client.execute_command("tree.set_weight", "route", "eu", "paris", "-1.5") == 0
+    assert redis_client.execute_command("tree.get_weight", "route", "gateway", "eu") == "0.7"
+    assert redis_client.execute_command("tree.get_weight", "route", "eu", "paris") == "-1.5"
+    assert redis_client.execute_command("tree.get_weight", "route", "eu", "berlin") is None
+    assert redis_client.execute_command("tree.get", "route", "FORMAT", "WEIGHTED") == \
+        ["gateway", None, ["eu", "0.7", ["paris", "-1.5"], ["berlin", None]], ["us", "0.3"]]
+
+    # JSON carries them both ways, and the weight moves along with the child
+    tree = json.loads(redis_client.execute_command("tree.get", "route", "FORMAT", "JSON"))
+    assert tree["children"][0]["weight"] == 0.7
+    assert "weight" not in tree
+    redis_client.execute_command("tree.init", "copy", json.dumps(tree), "FORMAT", "JSON")
+    assert redis_client.execute_command("tree.get_weight", "copy", "eu", "paris") == "-1.5"
+    redis_client.execute_command("tree.apply_diff", "route", "MOVE", "gateway/eu/paris", "gateway/us")
+    assert redis_client.execute_command("tree.get_weight", "route", "us", "paris") == "-1.5"
+
+    with pytest.raises(ResponseError, match="paris is not a child of eu"):
+        redis_client.execute_command("tree.get_weight", "route", "eu", "paris")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.set_weight", "route", "nope", "eu", "1")
+    with pytest.raises(ResponseError, match="invalid weight heavy"):
+        redis_client.execute_command("tree.set_weight", "route", "gateway", "eu", "heavy")
+    with pytest.raises(ResponseError, match="invalid weight inf"):
+        redis_client.execute_command("tree.set_weight", "route", "gateway", "eu", "inf")
+    with pytest.raises(ResponseError, match="FORMAT WEIGHTED is only for replies"):
+        redis_client.execute_command("tree.init", "other", "a", "FORMAT", "WEIGHTED")
+    assert redis_client.execute_command("tree.set_weight", "nope", "a", "b", "1") is None
+    assert redis_client.execute_command("tree.get_weight", "nope", "a", "b") is None
+
+
+def test_edge_weights_persist(redis_client, binary_client):
+    redis_client.execute_command("tree.init", "route", "gateway (eu us)")
+    redis_client.execute_command("tree.set_weight", "route", "gateway", "eu", "0.1")
+    redis_client.restore("copy", 0, binary_client.dump("route"))
+    assert redis_client.execute_command("tree.get_weight", "copy", "gateway", "eu") == "0.1"
+    dump = redis_client.execute_command("tree.dumpnode", "route", "gateway")
+    assert redis_client.execute_command("tree.restorenode", "copy", "us", dump) == 3
+    assert redis_client.execute_command("tree.get_weight", "copy", "us", "gateway") is None
+    assert redis_client.execute_command("tree.get_subtree", "copy", "us", "FORMAT", "WEIGHTED") == \
+        ["us", None, ["gateway", None, ["eu", "0.1"], ["us", None]]]
+
+    with appendonly(redis_client):
+        redis_client.execute_command("bgrewriteaof")
+        wait_for_aof_rewrite(redis_client)
+        load_aof(redis_client)
+    assert redis_client.execute_command("tree.get_weight", "route", "gateway", "eu") == "0.1"
+
+    redis_client.execute_command("tree.init", "hello", "a (b (x c) d)")
+    assert redis_client.execute_command(
+        "tree.apply_diff", "hello",
+        "INSERT", "a/d", "e (f)",
+        "RELABEL", "a/b/x", "y",
+        "MOVE", "a/b/c", "a/d/e",
+        "DELETE", "a/d/e/f",
+    ) == 4
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( y ) d( e( c ) ) )"
+
+    # a failing step leaves the tree untouched
+    with pytest.raises(ResponseError, match="no node at path a/nope"):
+        redis_client.execute_command("tree.apply_diff", "hello", "DELETE", "a/b/y", "DELETE", "a/nope")
+    with pytest.raises(ResponseError, match="no node at path a/b/y/z"):
+        redis_client.execute_command("tree.apply_diff", "hello", "MOVE", "a/b", "a/b/y/z")
+    with pytest.raises(ResponseError, match="root can not be deleted"):
+        redis_client.execute_command("tree.apply_diff", "hello", "DELETE", "a")
+    with pytest.raises(ResponseError, match="unknown edit operation"):
+        redis_client.execute_command("tree.apply_diff", "hello", "SWAP", "a/b")
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( y ) d( e( c ) ) )"
+    assert redis_client.execute_command("tree.apply_diff", "nope", "DELETE", "a/b") is None
+
+    # steps already applied are taken back, sibling order, attributes and expiries included
+    redis_client.execute_command("tree.init", "hello", "a (b (x c) d (e) g)")
+    redis_client.execute_command("tree.setattr", "hello", "c", "color", "red")
+    redis_client.execute_command("tree.expire_node", "hello", "x", "100")
+    redis_client.execute_command("tree.expire_node", "hello", "e", "100")
+    version = redis_client.execute_command("tree.version", "hello")
+    with pytest.raises(ResponseError, match="no node at path a/nope"):
+        redis_client.execute_command(
+            "tree.apply_diff", "hello",
+            "INSERT", "a/g", "h (i)",
+            "RELABEL", "a/b/x", "y",
+            "MOVE", "a/b/c", "a/g/h",
+            "DELETE", "a/d",
+            "DELETE", "a/nope",
+        )
+    assert redis_client.execute_command("tree.get", "hello") == "a( b( x c ) d( e ) g )"
+    assert redis_client.execute_command("tree.getattr", "hello", "c", "color") == "red"
+    assert 99 <= redis_client.execute_command("tree.ttl_node", "hello", "x") <= 100
+    assert 99 <= redis_client.execute_command("tree.ttl_node", "hello", "e") <= 100
+    assert redis_client.execute_command("tree.exists_node", "hello", "h") == 0
+    assert redis_client.execute_command("tree.version", "hello") == version
+
+    # an empty script changes nothing
+    assert redis_client.execute_command("tree.apply_diff", "hello") == 0
+    assert redis_client.execute_command("tree.version", "hello") == version
+
+
 def test_get_ancestors(redis_client):
     redis_client.execute_command("tree.init", "hello", "0 (1 2 (a b (d)) e f (g h))")
     assert redis_client.execute_command("tree.get_ancestors", "hello", "d") == ["b", "2", "0"]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "0") == []
+
+
+def test_get_ancestors_options(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "LIMIT", "2") == ["k", "a"]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "LIMIT", "0") == []
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "ROOTFIRST") == ["0", "2", "a", "k"]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "WITHSELF") == ["j", "k", "a", "2", "0"]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "LIMIT", "2", "ROOTFIRST") == ["a", "k"]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "WITHSELF", "LIMIT", "2") == ["j", "k"]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "0", "WITHSELF", "ROOTFIRST") == ["0"]
+    with pytest.raises(ResponseError, match="unknown argument NEAREST"):
+        redis_client.execute_command("tree.get_ancestors", "hello", "j", "WITHSELF", "NEAREST")
+
+
+def test_get_ancestors_batch(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "NODES", "2", "d", "0") == [["k", "a", "2", "0"], ["b", "2", "0"], []]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "NODES", "2", "nope", "g") == [["k", "a", "2", "0"], None, ["f", "0"]]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "LIMIT", "1", "NODES", "1", "g", "WITHSELF") == [["j"], ["g"]]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "d", "NODES", "1", "h", "ROOTFIRST") == [["0", "2", "b"], ["0", "f"]]
+    assert redis_client.execute_command("tree.get_ancestors", "hello", "j", "NODES", "0") == [["k", "a", "2", "0"]]
+    # one node alone keeps the flat reply and the error
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.get_ancestors", "hello", "nope", "LIMIT", "1")
+    with pytest.raises(ResponseError, match="unknown argument d"):
+        redis_client.execute_command("tree.get_ancestors", "hello", "j", "d")
+    with pytest.raises(ResponseError, match="NODES expects 3 nodes, got 2"):
+        redis_client.execute_command("tree.get_ancestors", "hello", "j", "NODES", "3", "d", "0")
+    assert redis_client.execute_command("tree.get_ancestors", "nope", "a", "NODES", "1", "b") is None
+
+    # nodes called like the options, the first one or counted by NODES
+    redis_client.execute_command("tree.init", "opts", "r (LIMIT (WITHSELF) NODES)")
+    assert redis_client.execute_command("tree.get_ancestors", "opts", "WITHSELF") == ["LIMIT", "r"]
+    assert redis_client.execute_command("tree.get_ancestors", "opts", "LIMIT", "WITHSELF") == ["LIMIT", "r"]
+    assert redis_client.execute_command(
+        "tree.get_ancestors", "opts", "NODES", "NODES", "2", "WITHSELF", "ROOTFIRST", "LIMIT", "5"
+    ) == [["r"], ["LIMIT", "r"], None]
+
+
+def test_first_ancestor_match(redis_client):
+    redis_client.execute_command("tree.init", "conf", "conf-root (app (conf-web (web (static) api)) db)")
+    assert redis_client.execute_command("tree.first_ancestor_match", "conf", "static", "conf-*") == "conf-web"
+    assert redis_client.execute_command("tree.first_ancestor_match", "conf", "conf-web", "conf-*") == "conf-root"
+    assert redis_client.execute_command("tree.first_ancestor_match", "conf", "conf-web", "conf-*", "WITHSELF") == "conf-web"
+    assert redis_client.execute_command("tree.first_ancestor_match", "conf", "db", "w*") is None
+    assert redis_client.execute_command("tree.first_ancestor_match", "conf", "conf-root", "*") is None
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.first_ancestor_match", "conf", "nope", "*")
+    with pytest.raises(ResponseError, match="unknown argument NEAREST"):
+        redis_client.execute_command("tree.first_ancestor_match", "conf", "static", "*", "NEAREST")
+    assert redis_client.execute_command("tree.first_ancestor_match", "nope", "a", "*") is None
+
+    redis_client.execute_command("tree.init", "shop", "Root (Food (Fruit))", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.first_ancestor_match", "shop", "fruit", "r*") == "Root"
+
+
+def test_lca(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.lca", "hello", "j", "d") == "2"
+    assert redis_client.execute_command("tree.lca", "hello", "j", "g") == "0"
+    assert redis_client.execute_command("tree.lca", "hello", "a", "j") == "a"
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.lca", "hello", "j", "nope")
+
+
+def test_is_ancestor(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "2", "j") == 1
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "0", "j") == 1
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "k", "j", "DIRECT") == 1
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "2", "j", "DIRECT") == 0
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "j", "2") == 0
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "j", "j") == 0
+    assert redis_client.execute_command("tree.is_ancestor", "hello", "2", "nope") == 0
+    assert redis_client.execute_command("tree.is_ancestor", "nope", "2", "j") == 0
+
+
+def test_path(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.path", "hello", "j", "d") == ["j", "k", "a", "2", "b", "d"]
+    assert redis_client.execute_command("tree.path", "hello", "0", "bb") == ["0", "2", "a", "bb"]
+    assert redis_client.execute_command("tree.path", "hello", "h", "h") == ["h"]
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.path", "hello", "h", "nope")
 
 
 def test_get_descendants(redis_client):
     redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
     assert redis_client.execute_command("tree.get_descendants", "hello", "2") == ["2", "a", "b", "k", "bb", "d", "j"]
+    assert redis_client.execute_command("tree.get_descendants", "hello", "2", "DEPTH", "1") == ["2", "a", "b"]
+    assert redis_client.execute_command("tree.get_descendants", "hello", "2", "DEPTH", "0") == ["2"]
+
+    redis_client.execute_command("tree.setattr", "hello", "j", "kind", "leaf")
+    redis_client.execute_command("tree.setattr", "hello", "d", "kind", "leaf")
+    redis_client.execute_command("tree.setattr", "hello", "bb", "kind", "twig")
+    assert redis_client.execute_command("tree.get_descendants", "hello", "2", "FILTER", "TAG", "kind") == ["bb", "d", "j"]
+    assert redis_client.execute_command("tree.get_descendants", "hello", "2", "FILTER", "kind=leaf") == ["d", "j"]
+    assert redis_client.execute_command(
+        "tree.get_descendants", "hello", "2", "FILTER", "kind=leaf", "WITHANCESTORS"
+    ) == ["2", "a", "b", "k", "d", "j"]
+    assert redis_client.execute_command(
+        "tree.get_descendants", "hello", "2", "DEPTH", "2", "FILTER", "kind=leaf", "WITHANCESTORS"
+    ) == ["2", "b", "d"]
+    assert redis_client.execute_command("tree.get_descendants", "hello", "2", "FILTER", "kind=tree") == []
+    with pytest.raises(ResponseError, match="FILTER takes TAG t or field=value"):
+        redis_client.execute_command("tree.get_descendants", "hello", "2", "FILTER", "kind")
+    with pytest.raises(ResponseError, match="WITHANCESTORS needs FILTER"):
+        redis_client.execute_command("tree.get_descendants", "hello", "2", "WITHANCESTORS")
+
+
+def test_depth_and_height(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.depth", "hello", "0") == 0
+    assert redis_client.execute_command("tree.depth", "hello", "j") == 4
+    assert redis_client.execute_command("tree.height", "hello") == 4
+    assert redis_client.execute_command("tree.height", "hello", "f") == 1
+    assert redis_client.execute_command("tree.height", "hello", "j") == 0
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.depth", "hello", "nope")
+    assert redis_client.execute_command("tree.depth", "nope", "j") is None
+
+
+def test_level(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.level", "hello", "0") == ["0"]
+    assert redis_client.execute_command("tree.level", "hello", "1") == ["1", "2", "e", "f"]
+    assert redis_client.execute_command("tree.level", "hello", "2") == ["a", "b", "g", "h"]
+    assert redis_client.execute_command("tree.level", "hello", "4") == ["j"]
+    assert redis_client.execute_command("tree.level", "hello", "5") == []
+    assert redis_client.execute_command("tree.level", "hello", "2", "2") == ["k", "bb", "d"]
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.level", "hello", "1", "nope")
+    assert redis_client.execute_command("tree.level", "nope", "1") is None
+
+
+def test_width_and_levelstats(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.levelstats", "hello") == [1, 4, 4, 3, 1]
+    assert redis_client.execute_command("tree.width", "hello") == 4
+    assert redis_client.execute_command("tree.levelstats", "hello", "a") == [1, 2, 1]
+    assert redis_client.execute_command("tree.width", "hello", "a") == 2
+    assert redis_client.execute_command("tree.levelstats", "hello", "j") == [1]
+    assert redis_client.execute_command("tree.width", "hello", "j") == 1
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.width", "hello", "nope")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.levelstats", "hello", "nope")
+    assert redis_client.execute_command("tree.width", "nope") is None
+    assert redis_client.execute_command("tree.levelstats", "nope") is None
+
+
+def test_aggregate(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.aggregate", "hello", "GROUPBY", "DEPTH", "COUNT") == \
+        [[0, 1], [1, 4], [2, 4], [3, 3], [4, 1]]
+    assert redis_client.execute_command("tree.aggregate", "hello", "UNDER", "a", "groupby", "depth", "count") == \
+        [[0, 1], [1, 2], [2, 1]]
+    assert redis_client.execute_command("tree.aggregate", "hello", "GROUPBY", "PARENT", "COUNT") == \
+        [["0", 4], ["2", 2], ["a", 2], ["k", 1], ["b", 1], ["f", 2]]
+    assert redis_client.execute_command("tree.aggregate", "hello", "UNDER", "j", "GROUPBY", "PARENT", "COUNT") == []
+
+    # parents of the same value count together, compared as the tree compares values
+    redis_client.execute_command("tree.init", "shop", "Root (Food (x y) food (z))", "CASEINSENSITIVE")
+    assert redis_client.execute_command("tree.aggregate", "shop", "GROUPBY", "PARENT", "COUNT") == \
+        [["Root", 2], ["Food", 3]]
+
+    with pytest.raises(ResponseError, match=r"aggregate takes GROUPBY DEPTH\|PARENT COUNT"):
+        redis_client.execute_command("tree.aggregate", "hello", "GROUPBY", "DEPTH")
+    with pytest.raises(ResponseError, match="unknown group SIBLING"):
+        redis_client.execute_command("tree.aggregate", "hello", "GROUPBY", "sibling", "COUNT")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.aggregate", "hello", "UNDER", "nope", "GROUPBY", "DEPTH", "COUNT")
+    assert redis_client.execute_command("tree.aggregate", "nope", "GROUPBY", "DEPTH", "COUNT") is None
+
+
+def test_sum_min_max_avg(redis_client):
+    redis_client.execute_command("tree.init", "hello", "total (10 (2.5 x) -4 (1e2))")
+    assert redis_client.execute_command("tree.sum", "hello", "total") == "108.5"
+    assert redis_client.execute_command("tree.sum", "hello", "10") == "12.5"
+    assert redis_client.execute_command("tree.min", "hello", "total") == "-4"
+    assert redis_client.execute_command("tree.max", "hello", "total") == "100"
+    assert redis_client.execute_command("tree.avg", "hello", "-4") == "48"
+    assert redis_client.execute_command("tree.sum", "hello", "x") == "0"
+    assert redis_client.execute_command("tree.min", "hello", "x") is None
+    assert redis_client.execute_command("tree.avg", "hello", "x") is None
+
+    redis_client.execute_command("tree.init", "org", "CEO (CTO (Dev Ops) CFO)")
+    redis_client.execute_command("tree.setattr", "org", "Dev", "budget", "300")
+    redis_client.execute_command("tree.setattr", "org", "Ops", "budget", "100")
+    redis_client.execute_command("tree.setattr", "org", "CFO", "budget", "lots")
+    assert redis_client.execute_command("tree.sum", "org", "CEO", "FIELD", "budget") == "400"
+    assert redis_client.execute_command("tree.avg", "org", "CTO", "field", "budget") == "200"
+    assert redis_client.execute_command("tree.max", "org", "CFO", "FIELD", "budget") is None
+    assert redis_client.execute_command("tree.sum", "org", "CEO") == "0"
+
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.sum", "org", "nope")
+    with pytest.raises(ResponseError, match="unknown argument BY"):
+        redis_client.execute_command("tree.sum", "org", "CEO", "BY", "budget")
+    assert redis_client.execute_command("tree.avg", "nope", "CEO") is None
+
+
+def test_size(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.size", "hello") == [4, 12]
+    assert redis_client.execute_command("tree.size", "hello", "a") == [2, 3]
+    assert redis_client.execute_command("tree.size", "hello", "j") == [0, 0]
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.size", "hello", "nope")
+    redis_client.execute_command("tree.del_subtree", "hello", "2")
+    assert redis_client.execute_command("tree.size", "hello") == [3, 5]
+
+
+def test_degree_and_is_leaf(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.degree", "hello") == 4
+    assert redis_client.execute_command("tree.degree", "hello", "a") == 2
+    assert redis_client.execute_command("tree.degree", "hello", "j") == 0
+    assert redis_client.execute_command("tree.is_leaf", "hello", "j") == 1
+    assert redis_client.execute_command("tree.is_leaf", "hello", "a") == 0
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.degree", "hello", "nope")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.is_leaf", "hello", "nope")
+    redis_client.execute_command("tree.del_children", "hello", "a")
+    assert redis_client.execute_command("tree.degree", "hello", "a") == 0
+    assert redis_client.execute_command("tree.is_leaf", "hello", "a") == 1
+    assert redis_client.execute_command("tree.degree", "nope") is None
+    assert redis_client.execute_command("tree.is_leaf", "nope", "a") is None
 
 
 def test_get_father(redis_client):
@@ -51,6 +1645,566 @@ def test_get_father(redis_client):
     assert redis_client.execute_command("tree.get_father", "hello", "j") == "k"
 
 
+def test_root(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.root", "hello") == "0"
+    redis_client.execute_command("tree.rename_node", "hello", "0", "top")
+    assert redis_client.execute_command("tree.root", "hello") == "top"
+    assert redis_client.execute_command("tree.root", "nope") is None
+
+
+def test_siblings(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.siblings", "hello", "2") == ["1", "e", "f"]
+    assert redis_client.execute_command("tree.siblings", "hello", "2", "WITHSELF") == ["1", "2", "e", "f"]
+    assert redis_client.execute_command("tree.siblings", "hello", "j") == []
+    assert redis_client.execute_command("tree.siblings", "hello", "0") == []
+
+
+def test_child_index(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.child_index", "hello", "e") == [2, 4]
+    assert redis_client.execute_command("tree.child_index", "hello", "bb") == [1, 2]
+    assert redis_client.execute_command("tree.child_index", "hello", "j") == [0, 1]
+    assert redis_client.execute_command("tree.child_index", "hello", "0") is None
+
+
 def test_get_children(redis_client):
     redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
     assert redis_client.execute_command("tree.get_children", "hello", "0") == ["1", "2", "e", "f"]
+
+
+def test_get_children_limit_count(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))")
+    assert redis_client.execute_command("tree.get_children", "hello", "0", "LIMIT", "1", "2") == ["2", "e"]
+    assert redis_client.execute_command("tree.get_children", "hello", "0", "LIMIT", "3", "10") == ["f"]
+    assert redis_client.execute_command("tree.get_children", "hello", "0", "LIMIT", "4", "10") == []
+    assert redis_client.execute_command("tree.get_children", "hello", "0", "LIMIT", "0", "0") == []
+    assert redis_client.execute_command("tree.get_children", "hello", "0", "COUNT") == 4
+    assert redis_client.execute_command("tree.get_children", "hello", "j", "COUNT") == 0
+    with pytest.raises(ResponseError, match="LIMIT and COUNT can not be combined"):
+        redis_client.execute_command("tree.get_children", "hello", "0", "LIMIT", "0", "1", "COUNT")
+    with pytest.raises(ResponseError, match="no node nope"):
+        redis_client.execute_command("tree.get_children", "hello", "nope", "COUNT")
+    assert redis_client.execute_command("tree.get_children", "nope", "0", "COUNT") is None
+
+
+def test_info(redis_client):
+    info = redis_client.execute_command("tree.info")
+    fields = dict(zip(info[::2], info[1::2]))
+    counters = {"node_allocations", "node_drops", "traversal_steps"}
+    assert set(fields) - counters == {"redis_version", "resp3", "keyspec", "config", "acl", "acl_category", "scan",
+                                      "max_nodes", "max_depth", "max_value_length"}
+    assert all(fields[flag] in (0, 1) for flag in ("resp3", "keyspec", "config", "acl", "acl_category", "scan"))
+    # the counters are there when the module is built with the stats feature
+    if not counters <= set(fields):
+        return
+
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    redis_client.execute_command("tree.get_children", "hello", "0")
+    redis_client.execute_command("tree.del", "hello")
+    info = redis_client.execute_command("tree.info")
+    after = dict(zip(info[::2], info[1::2]))
+    assert after["node_allocations"] >= fields["node_allocations"] + 3
+    assert after["node_drops"] >= fields["node_drops"] + 3
+    assert after["traversal_steps"] > fields["traversal_steps"]
+
+
+def resp3_reply(*args):
+    # redis-py 3 only speaks RESP2, the raw reply of a RESP3 connection is read up to a PING after it
+    def command(*args):
+        return b"*%d\r\n" % len(args) + b"".join(b"$%d\r\n%s\r\n" % (len(arg), arg) for arg in map(str.encode, args))
+
+    def read_to_pong(conn):
+        data = b""
+        while not data.endswith(b"+PONG\r\n"):
+            data += conn.recv(4096)
+        return data[:-len(b"+PONG\r\n")]
+
+    with socket.create_connection(("127.0.0.1", 6379)) as conn:
+        conn.sendall(command("HELLO", "3") + command("PING"))
+        read_to_pong(conn)
+        conn.sendall(command(*args) + command("PING"))
+        return read_to_pong(conn)
+
+
+def test_resp3(redis_client):
+    info = redis_client.execute_command("tree.info")
+    if not dict(zip(info[::2], info[1::2]))["resp3"]:
+        pytest.skip("map replies need redis 7")
+    redis_client.execute_command("tree.init", "hello", "a (b)")
+    redis_client.execute_command("tree.setattr", "hello", "b", "x", "1")
+
+    assert resp3_reply("tree.info", "hello").startswith(b"%9\r\n$5\r\nnodes\r\n:2\r\n")
+    assert resp3_reply("tree.getattr", "hello", "b") == b"%1\r\n$1\r\nx\r\n$1\r\n1\r\n"
+    assert resp3_reply("tree.get", "hello", "FORMAT", "NESTED") == (
+        b"%2\r\n$5\r\nvalue\r\n$1\r\na\r\n$8\r\nchildren\r\n*1\r\n"
+        b"%3\r\n$5\r\nvalue\r\n$1\r\nb\r\n$10\r\nattributes\r\n%1\r\n$1\r\nx\r\n$1\r\n1\r\n$8\r\nchildren\r\n*0\r\n"
+    )
+    assert resp3_reply("tree.todot", "hello").startswith(b"=")
+    # RESP2 clients keep the flat arrays
+    assert redis_client.execute_command("tree.getattr", "hello", "b") == ["x", "1"]
+    assert redis_client.execute_command("tree.get", "hello", "FORMAT", "NESTED") == ["a", ["b"]]
+
+
+def test_acl_categories(redis_client):
+    info = redis_client.execute_command("tree.info")
+    fields = dict(zip(info[::2], info[1::2]))
+    if not fields["acl"]:
+        pytest.skip("ACL categories for module commands need redis 7.2")
+    assert {"tree.get", "tree.get_children", "tree.randnode"} <= set(redis_client.acl_cat("read"))
+    assert "tree.init" not in redis_client.acl_cat("read")
+    assert {"tree.init", "tree.del_subtree"} <= set(redis_client.acl_cat("write"))
+    if fields["acl_category"]:
+        assert {"tree.get", "tree.init"} <= set(redis_client.acl_cat("tree"))
+
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    redis_client.execute_command("acl", "setuser", "reader", "on", "nopass", "~*", "+@read")
+    try:
+        assert redis_client.execute_command("acl", "dryrun", "reader", "tree.get", "hello") == "OK"
+        assert "permission" in redis_client.execute_command("acl", "dryrun", "reader", "tree.init", "hello", "x")
+    finally:
+        redis_client.execute_command("acl", "deluser", "reader")
+
+
+def test_limits(redis_client):
+    info = redis_client.execute_command("tree.info")
+    if not dict(zip(info[::2], info[1::2]))["config"]:
+        pytest.skip("module configs need redis 7")
+    # named after the module, ReTree
+    configs = {name.lower(): value for name, value in redis_client.config_get("retree.max-*").items()}
+    assert configs == {"retree.max-nodes": "0", "retree.max-depth": "0", "retree.max-value-length": "0"}
+
+    limits = {"retree.max-nodes": 6, "retree.max-depth": 3, "retree.max-value-length": 4}
+    try:
+        for name, limit in limits.items():
+            redis_client.config_set(name, limit)
+        redis_client.execute_command("tree.init", "a", "r (a (b) c)")
+        with pytest.raises(ResponseError, match="tree would exceed max-nodes 6"):
+            redis_client.execute_command("tree.init", "b", "r (a b c d e f)")
+        with pytest.raises(ResponseError, match="tree would exceed max-depth 3"):
+            redis_client.execute_command("tree.init", "b", "r (a (b (c (d))))")
+        with pytest.raises(ResponseError, match="value of 7 bytes exceeds max-value-length 4"):
+            redis_client.execute_command("tree.init", "b", "r (a toolong)")
+        assert redis_client.execute_command("exists", "b") == 0
+
+        with pytest.raises(ResponseError, match="tree would exceed max-nodes 6"):
+            redis_client.execute_command("tree.set_subtree", "a", "c", "x (y z)")
+        with pytest.raises(ResponseError, match="tree would exceed max-depth 3"):
+            redis_client.execute_command("tree.set_subtree", "a", "b", "x (y)")
+        assert redis_client.execute_command("tree.add_path", "a", "r/a/b/d") == 1
+        with pytest.raises(ResponseError, match="tree would exceed max-depth 3"):
+            redis_client.execute_command("tree.add_path", "a", "r/a/b/d/e")
+        with pytest.raises(ResponseError, match="tree would exceed max-nodes 6"):
+            redis_client.execute_command("tree.add_path", "a", "r/c/x/y")
+        # nothing to add is never over a limit
+        assert redis_client.execute_command("tree.add_path", "a", "r/a/b/d") == 0
+        assert redis_client.execute_command("tree.get", "a") == "r( a( b( d ) ) c )"
+
+        # every other command adding nodes checks them too
+        redis_client.execute_command("tree.init", "src", "s (t)")
+        dump = redis_client.execute_command("tree.dumpnode", "a", "b")
+        for args in (
+            ("tree.getset", "b", "r (a b c d e f)"),
+            ("tree.fromedges", "b", "r", "r", "a", "r", "b", "r", "c", "r", "d", "r", "e", "r", "f"),
+            ("tree.append_leaves", "a", "r", "x", "y"),
+            ("tree.graft", "a", "c", "src"),
+            ("tree.copy_subtree", "a", "a", "a", "c"),
+            ("tree.restorenode", "a", "c", dump),
+            ("tree.apply_diff", "a", "INSERT", "r/c", "x (y)"),
+        ):
+            with pytest.raises(ResponseError, match="tree would exceed max-nodes 6"):
+                redis_client.execute_command(*args)
+        with pytest.raises(ResponseError, match="tree would exceed max-depth 3"):
+            redis_client.execute_command("tree.apply_diff", "a", "MOVE", "r/c", "r/a/b/d")
+        with pytest.raises(ResponseError, match="value of 7 bytes exceeds max-value-length 4"):
+            redis_client.execute_command("tree.apply_diff", "a", "RELABEL", "r/c", "toolong")
+        # wrapping a node takes its subtree a level down
+        with pytest.raises(ResponseError, match="tree would exceed max-depth 3"):
+            redis_client.execute_command("tree.wrap_node", "a", "b", "x")
+        with pytest.raises(ResponseError, match="value of 7 bytes exceeds max-value-length 4"):
+            redis_client.execute_command("tree.wrap_node", "a", "c", "toolong")
+        with pytest.raises(ResponseError, match="value of 7 bytes exceeds max-value-length 4"):
+            redis_client.execute_command("tree.rename_node", "a", "c", "toolong")
+        with pytest.raises(ResponseError, match="value of 7 bytes exceeds max-value-length 4"):
+            redis_client.execute_command("tree.rename_node", "a", "c", "toolong", "ALL")
+        assert redis_client.execute_command("tree.wrap_node", "a", "c", "w") == "OK"
+        with pytest.raises(ResponseError, match="tree would exceed max-nodes 6"):
+            redis_client.execute_command("tree.wrap_node", "a", "w", "v")
+        redis_client.execute_command("tree.collapse_node", "a", "w")
+        assert redis_client.execute_command("tree.get", "a") == "r( a( b( d ) ) c )"
+        assert redis_client.execute_command("tree.get", "src") == "s( t )"
+        assert redis_client.execute_command("exists", "b") == 0
+    finally:
+        for name in limits:
+            redis_client.config_set(name, 0)
+    redis_client.execute_command("tree.set_subtree", "a", "d", "toolong (x (y))")
+
+
+def test_info_key(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1 2 (a (k (j) bb) b (d)) e f (g h))", "UNIQUE")
+    info = redis_client.execute_command("tree.info", "hello")
+    fields = dict(zip(info[::2], info[1::2]))
+    assert fields["nodes"] == 13
+    assert fields["height"] == 4
+    assert fields["max_degree"] == 4
+    assert fields["leaves"] == 7
+    assert fields["encoding_version"] == 1
+    assert fields["case_insensitive"] == 0
+    assert fields["unique"] == 1
+    assert fields["forest"] == 0
+
+    redis_client.execute_command("tree.set_subtree", "hello", "j", "x (y z)")
+    info = redis_client.execute_command("tree.info", "hello")
+    after = dict(zip(info[::2], info[1::2]))
+    assert after["nodes"] == 16
+    assert after["height"] == 6
+    assert after["leaves"] == 8
+    assert after["memory_bytes"] > fields["memory_bytes"]
+    # MEMORY USAGE goes by the same estimate, with the key on top
+    assert redis_client.execute_command("memory", "usage", "hello") >= after["memory_bytes"]
+
+    assert redis_client.execute_command("tree.info", "nope") is None
+    with pytest.raises(ResponseError, match="wrong number of arguments"):
+        redis_client.execute_command("tree.info", "hello", "extra")
+
+
+def test_edge_cases(redis_client):
+    redis_client.execute_command("tree.init", "hello", "0 (1)")
+
+    # the root's only child can go, leaving a single node tree
+    assert redis_client.execute_command("tree.del_subtree", "hello", "1") == "1"
+    assert redis_client.execute_command("tree.get", "hello") == "0"
+    assert redis_client.execute_command("tree.get_children", "hello", "0") == []
+    assert redis_client.execute_command("tree.get_father", "hello", "0") is None
+    assert redis_client.execute_command("tree.leaves", "hello") == ["0"]
+
+    # the root is addressed like any other node
+    assert redis_client.execute_command("tree.get_subtree", "hello", "0") == "0"
+    redis_client.execute_command("tree.set_subtree", "hello", "0", "1 (2)")
+    redis_client.execute_command("tree.set_subtree", "hello", "0", "3", "FRONT")
+    assert redis_client.execute_command("tree.get_subtree", "hello", "0") == "0( 3 1( 2 ) )"
+
+    # no node ever has an empty value, so it is never found and never written
+    with pytest.raises(ResponseError, match="no node"):
+        redis_client.execute_command("tree.get_subtree", "hello", "")
+    assert redis_client.execute_command("tree.exists_node", "hello", "") == 0
+    with pytest.raises(ResponseError, match="empty tree string"):
+        redis_client.execute_command("tree.set_subtree", "hello", "0", "")
+    with pytest.raises(ResponseError, match="empty tree string"):
+        redis_client.execute_command("tree.init", "hello", " ")
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.rename_node", "hello", "3", "")
+
+    # deleting the root deletes the tree
+    assert redis_client.execute_command("tree.del_subtree", "hello", "0") == "0( 3 1( 2 ) )"
+    assert redis_client.execute_command("exists", "hello") == 0
+    assert redis_client.execute_command("tree.del_subtree", "hello", "0") is None
+
+    redis_client.execute_command("tree.init", "hello", "0 (1 2)")
+    cursor, page = redis_client.execute_command("tree.scan", "hello", "0", "COUNT", str(2 ** 63))
+    assert cursor == "0" and page == ["0", "1", "2"]
+
+
+def test_digest(redis_client):
+    redis_client.execute_command("tree.init", "a", "0 (1 (2) 3)")
+    redis_client.execute_command("tree.init", "b", "0 (1 (2) 3)")
+    try:
+        digests = redis_client.execute_command("debug", "digest-value", "a", "b")
+    except ResponseError as e:
+        pytest.skip(f"DEBUG is not enabled: {e}")
+    assert digests[0] == digests[1]
+
+    # same values, another shape
+    redis_client.execute_command("tree.init", "b", "0 (1 (2 3))")
+    assert len(set(redis_client.execute_command("debug", "digest-value", "a", "b"))) == 2
+
+    redis_client.execute_command("tree.init", "b", "0 (1 (2) 3)", "CASEINSENSITIVE")
+    assert len(set(redis_client.execute_command("debug", "digest-value", "a", "b"))) == 2
+
+    redis_client.execute_command("tree.rename_node", "a", "3", "4")
+    assert redis_client.execute_command("debug", "digest-value", "a") != digests[:1]
+
+
+def wait_for_aof_rewrite(redis_client):
+    def rewriting():
+        info = redis_client.info("persistence")
+        return info["aof_rewrite_in_progress"] or info["aof_rewrite_scheduled"]
+    time.sleep(0.1)
+    while rewriting():
+        time.sleep(0.1)
+
+
+@contextlib.contextmanager
+def appendonly(redis_client):
+    # turns the AOF on without the RDB preamble, so everything in it went through the module
+    config = redis_client.config_get("appendonly")
+    config.update(redis_client.config_get("aof-use-rdb-preamble"))
+    redis_client.config_set("aof-use-rdb-preamble", "no")
+    redis_client.config_set("appendonly", "yes")
+    try:
+        wait_for_aof_rewrite(redis_client)
+        yield
+    finally:
+        redis_client.config_set("appendonly", config["appendonly"])
+        redis_client.config_set("aof-use-rdb-preamble", config["aof-use-rdb-preamble"])
+
+
+def load_aof(redis_client):
+    try:
+        redis_client.execute_command("debug", "loadaof")
+    except ResponseError as e:
+        pytest.skip(f"DEBUG is not enabled: {e}")
+
+
+def test_aof_rewrite(redis_client):
+    redis_client.execute_command("tree.init", "a", "0 (1 (2) 3)")
+    redis_client.execute_command("tree.init", "b", "Root (Food)", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.setattr", "a", "3", "color", "red")
+    redis_client.execute_command("tree.expire_node", "a", "2", 3600)
+    redis_client.execute_command("tree.init", "f", "x (y) z", "FOREST", "UNIQUE", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.setattr", "f", "z", "color", "blue")
+    # a lone node named like a tree.init option is not read back as one
+    redis_client.execute_command("tree.init", "k", '"FROMPATHS"')
+    redis_client.execute_command("tree.init", "u", "x", "UNIQUE")
+    redis_client.execute_command("tree.rename_node", "u", "x", "unique")
+
+    with appendonly(redis_client):
+        redis_client.execute_command("bgrewriteaof")
+        wait_for_aof_rewrite(redis_client)
+        load_aof(redis_client)
+
+    assert redis_client.execute_command("tree.get", "a") == "0( 1( 2 ) 3 )"
+    assert redis_client.execute_command("tree.get_subtree", "b", "food") == "Food"
+    assert redis_client.execute_command("tree.get", "f") == "x( y ) z"
+    assert redis_client.execute_command("tree.getattr", "f", "Z", "color") == "blue"
+    assert redis_client.execute_command("tree.get", "k") == "FROMPATHS"
+    assert redis_client.execute_command("tree.get", "u") == "unique"
+    info = redis_client.execute_command("tree.info", "u")
+    assert dict(zip(info[::2], info[1::2]))["unique"] == 1
+    # the versions come back as they were, deadlines counted in
+    assert redis_client.execute_command("tree.version", "a") == 3
+    assert redis_client.execute_command("tree.version", "b") == 1
+    assert redis_client.execute_command("tree.setattr", "a", "3", "IFVERSION", 3, "size", "2") == 1
+
+
+def test_copy(redis_client):
+    redis_client.execute_command("tree.init", "a", "Root (Food (Fruit) Toys)", "CASEINSENSITIVE")
+    assert redis_client.execute_command("copy", "a", "b") == 1
+    assert redis_client.execute_command("tree.get", "b") == "Root( Food( Fruit ) Toys )"
+    assert redis_client.execute_command("tree.get_father", "b", "fruit") == "Food"
+
+    # the copy shares nothing with the source
+    redis_client.execute_command("tree.del_subtree", "b", "Food")
+    assert redis_client.execute_command("tree.get", "a") == "Root( Food( Fruit ) Toys )"
+    assert redis_client.execute_command("tree.get", "b") == "Root( Toys )"
+
+    assert redis_client.execute_command("copy", "a", "b") == 0
+    assert redis_client.execute_command("copy", "a", "b", "REPLACE") == 1
+    assert redis_client.execute_command("tree.get", "b") == "Root( Food( Fruit ) Toys )"
+
+
+def test_get_depth(redis_client):
+    redis_client.execute_command("tree.init", "usa", "USA (Legislature (House (Pelosi) Senate (Harris)) Judiciary (SupremeCourt (Roberts)))")
+    assert redis_client.execute_command("tree.get", "usa", "DEPTH", 0) == "USA"
+    assert redis_client.execute_command("tree.get", "usa", "DEPTH", 1) == "USA( Legislature Judiciary )"
+    assert redis_client.execute_command("tree.get", "usa", "DEPTH", 2, "MARKER", "...") == \
+        "USA( Legislature( House( ... ) Senate( ... ) ) Judiciary( SupremeCourt( ... ) ) )"
+    assert redis_client.execute_command("tree.get", "usa", "DEPTH", 9) == redis_client.execute_command("tree.get", "usa")
+    assert redis_client.execute_command("tree.get_subtree", "usa", "Legislature", "DEPTH", 1, "MARKER", "more", "WITHPATH") == \
+        ["USA/Legislature", "Legislature( House( more ) Senate( more ) )"]
+    assert json.loads(redis_client.execute_command("tree.get_subtree", "usa", "Judiciary", "DEPTH", 0, "FORMAT", "JSON")) == \
+        {"value": "Judiciary", "children": []}
+
+    # the hash stays that of the whole tree, it tells when to fetch again
+    _, tree_hash = redis_client.execute_command("tree.get", "usa", "DEPTH", 1, "WITHHASH")
+    assert tree_hash == redis_client.execute_command("tree.hash", "usa")
+
+    with pytest.raises(ResponseError, match="MARKER needs DEPTH"):
+        redis_client.execute_command("tree.get", "usa", "MARKER", "...")
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.get", "usa", "DEPTH", 1, "MARKER", "")
+    with pytest.raises(ResponseError):
+        redis_client.execute_command("tree.get", "usa", "DEPTH", -1)
+    assert redis_client.execute_command("tree.get", "nope", "DEPTH", 1) is None
+
+
+def test_lazy_free(redis_client):
+    freed = redis_client.info("memory").get("lazyfreed_objects")
+    if freed is None:
+        pytest.skip("the server does not count lazy freed objects")
+
+    def lazy_freed():
+        # the background thread gets to it a moment later
+        for _ in range(50):
+            if redis_client.info("memory")["lazyfreed_objects"] > freed:
+                break
+            time.sleep(0.01)
+        return redis_client.info("memory")["lazyfreed_objects"] - freed
+
+    redis_client.execute_command("tree.init", "big", "root( %s )" % " ".join("n%d" % i for i in range(1000)))
+    assert redis_client.execute_command("unlink", "big") == 1
+    assert redis_client.execute_command("exists", "big") == 0
+    assert lazy_freed() == 1
+
+    # small trees are freed right away
+    freed += 1
+    redis_client.execute_command("tree.init", "small", "a (b c)")
+    assert redis_client.execute_command("unlink", "small") == 1
+    assert lazy_freed() == 0
+
+
+def test_selftest(redis_client):
+    results = redis_client.execute_command("tree.selftest")
+    assert [check for check, _ in results] == ["parser", "sizes", "index", "locate", "rdb", "defrag"]
+    assert all(status == "pass" for _, status in results)
+
+    for check, status, detail in redis_client.execute_command("tree.selftest", "VERBOSE"):
+        assert status == "pass" and detail
+
+    with pytest.raises(ResponseError, match="unknown argument"):
+        redis_client.execute_command("tree.selftest", "NOPE")
+    assert redis_client.execute_command("dbsize") == 0
+
+
+def test_quoted_values(redis_client):
+    redis_client.execute_command("tree.init", "a", 'r( "New York"( "(1)" ) a"b "say \\"hi\\"" )')
+    assert redis_client.execute_command("tree.get_children", "a", "r") == ["New York", 'a"b', 'say "hi"']
+    assert redis_client.execute_command("tree.get_father", "a", "(1)") == "New York"
+    redis_client.execute_command("tree.rename_node", "a", "a\"b", "C:\\ two")
+    redis_client.execute_command("tree.append_leaves", "a", "r", ")")
+    printed = 'r( "New York"( "(1)" ) "C:\\\\ two" "say \\"hi\\"" ")" )'
+    assert redis_client.execute_command("tree.get", "a") == printed
+
+    # what tree.get prints builds the same tree again
+    redis_client.execute_command("tree.init", "b", printed)
+    assert redis_client.execute_command("tree.equals", "a", "b") == 1
+    assert redis_client.execute_command("tree.get_children", "b", "r")[1] == "C:\\ two"
+
+    redis_client.execute_command("tree.rename_node", "b", ")", "two\nlines")
+    assert redis_client.execute_command("tree.get", "b").endswith(' "two\\nlines" )')
+    redis_client.execute_command("tree.init", "c", 'r( "tab\\there" "\\q" )')
+    assert redis_client.execute_command("tree.get_children", "c", "r") == ["tab\there", "q"]
+
+    with pytest.raises(ResponseError, match="unclosed quote in tree string"):
+        redis_client.execute_command("tree.init", "c", 'r( "x )')
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.init", "c", 'r( "" )')
+
+
+def test_binary_values(redis_client, binary_client):
+    # NUL and bytes outside UTF-8 are values like any other, quoted as \xHH in tree strings
+    binary_client.execute_command("tree.init", "b", b'r( "nul\\x00" "\\xff" a/b "c\\\\" )')
+    assert binary_client.execute_command("tree.get", "b") == b'r( "nul\\x00" "\\xff" a/b "c\\\\" )'
+    assert binary_client.execute_command("tree.get_children", "b", "r") == [b"nul\0", b"\xff", b"a/b", b"c\\"]
+    assert binary_client.execute_command("tree.rename_node", "b", b"\xff", b"\xfe\0") == 1
+    assert binary_client.execute_command("tree.get_father", "b", b"\xfe\0") == b"r"
+    assert binary_client.execute_command("tree.setattr", "b", b"nul\0", "k", "v\0w") == 1
+    assert binary_client.execute_command("tree.getattr", "b", b"nul\0", "k") == b"v\0w"
+    binary_client.execute_command("tree.init", "raw", b"\x00\x80")
+    assert binary_client.execute_command("tree.get", "raw") == b'"\\x00\\x80"'
+
+    # a / or a trailing backslash inside a value is escaped in paths
+    assert binary_client.execute_command("tree.locate_all", "b", "a/b") == [b"r/a\\/b"]
+    assert binary_client.execute_command("tree.locate_all", "b", b"c\\") == [b"r/c\\\\"]
+    assert binary_client.execute_command("tree.contains_path", "b", b"r/a\\/b") == 1
+    assert binary_client.execute_command("tree.contains_path", "b", "r/a/b") == 0
+    assert binary_client.execute_command("tree.add_path", "b", b"r/a\\/b/d") == 1
+    assert binary_client.execute_command("tree.get_subtree", "b", "a/b") == b"a/b( d )"
+
+    # JSON carries the values outside UTF-8 in hex
+    tree = json.loads(binary_client.execute_command("tree.get", "b", "FORMAT", "JSON"))
+    assert [child.get("value", child.get("value_hex")) for child in tree["children"]] == ["nul\0", "fe00", "a/b", "c\\"]
+    binary_client.execute_command("tree.init", "j", '{"value": "r", "children": [{"value_hex": "ff00"}, {"value": "x\\u0000"}]}', "FORMAT", "JSON")
+    assert binary_client.execute_command("tree.get_children", "j", "r") == [b"\xff\0", b"x\0"]
+
+    # and everything comes back from the RDB and the AOF
+    assert binary_client.execute_command("tree.expire_node", "b", b"r/a\\/b", 3600, "PATH") == 1
+    expected = binary_client.execute_command("tree.get", "b")
+    redis_client.execute_command("debug", "reload")
+    assert binary_client.execute_command("tree.get", "b") == expected
+    with appendonly(redis_client):
+        redis_client.execute_command("bgrewriteaof")
+        wait_for_aof_rewrite(redis_client)
+        load_aof(redis_client)
+    assert binary_client.execute_command("tree.get", "b") == expected
+    assert binary_client.execute_command("tree.getattr", "b", b"nul\0", "k") == b"v\0w"
+    assert binary_client.execute_command("tree.ttl_node", "b", "a/b") > 0
+
+    # only the empty value is refused
+    with pytest.raises(ResponseError, match="invalid node value"):
+        redis_client.execute_command("tree.rename_node", "b", "r", "")
+
+
+def test_rdb_reload(redis_client):
+    deep = " ".join(f"{i}(" for i in range(500)) + " x" + " )" * 500
+    redis_client.execute_command("tree.init", "deep", deep)
+    redis_client.execute_command("tree.init", "a", "héllo (wörld x (y (z)))", "CASEINSENSITIVE")
+    redis_client.execute_command("tree.init", "f", "r (s) t", "FOREST")
+    before = {key: redis_client.execute_command("tree.get", key) for key in ("deep", "a", "f")}
+    try:
+        redis_client.execute_command("debug", "reload")
+    except ResponseError as e:
+        pytest.skip(f"DEBUG is not enabled: {e}")
+
+    assert {key: redis_client.execute_command("tree.get", key) for key in ("deep", "a", "f")} == before
+    assert redis_client.execute_command("tree.root", "f") == ["r", "t"]
+    assert redis_client.execute_command("tree.get_father", "f", "r") is None
+    assert redis_client.execute_command("tree.size", "deep") == [1, 500]
+    assert redis_client.execute_command("tree.get_father", "a", "HÉLLO") is None
+    assert redis_client.execute_command("tree.get_father", "a", "y") == "x"
+
+
+def test_dump_restore(redis_client, binary_client):
+    redis_client.execute_command("tree.init", "a", "Root (Food (Fruit Meat) Toys)", "CASEINSENSITIVE")
+    payload = binary_client.dump("a")
+    redis_client.restore("b", 0, payload)
+    assert redis_client.execute_command("tree.get", "b") == "Root( Food( Fruit Meat ) Toys )"
+    assert redis_client.execute_command("tree.get_father", "b", "meat") == "Food"
+
+
+
+def crc64(data):
+    # the CRC-64/Jones redis closes DUMP payloads with
+    crc = 0
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = (crc >> 1) ^ (0x95ac9329ac4bc9b5 if crc & 1 else 0)
+    return crc
+
+
+def test_restore_corrupt(redis_client, binary_client):
+    redis_client.execute_command("tree.init", "a", "0 (1)")
+    payload = binary_client.dump("a")
+
+    # 2 nodes: 0 with no child and 1 with one, which leaves 1 without a parent
+    encoded = b"\x02\x010\x01\x011\x00"
+    assert payload.count(encoded) == 1
+    body = payload[:-8].replace(encoded, b"\x02\x010\x00\x011\x01")
+    corrupt = body + crc64(body).to_bytes(8, "little")
+    with pytest.raises(ResponseError, match="Bad data format"):
+        redis_client.restore("b", 0, corrupt)
+    assert redis_client.execute_command("exists", "b") == 0
+
+
+def test_writes_replicate(redis_client):
+    # what reaches the AOF is what replicas get, reloading it replays every write
+    with appendonly(redis_client):
+        redis_client.execute_command("tree.init", "a", "0 (1 (2 3) 4 (5))")
+        redis_client.execute_command("tree.set_subtree", "a", "1", "6", "FRONT")
+        redis_client.execute_command("tree.append_leaves", "a", "0/4", "7", "8")
+        redis_client.execute_command("tree.rename_node", "a", "8", "9")
+        redis_client.execute_command("tree.del_subtree", "a", "2")
+        redis_client.execute_command("tree.del_children", "a", "5")
+        redis_client.execute_command("tree.apply_diff", "a", "MOVE", "0/4/7", "0/1")
+        redis_client.execute_command("tree.init", "b", "x (y)")
+        redis_client.execute_command("tree.del", "b")
+        # failed writes leave nothing to replay
+        with pytest.raises(ResponseError):
+            redis_client.execute_command("tree.apply_diff", "a", "DELETE", "0/nope")
+        expected = redis_client.execute_command("tree.get", "a")
+        load_aof(redis_client)
+
+    assert redis_client.execute_command("tree.get", "a") == expected
+    assert redis_client.execute_command("exists", "b") == 0